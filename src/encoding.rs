@@ -0,0 +1,112 @@
+//! Decode the bytes of an XML file into a string that can be skimmed.
+//!
+//! The parser reads `&str`, so files that are not UTF-8 (like the UTF-16 files that Windows programs write)
+//! must be decoded first. Use [`decode()`], or [`skim_bytes()`](crate::skim_bytes) to decode and skim in one step.
+use std::borrow::Cow;
+use crate::SkimError;
+
+/// The encodings that bytes can be decoded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// UTF-16 little endian.
+    Utf16Le,
+    /// UTF-16 big endian.
+    Utf16Be,
+}
+
+/// Find the encoding of the bytes of an XML file, from its byte order mark (BOM) if it has one.
+/// Returns the encoding and whether it was found from a BOM.
+///
+/// Without a BOM, UTF-16 is detected by the zero bytes around the first character,
+/// which is ASCII in any XML document (`<` or whitespace). Otherwise, the bytes are UTF-8.
+///
+/// ```
+/// use xml_skimmer::encoding::{detect_encoding, Encoding};
+///
+/// assert_eq!(detect_encoding(b"\xFF\xFE<\0a\0/\0>\0"), (Encoding::Utf16Le, true));
+/// assert_eq!(detect_encoding(b"\0<\0a\0/\0>"), (Encoding::Utf16Be, false));
+/// assert_eq!(detect_encoding(b"<a/>"), (Encoding::Utf8, false));
+/// ```
+pub fn detect_encoding(bytes: &[u8]) -> (Encoding, bool) {
+    match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, true),
+        [0xFF, 0xFE, ..] => (Encoding::Utf16Le, true),
+        [0xFE, 0xFF, ..] => (Encoding::Utf16Be, true),
+        // An ASCII character with a zero byte after it, or before it
+        [first, 0, ..] if *first != 0 => (Encoding::Utf16Le, false),
+        [0, second, ..] if *second != 0 => (Encoding::Utf16Be, false),
+        _ => (Encoding::Utf8, false)
+    }
+}
+
+/// Decode the bytes of an XML file, in the encoding found with [`detect_encoding()`]. The BOM is not part of the string.
+/// Returns [`Cow::Borrowed`] for UTF-8.
+///
+/// When the prolog declares an encoding (`<?xml version="1.0" encoding="UTF-16"?>`), it must agree with the detected one,
+/// or [`SkimError::EncodingMismatch`] is returned. Encodings other than UTF-8 and UTF-16 are not checked,
+/// so ASCII documents that declare another encoding (like `ISO-8859-1`) are decoded as UTF-8.
+///
+/// Bytes that are not valid in the detected encoding return [`SkimError::InvalidEncoding`].
+///
+/// ```
+/// use xml_skimmer::encoding::decode;
+///
+/// let utf16 = "<?xml version='1.0' encoding='UTF-16'?><a/>".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+/// assert_eq!(decode(&utf16).unwrap(), "<?xml version='1.0' encoding='UTF-16'?><a/>");
+/// assert_eq!(decode(b"\xEF\xBB\xBF<a/>").unwrap(), "<a/>");
+/// ```
+pub fn decode(bytes: &[u8]) -> Result<Cow<'_, str>, SkimError> {
+    let (encoding, has_bom) = detect_encoding(bytes);
+    let bom_len = match (encoding, has_bom) {
+        (_, false) => 0,
+        (Encoding::Utf8, true) => 3,
+        (Encoding::Utf16Le | Encoding::Utf16Be, true) => 2,
+    };
+
+    let text = match encoding {
+        Encoding::Utf8 => Cow::Borrowed(std::str::from_utf8(&bytes[bom_len..])
+            .map_err(|error| SkimError::InvalidEncoding(bom_len + error.valid_up_to()))?),
+        Encoding::Utf16Le => Cow::Owned(decode_utf16(&bytes[bom_len..], u16::from_le_bytes, bom_len)?),
+        Encoding::Utf16Be => Cow::Owned(decode_utf16(&bytes[bom_len..], u16::from_be_bytes, bom_len)?),
+    };
+
+    if let Some(declared) = crate::xml_declaration(&text).and_then(|declaration| crate::declaration_value(declaration, "encoding")) {
+        let agrees = match declared.to_ascii_uppercase().as_str() {
+            "UTF-8" | "UTF8" => encoding == Encoding::Utf8,
+            "UTF-16" | "UTF16" => encoding != Encoding::Utf8,
+            "UTF-16LE" => encoding == Encoding::Utf16Le,
+            "UTF-16BE" => encoding == Encoding::Utf16Be,
+            _ => true
+        };
+        if !agrees {
+            return Err(SkimError::EncodingMismatch(declared.to_string()))
+        }
+    }
+
+    Ok(text)
+}
+
+/// Decode UTF-16 **bytes**, where each pair of bytes is a code unit read with **from_bytes**.
+/// **offset** is added to the byte offset of an error (the length of the BOM that was before **bytes**).
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16, offset: usize) -> Result<String, SkimError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(SkimError::InvalidEncoding(offset + bytes.len() - 1))
+    }
+
+    let units = bytes.chunks_exact(2).map(|pair| from_bytes([pair[0], pair[1]]));
+    let mut text = String::with_capacity(bytes.len() / 2);
+    // How many code units were decoded, to find where an unpaired surrogate is
+    let mut decoded = 0;
+    for character in char::decode_utf16(units) {
+        match character {
+            Ok(character) => {
+                text.push(character);
+                decoded += character.len_utf16();
+            },
+            Err(_) => return Err(SkimError::InvalidEncoding(offset + decoded * 2))
+        }
+    }
+
+    Ok(text)
+}
@@ -0,0 +1,157 @@
+use std::borrow::Cow;
+use crate::SkimError;
+
+
+/// Replace the predefined XML entities (`&lt;`, `&gt;`, `&amp;`, `&quot;`, `&apos;`)
+/// and character references (`&#60;`, `&#x3C;`) in **s** with the characters they represent.
+/// 
+/// Anything else that starts with `&` (unknown entities, or an `&` by itself) is left as-is.
+/// Returns [`Cow::Borrowed`] when there is nothing to decode.
+/// 
+/// ```
+/// use xml_skimmer::entities::decode_entities;
+/// 
+/// assert_eq!(decode_entities("a &lt; b &amp;&amp; &#x63; &#62; &#100;"), "a < b && c > d");
+/// assert_eq!(decode_entities("Tom & Jerry &nbsp;"), "Tom & Jerry &nbsp;");
+/// ```
+pub fn decode_entities(s: &str) -> Cow<'_, str> {
+    if !s.contains('&') {
+        return Cow::Borrowed(s)
+    }
+
+    let mut rtrn = String::with_capacity(s.len());
+    let mut remaining = s;
+
+    while let Some(i) = remaining.find('&') {
+        rtrn.push_str(&remaining[..i]);
+        remaining = &remaining[i..];
+
+        match decode_entity(remaining) {
+            Some((character, len)) => {
+                rtrn.push(character);
+                remaining = &remaining[len..];
+            }
+            // Not an entity, keep the `&`
+            None => {
+                rtrn.push('&');
+                remaining = &remaining[1..];
+            }
+        }
+    }
+    rtrn.push_str(remaining);
+
+    Cow::Owned(rtrn)
+}
+
+/// Same as [`decode_entities()`], but returns [`SkimError::UnknownEntity`] when an `&` does not start one of the entities it can decode
+/// (an unknown entity like `&foo;`, or an `&` by itself).
+/// 
+/// ```
+/// use xml_skimmer::{entities::decode_entities_strict, SkimError};
+/// 
+/// assert_eq!(decode_entities_strict("a &amp; b").unwrap(), "a & b");
+/// assert_eq!(decode_entities_strict("&nbsp;"), Err(SkimError::UnknownEntity("&nbsp;".to_string())));
+/// ```
+pub fn decode_entities_strict(s: &str) -> Result<Cow<'_, str>, SkimError> {
+    for (i, _) in s.match_indices('&') {
+        if decode_entity(&s[i..]).is_none() {
+            return Err(SkimError::UnknownEntity(unknown_entity(&s[i..]).to_string()))
+        }
+    }
+    Ok(decode_entities(s))
+}
+
+/// The text of the entity that could not be decoded at the start of **s**:
+/// the whole `&name;` if it looks like an entity, or just the `&`.
+fn unknown_entity(s: &str) -> &str {
+    // Skip the `&` at the start
+    match s[1..].find(|c: char| c == ';' || c == '&' || c == '<' || c.is_whitespace()) {
+        Some(end) if end > 0 && s[end + 1..].starts_with(';') => &s[..=end + 1],
+        _ => &s[..1]
+    }
+}
+
+/// Decode the entity at the start of **s** (which starts with `&`).
+/// Returns the character, and the length of the entity (including `&` and `;`).
+fn decode_entity(s: &str) -> Option<(char, usize)> {
+    let end = s.find(';')?;
+
+    let character = match &s[1..end] {
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "quot" => '"',
+        "apos" => '\'',
+        // Hexadecimal character reference
+        name if name.starts_with("#x") => char::from_u32(parse_digits(&name[2..], 16)?)?,
+        // Decimal character reference
+        name if name.starts_with('#') => char::from_u32(parse_digits(&name[1..], 10)?)?,
+        _ => return None
+    };
+
+    Some((character, end + 1))
+}
+
+/// Like [`u32::from_str_radix()`], but only accepts digits (no sign).
+fn parse_digits(digits: &str, radix: u32) -> Option<u32> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None
+    }
+    u32::from_str_radix(digits, radix).ok()
+}
+
+/// Replace the characters that can't be in the text of a node (`&`, `<`, `>`) with their entities.
+/// Returns [`Cow::Borrowed`] when there is nothing to escape.
+/// 
+/// This is the inverse of [`decode_entities()`] for text.
+/// 
+/// ```
+/// use xml_skimmer::entities::{decode_entities, escape_text};
+/// 
+/// assert_eq!(escape_text("a < b && c > d"), "a &lt; b &amp;&amp; c &gt; d");
+/// assert_eq!(decode_entities(&escape_text("a < b && c > d")), "a < b && c > d");
+/// ```
+pub fn escape_text(s: &str) -> Cow<'_, str> {
+    escape(s, &['&', '<', '>'])
+}
+
+/// Replace the characters that can't be in an attribute value (`&`, `<`, `>`, `"`, `'`) with their entities,
+/// so the value can be quoted with either quote.
+/// Returns [`Cow::Borrowed`] when there is nothing to escape.
+/// 
+/// This is the inverse of [`decode_entities()`] for attribute values.
+/// 
+/// ```
+/// use xml_skimmer::entities::escape_attr;
+/// 
+/// assert_eq!(escape_attr(r#"say "hi" & 'bye'"#), "say &quot;hi&quot; &amp; &apos;bye&apos;");
+/// ```
+pub fn escape_attr(s: &str) -> Cow<'_, str> {
+    escape(s, &['&', '<', '>', '"', '\''])
+}
+
+/// Replace the characters that can't be in an attribute value quoted with `"` (`&`, `<`, `"`) with their entities.
+/// Used for the canonical form of [`tree`](crate::tree), which escapes as little as possible.
+pub(crate) fn escape_attr_canonical(s: &str) -> Cow<'_, str> {
+    escape(s, &['&', '<', '"'])
+}
+
+/// Replace `&` and `<` (which are always escaped), and the other **special** characters, with their entities.
+fn escape<'a>(s: &'a str, special: &[char]) -> Cow<'a, str> {
+    if !s.contains(special) {
+        return Cow::Borrowed(s)
+    }
+
+    let mut rtrn = String::with_capacity(s.len());
+    for character in s.chars() {
+        match character {
+            '&' => rtrn.push_str("&amp;"),
+            '<' => rtrn.push_str("&lt;"),
+            '>' if special.contains(&'>') => rtrn.push_str("&gt;"),
+            '"' if special.contains(&'"') => rtrn.push_str("&quot;"),
+            '\'' if special.contains(&'\'') => rtrn.push_str("&apos;"),
+            _ => rtrn.push(character)
+        }
+    }
+    Cow::Owned(rtrn)
+}
@@ -1,252 +1,1772 @@
+pub mod encoding;
+pub mod entities;
+pub mod namespace;
 pub mod selector;
-use std::{collections::{HashMap, HashSet}, fmt::Display};
-use crate::selector::{CommaSeparated, Selector};
+pub mod tree;
+use std::{borrow::Cow, collections::{HashMap, HashSet, VecDeque}, fmt::Display, ops::Range, str::Chars, sync::Arc};
+use crate::{selector::{CommaSeparated, Selector, SelectorParseError}, entities::{decode_entities, decode_entities_strict}};
 
 
 /// Skim an XML file. Can call handler closures when a node that matches a selector is found.
-pub fn skim_xml<F>(xml_src: &str, handlers: HashMap<&'static str, F>) -> Result<(), SkimError>
+/// 
+/// **handlers** are pairs of a selector and its handler, like a [`HashMap`] or an array.
+/// When several selectors match the same node, their handlers are called in the order **handlers** gives them,
+/// so use an array or [`Vec`] (not a [`HashMap`], which has no order) when that order matters.
+/// 
+/// ```
+/// use std::cell::RefCell;
+/// use xml_skimmer::{skim_xml, ParsedNode};
+/// 
+/// let order = RefCell::new(vec![]);
+/// let handler = |name: &'static str| { let order = &order; move |_: &ParsedNode| order.borrow_mut().push(name) };
+/// skim_xml("<ul><li/></ul>", [("li", handler("li")), ("ul > li", handler("child"))]).unwrap();
+/// assert_eq!(order.into_inner(), ["li", "child"]);
+/// ```
+pub fn skim_xml<I, F>(xml_src: &str, handlers: I) -> Result<(), SkimError>
+where I: IntoIterator<Item = (&'static str, F)>, F: FnMut(&ParsedNode) {
+    let mut skimmer = Skimmer::new();
+    for (sel, handler) in handlers {
+        // parse selector strings
+        skimmer.on(sel, handler).map_err(SkimError::BadSelector)?;
+    }
+
+    skimmer.skim(xml_src)
+}
+
+/// Same as [`skim_xml`], but for the bytes of an XML file, which are decoded first (with [`encoding::decode()`]).
+/// Use it for files that could be UTF-16.
+/// 
+/// ```
+/// let bytes = "\u{FEFF}<ul><li/></ul>".encode_utf16().flat_map(u16::to_be_bytes).collect::<Vec<_>>();
+/// let mut count = 0;
+/// xml_skimmer::skim_bytes(&bytes, [("li", |_: &xml_skimmer::ParsedNode| count += 1)]).unwrap();
+/// assert_eq!(count, 1);
+/// ```
+pub fn skim_bytes<I, F>(bytes: &[u8], handlers: I) -> Result<(), SkimError>
+where I: IntoIterator<Item = (&'static str, F)>, F: FnMut(&ParsedNode) {
+    skim_xml(&encoding::decode(bytes)?, handlers)
+}
+
+/// Skim an XML file with a single **selector** and **handler**, without building a [`HashMap`].
+/// 
+/// ```
+/// let mut ids = vec![];
+/// xml_skimmer::skim_xml_one(r#"<ul><li id="a"/><li id="b"/></ul>"#, "li", |node| {
+///     ids.push(node.attributes["id"].clone());
+/// }).unwrap();
+/// assert_eq!(ids, ["a", "b"]);
+/// ```
+pub fn skim_xml_one<F>(xml_src: &str, selector: &str, handler: F) -> Result<(), SkimError>
 where F: FnMut(&ParsedNode) {
-    let mut stack: Vec<ParsedNode> = vec![];
-    // Node that this fn is working with. Will be pushed to stack if is an OPENING_NODE, and popped if is a CLOSING_NODE
-    let mut current_node = ParsedNode::default();
-    // Temporary attribute; will be added to the last ParsedNode
-    let mut current_attr = Attr::default();
-    let mut node_type = NodeType::None;
-    // Whether the characters being read are appended to the tag, an attribute name, or an attribute value
-    let mut writing_to = WriteTo::Content;
-
-    // parse selector strings
-    let mut handlers = handlers.into_iter().map(|(sel, fun)| {
-        (sel.parse::<CommaSeparated<Selector>>().unwrap(), fun)
-    }).collect::<Vec<(CommaSeparated<Selector>, F)>>();
-
-
-    let mut iter = xml_src.chars();
-    while let Some(character) = iter.next() {
-        // Anything goes in a TextNode (except `<`)
-        if writing_to == WriteTo::Content && character != '<' {
-            // TODO: write text content
-            // todo!("write text content");
-            continue;
-        }
-
-        match character {
-            // Creating an OPENING_NODE
-            '<' => {
-                node_type = NodeType::Opening;
-                writing_to = WriteTo::Tag;
-
-                /* Check if the next 3 characters are !-- to initiate a comment.
-                   Save a slice of the remaining characters after !-- */
-                if let Some(remaining) = iter.as_str().strip_prefix("!--") {
-                    println!("Comment Start");
-                    /* Look for the end-of-comment delimeter (-->) */
-                    let remaining = match remaining.split_once("-->") {
-                        Some((content, remaining)) => {
-                            // print comment content
-                            println!("    {content}");
-                            remaining
-                        }
-                        // The rest of xml_src is the comment
-                        None => return Err(SkimError::UnclosedComment(remaining.to_string()))
-                    };
+    let mut skimmer = Skimmer::new();
+    skimmer.on(selector, handler).map_err(SkimError::BadSelector)?;
+    skimmer.skim(xml_src)
+}
+
+/// Same as [`skim_xml`], but the parser will behave according to **options**.
+pub fn skim_xml_with_options<I, F>(xml_src: &str, handlers: I, options: SkimOptions) -> Result<(), SkimError>
+where I: IntoIterator<Item = (&'static str, F)>, F: FnMut(&ParsedNode) {
+    let mut skimmer = Skimmer::with_options(options);
+    for (sel, handler) in handlers {
+        // parse selector strings
+        skimmer.on(sel, handler).map_err(SkimError::BadSelector)?;
+    }
+
+    skimmer.skim(xml_src)
+}
+
+/// Skim an XML file, calling a handler when a closing tag `</tag>` is read.
+/// The keys of **handlers** are plain tag names (not selectors), and the handler is called with the node that was closed.
+/// 
+/// Self-closing nodes (`<tag/>`) have no closing tag, so they don't call any handler.
+pub fn skim_xml_close<F>(xml_src: &str, mut handlers: HashMap<&str, F>) -> Result<(), SkimError>
+where F: FnMut(&ParsedNode) {
+    let mut parser = Parser::new(xml_src, SkimOptions::default());
+    let mut self_closing = false;
+
+    while let Some(token) = parser.next_token()? {
+        match token {
+            Token::Open { self_closing: is_self_closing } => self_closing = is_self_closing,
+            // The node was self-closing, it had no closing tag
+            Token::Close(_) if self_closing => self_closing = false,
+            Token::Close(node) =>
+                if let Some(handler) = handlers.get_mut(node.tag.as_ref()) {
+                    handler(&node.to_owned())
+                },
+            Token::Text(_) | Token::Comment(_) | Token::ProcessingInstruction(..) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the first node that matches **selector**. Parsing stops at the end of that node
+/// (so its [`ParsedNode::inner_span()`] is known), and errors in the rest of the source are not found.
+/// 
+/// The node is cloned out of the parser's stack (including all its attributes).
+/// Use [`find_first_map()`] to only take what is needed from the node.
+/// 
+/// ```
+/// use xml_skimmer::{find_first, ParsedNode};
+/// 
+/// let node = find_first("<ul><li id='a'/><li id='b'/></ul>", "li").unwrap();
+/// assert_eq!(node, Some(ParsedNode::new("li").with_attr("id", "a")));
+/// ```
+pub fn find_first(xml_src: &str, selector: &str) -> Result<Option<ParsedNode>, SkimError> {
+    find_nth_ref(xml_src, selector, 0, |node| node.to_owned())
+}
+
+/// Find the **n**th node (starting at 0) that matches **selector**, in the order they appear.
+/// Parsing stops at the end of that node, so errors in the rest of the source are not found.
+/// Returns [`None`] if there are not that many matches.
+/// 
+/// ```
+/// use xml_skimmer::{find_nth, ParsedNode};
+/// 
+/// let xml_src = "<ul><li id='a'/><li id='b'/><li id='c'/></ul>";
+/// assert_eq!(find_nth(xml_src, "li", 2).unwrap(), Some(ParsedNode::new("li").with_attr("id", "c")));
+/// assert_eq!(find_nth(xml_src, "li", 3).unwrap(), None);
+/// ```
+pub fn find_nth(xml_src: &str, selector: &str, n: usize) -> Result<Option<ParsedNode>, SkimError> {
+    find_nth_ref(xml_src, selector, n, |node| node.to_owned())
+}
+
+/// Same as [`find_first()`], but calls **f** with the matched node and returns its result.
+/// The node is still borrowed from **xml_src**, so nothing is copied except what **f** takes from it.
+/// 
+/// ```
+/// use xml_skimmer::{find_first_map, Element};
+/// 
+/// let id = find_first_map("<ul><li id='a'/><li id='b'/></ul>", "li", |node| node.attribute("id").unwrap_or_default().to_string()).unwrap();
+/// assert_eq!(id.as_deref(), Some("a"));
+/// ```
+pub fn find_first_map<T>(xml_src: &str, selector: &str, f: impl FnOnce(&ParsedNodeRef<'_>) -> T) -> Result<Option<T>, SkimError> {
+    find_nth_ref(xml_src, selector, 0, f)
+}
+
+/// Call **f** with the **n**th node that matches **selector** once it is closed, while it is still borrowed from **xml_src**.
+fn find_nth_ref<T>(xml_src: &str, selector: &str, mut n: usize, f: impl FnOnce(&ParsedNodeRef) -> T) -> Result<Option<T>, SkimError> {
+    let selector = parse_unscoped(selector).map_err(SkimError::BadSelector)?;
+    let mut parser = Parser::new(xml_src, SkimOptions::default());
+    // The depth of the matched node, once it was found
+    let mut found = None;
 
-                    // skip the comment and its delimeters
-                    iter = remaining.chars();
-                    println!("Comment Stop");
+    while let Some(token) = parser.next_token()? {
+        match token {
+            Token::Open { .. } if found.is_none() && selector.match_node(parser.stack()) => {
+                if n == 0 {
+                    found = Some(parser.stack().len());
+                } else {
+                    n -= 1;
                 }
-                // Treat prolog nodes <?xml?> as comments
-                else if let Some(remaining) = iter.as_str().strip_prefix("?") {
-                    println!("Prolog start");
-                    // Question-mark (?) is used as a delimiter, look for the ending one
-                    let remaining = match remaining.split_once("?>") {
-                        Some((content, remaining)) => {
-                            // print prolog content
-                            println!("    {content}");
-                            remaining
-                        }
-                        // The rest of xml_src is the comment
-                        None => return Err(SkimError::UnclosedComment(remaining.to_string()))
-                    };
+            },
+            Token::Close(node) if found == Some(parser.stack().len() + 1) => return Ok(Some(f(&node))),
+            _ => {}
+        }
+    }
+
+    Ok(None)
+}
 
-                    // skip the prolog and its delimeter
-                    iter = remaining.chars();
-                    println!("Prolog Stop");
+/// Call **f** with the **n**th node where **matches** returns `true` for the stack that ends at that node.
+pub(crate) fn find_nth_where<T>(xml_src: &str, matches: impl Fn(&[ParsedNodeRef]) -> bool, mut n: usize, f: impl FnOnce(&ParsedNodeRef) -> T) -> Result<Option<T>, SkimError> {
+    let mut parser = Parser::new(xml_src, SkimOptions::default());
+
+    while let Some(token) = parser.next_token()? {
+        if let Token::Open { .. } = token {
+            if matches(parser.stack()) {
+                if n == 0 {
+                    return Ok(Some(f(parser.stack().last().unwrap())))
                 }
+                n -= 1;
             }
-            // Change OPENING_NODE to CLOSING_NODE
-            '/' => {
-                /* Empty tag at this point means this is a regular closing node.
-                   If tag has content it means this is a self-closing node */
-                if current_node.tag.is_empty() {
-                    node_type = NodeType::Closing;
-                } else {
-                    node_type = NodeType::SelfClosing;
-                }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Convert a matched node into a user type. Used by [`find_all_as()`].
+/// 
+/// ```
+/// use xml_skimmer::{FromNode, FromNodeError, ParsedNode};
+/// 
+/// struct Link { href: String }
+/// impl FromNode for Link {
+///     fn from_node(node: &ParsedNode) -> Result<Self, FromNodeError> {
+///         match node.attributes.get("href") {
+///             Some(href) => Ok(Link { href: href.clone() }),
+///             None => Err(FromNodeError::MissingAttribute("href".to_string()))
+///         }
+///     }
+/// }
+/// 
+/// let links = xml_skimmer::find_all_as::<Link>("<p><a href='/'/><a href='/about'/></p>", "a").unwrap();
+/// assert_eq!(links.iter().map(|link| link.href.as_str()).collect::<Vec<_>>(), ["/", "/about"]);
+/// ```
+pub trait FromNode: Sized {
+    fn from_node(node: &ParsedNode) -> Result<Self, FromNodeError>;
+}
+
+/// Convert every node that matches **selector** with [`FromNode`], in the order they appear.
+/// Parsing stops at the first node that can't be converted, returning [`SkimError::FromNode`].
+pub fn find_all_as<T: FromNode>(xml_src: &str, selector: &str) -> Result<Vec<T>, SkimError> {
+    let selector = parse_unscoped(selector).map_err(SkimError::BadSelector)?;
+    let mut parser = Parser::new(xml_src, SkimOptions::default());
+    let mut found = vec![];
+
+    while let Some(token) = parser.next_token()? {
+        if let Token::Open { .. } = token {
+            if selector.match_node(parser.stack()) {
+                found.push(T::from_node(&parser.stack().last().unwrap().to_owned()).map_err(SkimError::FromNode)?);
             }
-            // Stop creating the OPENING_NODE or CLOSING_NODE. Then Push or Pop from stack
-            '>' => {
-                // Push any remaining attribute
-                if !current_attr.name.is_empty() {
-                    current_node.attributes.insert(current_attr.name, current_attr.value);
+        }
+    }
+
+    Ok(found)
+}
+
+/// Get the text inside every node that matches **selector** (including the text of its descendants), trimmed,
+/// in the order the nodes appear.
+/// 
+/// A matched node inside another matched node has its own text, which is also part of the text of the outer node.
+/// 
+/// ```
+/// use xml_skimmer::find_all_text;
+/// 
+/// let texts = find_all_text("<ul><li> a </li><li>b<li>c</li></li></ul>", "li").unwrap();
+/// assert_eq!(texts, ["a", "bc", "c"]);
+/// ```
+pub fn find_all_text(xml_src: &str, selector: &str) -> Result<Vec<String>, SkimError> {
+    let selector = parse_unscoped(selector).map_err(SkimError::BadSelector)?;
+    let mut parser = Parser::new(xml_src, SkimOptions::default());
+    let mut found = vec![];
+    // Text being buffered for the matched nodes that are open, with the depth of the node and its index in found
+    let mut text_sinks: Vec<(usize, usize, String)> = vec![];
+
+    while let Some(token) = parser.next_token()? {
+        match token {
+            Token::Open { .. } => if selector.match_node(parser.stack()) {
+                // Reserve the place of the text, so they are in the order the nodes were opened
+                text_sinks.push((parser.stack().len(), found.len(), String::new()));
+                found.push(String::new());
+            },
+            Token::Close(_) => if text_sinks.last().is_some_and(|(depth, _, _)| *depth == parser.stack().len() + 1) {
+                let (_, index, text) = text_sinks.pop().unwrap();
+                found[index] = text.trim().to_string();
+            },
+            Token::Text(text) =>
+                for (_, _, sink) in text_sinks.iter_mut() {
+                    sink.push_str(&text);
+                },
+            Token::Comment(_) | Token::ProcessingInstruction(..) => {}
+        }
+        // Whitespace inside the matched nodes is part of their text
+        parser.keep_whitespace_text = !text_sinks.is_empty();
+    }
+
+    Ok(found)
+}
+
+/// Get the value of the attribute **attr** of every node that matches **selector**, in the order the nodes appear.
+/// Matched nodes that don't have the attribute are skipped. The values are decoded.
+/// 
+/// ```
+/// use xml_skimmer::find_all_attr;
+/// 
+/// let links = find_all_attr("<p><a href='/'/><a name='top'/><a href='/a?b=1&amp;c=2'/></p>", "a", "href").unwrap();
+/// assert_eq!(links, ["/", "/a?b=1&c=2"]);
+/// ```
+pub fn find_all_attr(xml_src: &str, selector: &str, attr: &str) -> Result<Vec<String>, SkimError> {
+    let selector = parse_unscoped(selector).map_err(SkimError::BadSelector)?;
+    let mut parser = Parser::new(xml_src, SkimOptions::default());
+    let mut found = vec![];
+
+    while let Some(token) = parser.next_token()? {
+        if let Token::Open { .. } = token {
+            if selector.match_node(parser.stack()) {
+                // Only the value is copied, not the node
+                if let Some(value) = parser.stack().last().unwrap().attribute_decoded(attr) {
+                    found.push(value.into_owned());
                 }
+            }
+        }
+    }
 
-                // Managing XML Stack
-                match node_type {
-                    // Doe something if a selector matches the current_node
-                    NodeType::Opening | NodeType::SelfClosing => {
-                        stack.push(current_node);
-                        // Handlers: when a node has been parsed and some data needs to be read from it
-                        // Check if any selector (keys in the HashMap) matches current_node
-                        for (sel, handler) in handlers.iter_mut() {
-                            if sel.match_node(&stack) {
-                                handler(stack.last().unwrap());
-                            }
+    Ok(found)
+}
+
+/// The byte range in **xml_src** of every node that matches **selector** (from the `<` of its opening tag to the `>` of its closing tag),
+/// in the order the nodes appear. The range of a self-closing node only covers its tag.
+/// Useful to highlight the matches in an editor.
+/// 
+/// ```
+/// use xml_skimmer::find_all_spans;
+/// 
+/// let xml_src = "<ul><li>a</li><li/></ul>";
+/// let spans = find_all_spans(xml_src, "li").unwrap();
+/// assert_eq!(spans, [4..14, 14..19]);
+/// assert_eq!(&xml_src[spans[0].clone()], "<li>a</li>");
+/// ```
+pub fn find_all_spans(xml_src: &str, selector: &str) -> Result<Vec<Range<usize>>, SkimError> {
+    let selector = parse_unscoped(selector).map_err(SkimError::BadSelector)?;
+    let mut parser = Parser::new(xml_src, SkimOptions::default());
+    let mut found = vec![];
+    // The matched nodes that are open, with the depth of the node and its index in found
+    let mut open: Vec<(usize, usize)> = vec![];
+
+    while let Some(token) = parser.next_token()? {
+        match token {
+            Token::Open { .. } => if selector.match_node(parser.stack()) {
+                // Reserve the place of the span, so they are in the order the nodes were opened
+                let start = parser.stack().last().unwrap().start();
+                open.push((parser.stack().len(), found.len()));
+                found.push(start..start);
+            },
+            Token::Close(_) => if open.last().is_some_and(|(depth, _)| *depth == parser.stack().len() + 1) {
+                // The parser is right after the closing tag (or the self-closing tag)
+                let (_, index) = open.pop().unwrap();
+                found[index].end = parser.offset();
+            },
+            Token::Text(_) | Token::Comment(_) | Token::ProcessingInstruction(..) => {}
+        }
+    }
+
+    Ok(found)
+}
+
+/// The line and column (both starting at 1) of a byte **offset** in **xml_src**.
+/// Columns count characters, not bytes. An offset past the end is clamped to the end.
+/// 
+/// ```
+/// use xml_skimmer::line_col;
+/// 
+/// let xml_src = "<a>\n  <b></c>";
+/// assert_eq!(line_col(xml_src, 0), (1, 1));
+/// assert_eq!(line_col(xml_src, 9), (2, 6));
+/// ```
+pub fn line_col(xml_src: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(xml_src.len());
+    // Don't split a multi-byte char
+    while !xml_src.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let before = &xml_src[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/// Count how many times each tag appears in the XML source (both normal and self-closing nodes).
+/// 
+/// ```
+/// use xml_skimmer::tag_histogram;
+/// 
+/// let counts = tag_histogram("<ul><li/><li>a</li></ul>").unwrap();
+/// assert_eq!(counts["ul"], 1);
+/// assert_eq!(counts["li"], 2);
+/// ```
+pub fn tag_histogram(xml_src: &str) -> Result<HashMap<String, usize>, SkimError> {
+    let mut counts = HashMap::new();
+    for event in events(xml_src) {
+        match event? {
+            Event::Open(node) | Event::SelfClose(node) => *counts.entry(node.tag).or_insert(0) += 1,
+            _ => {}
+        }
+    }
+    Ok(counts)
+}
+
+/// The distinct tag names in the XML source (of both normal and self-closing nodes).
+/// Use [`tag_histogram()`] to also know how many times each one appears.
+/// 
+/// ```
+/// use std::collections::HashSet;
+/// use xml_skimmer::tag_set;
+/// 
+/// let tags = tag_set("<ul><li/><li>a</li></ul>").unwrap();
+/// assert_eq!(tags, HashSet::from(["ul".to_string(), "li".to_string()]));
+/// ```
+pub fn tag_set(xml_src: &str) -> Result<HashSet<String>, SkimError> {
+    let mut parser = Parser::new(xml_src, SkimOptions::default());
+    let mut tags = HashSet::new();
+    while let Some(token) = parser.next_token()? {
+        if let Token::Open { .. } = token {
+            // Only copy the tag the first time it is found
+            let tag = &parser.stack().last().unwrap().tag;
+            if !tags.contains(tag.as_ref()) {
+                tags.insert(tag.to_string());
+            }
+        }
+    }
+    Ok(tags)
+}
+
+/// Write the tree of an XML source for debugging: every tag and run of text goes in its own line, indented by its depth.
+/// Whitespace in text is collapsed (like [`TextMode::Collapse`]), and comments are skipped.
+/// 
+/// Nodes are written like [`ParsedNode`]'s [`Display`], with their attributes sorted by name and without escaping their values,
+/// so the output is not meant to be parsed again (see [`tree::Node::to_pretty_xml()`] for that).
+/// 
+/// ```
+/// use xml_skimmer::{pretty_print, PrintConfig};
+/// 
+/// let config = PrintConfig { indent: 2, colors: false };
+/// let printed = pretty_print("<ul><li id='a'>one</li><li/></ul>", &config).unwrap();
+/// assert_eq!(printed, "<ul>\n  <li id=\"a\">\n    one\n  </li>\n  <li/>\n</ul>\n");
+/// ```
+pub fn pretty_print(xml_src: &str, config: &PrintConfig) -> Result<String, SkimError> {
+    use std::fmt::Write;
+
+    let options = SkimOptions { text_mode: TextMode::Collapse, ..Default::default() };
+    let mut printed = String::new();
+    let mut depth = 0;
+
+    for event in events_with_options(xml_src, options) {
+        let event = event?;
+        if let Event::Close(_) = event {
+            depth -= 1;
+        }
+        printed.extend(std::iter::repeat_n(' ', depth * config.indent));
+
+        let _ = match &event {
+            Event::Open(node) | Event::SelfClose(node) if config.colors => write!(printed, "{node:#}"),
+            Event::Open(node) | Event::SelfClose(node) => write!(printed, "{node}"),
+            Event::Close(tag) if config.colors => write!(printed, "</\x1b[91m{tag}\x1b[0m>"),
+            Event::Close(tag) => write!(printed, "</{tag}>"),
+            Event::Text(text) => write!(printed, "{text}"),
+            // Not emitted with these options
+            Event::Comment(_) | Event::ProcessingInstruction(..) => Ok(())
+        };
+        match event {
+            Event::Open(_) => depth += 1,
+            Event::SelfClose(_) => {
+                // The node is written as an opening tag, which ends with `>`
+                printed.pop();
+                printed.push_str("/>");
+            },
+            _ => {}
+        }
+        printed.push('\n');
+    }
+
+    Ok(printed)
+}
+
+/// How [`pretty_print()`] writes the tree of an XML source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintConfig {
+    /// How many spaces each level of depth is indented with. Is [`PrintConfig::DEFAULT_INDENT`] by default.
+    pub indent: usize,
+    /// Whether tag names and attribute names are colored with ANSI escape codes, for terminals. Is `false` by default.
+    /// Opening tags are green, closing tags are red, and attribute names are cyan.
+    pub colors: bool,
+}
+impl PrintConfig {
+    /// The [`indent`](PrintConfig::indent) of the default config.
+    /// Also a good choice for [`tree::Node::to_pretty_xml()`].
+    pub const DEFAULT_INDENT: usize = 2;
+}
+impl Default for PrintConfig {
+    fn default() -> Self {
+        Self { indent: Self::DEFAULT_INDENT, colors: false }
+    }
+}
+
+/// Parse a selector for a query that does not start from a node (the whole document is skimmed),
+/// so selectors that use `:scope` (or start with a combinator, like `> li`) would never match.
+/// An empty (or whitespace-only) selector would never match either.
+fn parse_unscoped(selector: &str) -> Result<CommaSeparated<Selector>, SelectorParseError> {
+    let selector = selector.parse::<CommaSeparated<Selector>>()?;
+    if selector.0.is_empty() {
+        return Err(SelectorParseError::EmptyString)
+    }
+    if selector.0.iter().any(Selector::uses_scope) {
+        return Err(SelectorParseError::NoScope)
+    }
+    Ok(selector)
+}
+
+
+/// Options that change how the parser behaves.
+/// 
+/// Use with [`Skimmer::with_options()`] or [`skim_xml_with_options()`].
+/// 
+/// ```
+/// use xml_skimmer::{SkimOptions, TextMode};
+/// 
+/// let options = SkimOptions { text_mode: TextMode::Trim, ..Default::default() };
+/// ```
+#[derive(Debug, Clone)]
+pub struct SkimOptions {
+    /// How whitespace is handled in the text that is given to handlers.
+    pub text_mode: TextMode,
+    /// Skip runs of text that only have whitespace (like the indentation between tags), instead of giving them to handlers. Is `true` by default.
+    /// When `false`, they are kept as they are (and then handled with [`SkimOptions::text_mode`]).
+    /// 
+    /// Whitespace inside a node with `xml:space="preserve"` (or a descendant of one), and in CDATA, is never skipped.
+    /// Neither is whitespace inside a node whose text is captured (with [`Skimmer::on_text_of()`] or [`find_all_text()`]),
+    /// since it separates the text of the nodes inside it (like in `<p><b>a</b> <i>b</i></p>`).
+    pub ignore_whitespace_text: bool,
+    /// Whether comments are emitted as [`Event::Comment`]s (in the order they appear) instead of being skipped.
+    pub emit_comments: bool,
+    /// When parsing a [`tree`], join runs of text that end up next to each other into a single [`tree::NodeChild::Text`],
+    /// like the ones around a skipped comment (`a<!--x-->b`) or a CDATA section (`a<![CDATA[b]]>c`).
+    /// Text on both sides of a comment is not joined when the comment is kept (with [`SkimOptions::emit_comments`]).
+    /// 
+    /// [`Event`]s and handlers still get each run of text separately.
+    pub merge_adjacent_text: bool,
+    /// Whether the document can have comments. Is `true` by default.
+    /// When `false`, a comment is a [`SkimError::CommentsNotAllowed`] (for formats that don't allow them).
+    pub allow_comments: bool,
+    /// Return [`SkimError::NoRootElement`] when the document has no elements (it is empty, or only has text, comments, etc.),
+    /// like when the source is not actually XML. Is `false` by default, so fragments without elements are skimmed without errors.
+    pub require_root_element: bool,
+    /// Return [`SkimError::MissingProlog`] when the source doesn't start with an XML declaration (`<?xml version="1.0"?>`),
+    /// after an optional byte order mark (`\u{FEFF}`, which is then skipped), and [`SkimError::UnsupportedVersion`]
+    /// when the declared version is not `1.0` or `1.1`. Is `false` by default.
+    pub require_prolog: bool,
+    /// Whether processing instructions (`<?target data?>`) are emitted as [`Event::ProcessingInstruction`]s instead of being skipped.
+    /// Skipped processing instructions are not copied.
+    /// 
+    /// The XML declaration (`<?xml version="1.0"?>`) is not a processing instruction, and is always skipped.
+    pub emit_processing_instructions: bool,
+    /// Give a self-closing node (`<tag/>`) as an [`Event::Open`] followed by an [`Event::Close`], like `<tag></tag>`,
+    /// instead of an [`Event::SelfClose`]. Then every node has an open and a close event.
+    pub expand_self_closing: bool,
+    /// Accept HTML-ish documents that are not well-formed XML.
+    /// 
+    /// A closing tag that doesn't match the last open node, but matches one of its ancestors,
+    /// closes every node above that ancestor implicitly (e.g. `<div><p>text</div>` closes `p`, then `div`).
+    /// A closing tag that doesn't match any open node is skipped (e.g. the extra `</div>` in `<p></p></div>`),
+    /// and counted in [`SkimStats::stray_closing_tags`].
+    /// 
+    /// Characters between the `/` and `>` of a self-closing node are ignored (e.g. `<br/ clear>`),
+    /// and so is whitespace before a tag name (e.g. `< br>`).
+    /// Attribute names that are not valid XML names are accepted (e.g. `<a 1x="y">`, `<a @click="f">`, or `<a x/y="z">`).
+    pub lenient: bool,
+    /// Return [`SkimError::DuplicateAttribute`] when a node has the same attribute more than once (as XML requires),
+    /// instead of keeping the value of the last one.
+    pub reject_duplicate_attributes: bool,
+    /// Return [`SkimError::SuspiciousAttrValue`] when an attribute value has a `<` or `>`,
+    /// which usually means that its closing quote is missing and the value was read until a quote in some later markup
+    /// (e.g. `<a title="x><b id="y">` reads the value `x><b id=`).
+    /// Values that really contain markup must escape it with `&lt;` and `&gt;`.
+    pub reject_markup_in_attributes: bool,
+    /// Return [`SkimError::IllegalCharSequence`] when text has `]]>` outside of a CDATA section, which XML doesn't allow
+    /// (it has to be escaped, like `]]&gt;`). It is accepted as text otherwise.
+    pub reject_cdata_end_in_text: bool,
+    /// Return [`SkimError::TextOutsideRoot`] when there is text (or CDATA) other than whitespace before or after the root element,
+    /// which XML doesn't allow (e.g. `text<root/>` or `<root/>junk`).
+    /// Whitespace, comments, and processing instructions are still allowed there.
+    pub reject_text_outside_root: bool,
+    /// Convert tag names and attribute names to ASCII lowercase (like HTML does), so `<DIV CLASS="x">` is stored as `div` with attribute `class`.
+    /// Closing tags are also lowercased, so `<P></p>` is valid.
+    /// Attribute values and text are not changed.
+    pub html_lowercase: bool,
+    /// Stop parsing once handlers were called this many times (counting every handler that matched a node).
+    /// The rest of the source is not read, so errors in it are not found.
+    /// An `on_text_of` handler counts when its node is matched, and parsing continues until it gets its text.
+    pub max_matches: Option<usize>,
+    /// Return [`SkimError::UnknownEntity`] for an entity that is not predefined (like `&foo;`) or an `&` that is not part of an entity,
+    /// instead of leaving them as they are. See [`decode_entities_strict()`](entities::decode_entities_strict).
+    pub strict_entities: bool,
+    /// Keep attribute values as they are in the source, without decoding their entities,
+    /// which saves allocations when handlers only read a few attributes.
+    /// Decode the values that are needed with [`decode_entities()`](entities::decode_entities),
+    /// which only allocates if the value has entities (selectors already match with the decoded values).
+    /// 
+    /// The values are not checked by [`SkimOptions::strict_entities`].
+    pub lazy_attributes: bool,
+    /// Only keep these attributes in the nodes given to handlers (by name, after [`SkimOptions::html_lowercase`]), to save memory
+    /// when nodes have many attributes that are not needed. The other attributes are still read (and checked),
+    /// and selectors match all the attributes, since nodes are matched before they are copied.
+    /// 
+    /// Nodes in [`Event`]s, [`tree`] nodes, and nodes in errors also only have these attributes.
+    pub retain_attributes: Option<HashSet<String>>,
+    /// Namespace prefixes of the selectors (`prefix|tag`), mapped to their URI.
+    /// Selectors given to the [`Skimmer`] match the URI, no matter what prefix the document uses for it.
+    /// See [`Selector::resolve_namespaces()`].
+    pub namespaces: HashMap<String, String>,
+    /// Tags that are always self-closing, even when written as `<tag>` (like `<br>` in HTML, but for any vocabulary).
+    /// They never have content, so a closing tag for them (`</tag>`) is an error.
+    pub self_closing_tags: HashSet<String>,
+}
+impl Default for SkimOptions {
+    fn default() -> Self {
+        Self {
+            text_mode: TextMode::default(),
+            ignore_whitespace_text: true,
+            emit_comments: false,
+            merge_adjacent_text: false,
+            allow_comments: true,
+            require_root_element: false,
+            require_prolog: false,
+            emit_processing_instructions: false,
+            expand_self_closing: false,
+            lenient: false,
+            reject_duplicate_attributes: false,
+            reject_markup_in_attributes: false,
+            reject_cdata_end_in_text: false,
+            reject_text_outside_root: false,
+            html_lowercase: false,
+            max_matches: None,
+            strict_entities: false,
+            lazy_attributes: false,
+            retain_attributes: None,
+            namespaces: HashMap::new(),
+            self_closing_tags: HashSet::new(),
+        }
+    }
+}
+
+/// How whitespace of each run of text (text between 2 tags) is handled.
+/// 
+/// Text inside a node with `xml:space="preserve"` (or a descendant of one) is always [`Preserve`](TextMode::Preserve)d.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextMode {
+    /// Keep the text as it was read.
+    #[default]
+    Preserve,
+    /// Remove leading and trailing whitespace.
+    Trim,
+    /// Replace every run of whitespace with a single space, and remove leading and trailing whitespace.
+    Collapse,
+}
+impl TextMode {
+    /// Handle the whitespace of a run of **text** according to this mode.
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            Self::Preserve => Cow::Borrowed(text),
+            Self::Trim => Cow::Borrowed(text.trim()),
+            Self::Collapse => Cow::Owned(text.split_whitespace().collect::<Vec<_>>().join(" "))
+        }
+    }
+}
+
+
+/// Holds a list of handlers (closures) paired with a CSS selector,
+/// and calls them while skimming through an XML source.
+/// 
+/// Unlike [`skim_xml`], the selectors are parsed once when the handler is registered,
+/// and handlers are called in the order they were registered.
+/// 
+/// ## Example
+/// 
+/// ```
+/// use xml_skimmer::Skimmer;
+/// 
+/// let mut titles = vec![];
+/// let mut skimmer = Skimmer::new();
+/// skimmer.on_text_of("book > title", |text, _node| titles.push(text.to_string())).unwrap();
+/// skimmer.skim("<book><title>Dune</title></book>").unwrap();
+/// drop(skimmer);
+/// 
+/// assert_eq!(titles, ["Dune"]);
+/// ```
+#[derive(Default)]
+pub struct Skimmer<'f> {
+    handlers: Vec<(CommaSeparated<Selector>, Handler<'f>)>,
+    complete_handlers: Vec<CompleteHandler<'f>>,
+    options: SkimOptions
+}
+impl<'f> Skimmer<'f> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: SkimOptions) -> Self {
+        Self {
+            handlers: vec![],
+            complete_handlers: vec![],
+            options
+        }
+    }
+
+    /// Parse a selector of a handler, with the [namespaces](SkimOptions::namespaces) of the options.
+    fn parse_selector(&self, selector: &str) -> Result<CommaSeparated<Selector>, SelectorParseError> {
+        let mut selector = parse_unscoped(selector)?;
+        selector.resolve_namespaces(&self.options.namespaces);
+        Ok(selector)
+    }
+
+    /// Register a **handler** that is called with the node when its opening tag is read.
+    /// No text of the node has been read at this point.
+    pub fn on<F>(&mut self, selector: &str, handler: F) -> Result<&mut Self, SelectorParseError>
+    where F: FnMut(&ParsedNode) + 'f {
+        self.handlers.push((self.parse_selector(selector)?, Handler::Node(Box::new(handler))));
+        Ok(self)
+    }
+
+    /// Register a **handler** that is called with the whole stack when the opening tag of a matched node is read.
+    /// The last node of the stack is the matched node, and the rest are its ancestors.
+    /// 
+    /// Useful with [`closest()`] to read data from the context of the matched node.
+    pub fn on_with_stack<F>(&mut self, selector: &str, handler: F) -> Result<&mut Self, SelectorParseError>
+    where F: FnMut(&[ParsedNode]) + 'f {
+        self.handlers.push((self.parse_selector(selector)?, Handler::Stack(Box::new(handler))));
+        Ok(self)
+    }
+
+    /// Register a **handler** that is called with all the text inside a matched node
+    /// (including the text of its descendants), once the node is closed.
+    /// 
+    /// The text is buffered from the opening tag of the matched node until its closing tag.
+    /// Self-closing nodes call the handler with an empty string.
+    pub fn on_text_of<F>(&mut self, selector: &str, handler: F) -> Result<&mut Self, SelectorParseError>
+    where F: FnMut(&str, &ParsedNode) + 'f {
+        self.handlers.push((self.parse_selector(selector)?, Handler::Text(Box::new(handler))));
+        Ok(self)
+    }
+
+    /// Register a **handler** that is called with a matched node once it is closed
+    /// (right after the opening tag for self-closing nodes), so its [`ParsedNode::inner_span()`] is known.
+    /// 
+    /// ```
+    /// use xml_skimmer::Skimmer;
+    /// 
+    /// let xml_src = "<p>Some <b>bold</b> text</p>";
+    /// let mut inner = vec![];
+    /// let mut skimmer = Skimmer::new();
+    /// skimmer.on_close("p, b", |node| inner.push(node.inner_xml(xml_src).unwrap().to_string())).unwrap();
+    /// skimmer.skim(xml_src).unwrap();
+    /// drop(skimmer);
+    /// assert_eq!(inner, ["bold", "Some <b>bold</b> text"]);
+    /// ```
+    pub fn on_close<F>(&mut self, selector: &str, handler: F) -> Result<&mut Self, SelectorParseError>
+    where F: FnMut(&ParsedNode) + 'f {
+        self.handlers.push((self.parse_selector(selector)?, Handler::Close(Box::new(handler))));
+        Ok(self)
+    }
+
+    /// Register a **handler** that is called once with the [`SkimStats`] of the source, after [`skim()`](Skimmer::skim) read all of it.
+    /// Useful to flush buffers or log a summary. It is not called if skimming fails.
+    /// 
+    /// It is also called when skimming stopped early because of [`SkimOptions::max_matches`].
+    /// 
+    /// ```
+    /// use xml_skimmer::Skimmer;
+    /// 
+    /// let mut total = 0;
+    /// let mut skimmer = Skimmer::new();
+    /// skimmer.on_complete(|stats| total = stats.nodes);
+    /// skimmer.skim("<a><b/><b/></a>").unwrap();
+    /// drop(skimmer);
+    /// 
+    /// assert_eq!(total, 3);
+    /// ```
+    pub fn on_complete<F>(&mut self, handler: F) -> &mut Self
+    where F: FnMut(SkimStats) + 'f {
+        self.complete_handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Skim an XML source, calling the registered handlers for every node that matches their selector.
+    pub fn skim(&mut self, xml_src: &str) -> Result<(), SkimError> {
+        let mut parser = Parser::new(xml_src, self.options.clone());
+        // Text being buffered for the `on_text_of` handlers, with the depth of the node they matched.
+        let mut text_sinks: Vec<TextSink> = vec![];
+        let mut stats = SkimStats::default();
+        // How many times a handler matched, for SkimOptions::max_matches
+        let mut matches = 0;
+        let max_matches = self.options.max_matches.unwrap_or(usize::MAX);
+
+        while let Some(token) = parser.next_token()? {
+            match token {
+                Token::Open { .. } => {
+                    let stack = parser.stack();
+                    stats.nodes += 1;
+                    stats.max_depth = stats.max_depth.max(stack.len());
+                    // The stack is only copied out of the source if a handler needs it
+                    let mut owned_stack: Option<Vec<ParsedNode>> = None;
+                    // Handlers: when a node has been parsed and some data needs to be read from it
+                    // Check if any selector (registered with the handler) matches current_node
+                    for (i, (sel, handler)) in self.handlers.iter_mut().enumerate() {
+                        if matches >= max_matches {
+                            break
                         }
-                        // When is self-closing, node is pushed, matched, then removed.
-                        if node_type == NodeType::SelfClosing {
-                            stack.pop();
+                        if sel.match_node(stack) {
+                            matches += 1;
+                            match handler {
+                                Handler::Node(handler) => handler(&stack.last().unwrap().to_owned()),
+                                Handler::Stack(handler) => handler(owned_stack.get_or_insert_with(|| stack.iter().map(ParsedNodeRef::to_owned).collect())),
+                                // Start buffering the text of this node (or just wait for it to close)
+                                Handler::Text(_) | Handler::Close(_) => text_sinks.push(TextSink { depth: stack.len(), handler: i, text: String::new() })
+                            }
                         }
                     }
-                    // Pop last ParsedNode.
-                    NodeType::Closing =>
-                        // Tag of last ParsedNode must be identical to the current/CLOSING_NODE
-                        match stack.pop() {
-                            Some(node) if current_node.tag == node.tag => {
-                                // print!("{}", " ".repeat((stack.len() * INDENT_AMOUNT) as usize));
-                                // println!("</\x1b[91m{}\x1b[0m>", node.tag);
-                            }
-                            Some(node) => return Err(SkimError::CantCloseNode(current_node.tag, Some(node))),
-                            None => return Err(SkimError::CantCloseNode(current_node.tag, None))
-                        },
-                    // NodeType::None will not be reached here
-                    NodeType::None => panic!("Found '>' with NodeType::None")
                 }
+                Token::Close(node) => self.flush_text_sinks(&mut text_sinks, parser.stack().len() + 1, &node),
+                Token::Text(text) =>
+                    for sink in text_sinks.iter_mut() {
+                        if let Handler::Text(_) = self.handlers[sink.handler].1 {
+                            sink.text.push_str(&text);
+                        }
+                    },
+                Token::Comment(_) | Token::ProcessingInstruction(..) => {}
+            }
 
-                // Reset Values
-                current_node = ParsedNode::default();
-                current_attr = Attr::default();
-                writing_to = WriteTo::Content;
-                node_type = NodeType::None;
+            // Stop once the last match got all of its text
+            if matches >= max_matches && text_sinks.is_empty() {
+                break
             }
-            
-            _ if character.is_whitespace() => {
-                // Whitespace only matters in an OPENING_NODE
-                if node_type == NodeType::Opening {
-                    match writing_to {
-                        // Switch from writing to tag -> writing to attr_name
-                        WriteTo::Tag if !current_node.tag.is_empty() => writing_to = WriteTo::AttrName,
-                        // Push attr (if name not empty) to current_node (In case of duplicate attr, the last one read will remain)
-                        // Case of Boolean Attributes (e.g.: <tag attr1 attr2>)
-                        WriteTo::AttrName => {
-                            // Look for the equal sign (=) before hitting any other char (except whitespace)
-                            while let Some(character) = iter.next() {
-                                match character {
-                                    // Equal sign (=) means to begin AttrVal
-                                    '=' => {
-                                        writing_to = WriteTo::AttrVal;
-                                        break;
-                                    }
-                                    // Ignore whitespace
-                                    ' ' | '\n' | '\t' => {  }
-                                    // A different attribute has been reached
-                                    _ => {
-                                        // Only push attribute if it exists
-                                        if !current_attr.name.is_empty() {
-                                            // Attr will have an empty value
-                                            current_node.attributes.insert(current_attr.name, String::new());
-                                            current_attr = Attr::default();
-                                        }
-                                        // add this character to the new attribute, as it will be skipped by the iterator
-                                        current_attr.name.push(character);
-                                        break;
-                                    }
-                                }
-                            }
+            // Whitespace inside the nodes whose text is buffered is part of their text
+            parser.keep_whitespace_text = text_sinks.iter().any(|sink| matches!(self.handlers[sink.handler].1, Handler::Text(_)));
+        }
+
+        stats.matches = matches;
+        stats.stray_closing_tags = parser.stray_closing_tags;
+        for handler in &mut self.complete_handlers {
+            handler(stats);
+        }
+        Ok(())
+    }
+
+    /// Iterate over the nodes that match the selectors of the handlers, in the order they appear, instead of calling the handlers.
+    /// Each item has the index of the handler (in the order they were registered) and the matched node.
+    /// A node that matches several handlers is given once for each, in the order of the handlers.
+    /// 
+    /// The iterator stops after the first error, or after [`SkimOptions::max_matches`] items.
+    /// 
+    /// ```
+    /// use xml_skimmer::{ParsedNode, Skimmer};
+    /// 
+    /// let mut skimmer = Skimmer::new();
+    /// skimmer.on("a", |_| {}).unwrap().on("b, a", |_| {}).unwrap();
+    /// let matches = skimmer.matches("<a><b/></a>").collect::<Result<Vec<_>, _>>().unwrap();
+    /// assert_eq!(matches, [(0, ParsedNode::new("a")), (1, ParsedNode::new("a")), (1, ParsedNode::new("b"))]);
+    /// ```
+    pub fn matches<'a>(&'a self, xml_src: &'a str) -> Matches<'a> {
+        Matches {
+            parser: Parser::new(xml_src, self.options.clone()),
+            selectors: self.handlers.iter().map(|(selector, _)| selector).collect(),
+            pending: VecDeque::new(),
+            remaining: self.options.max_matches.unwrap_or(usize::MAX),
+            done: false
+        }
+    }
+
+    /// Call the `on_text_of` handlers of the node that was just closed (which was at **depth** in the stack)
+    /// with the text they buffered, and its `on_close` handlers.
+    fn flush_text_sinks(&mut self, text_sinks: &mut Vec<TextSink>, depth: usize, node: &ParsedNodeRef) {
+        // Sinks of the same node are next to each other at the end, in the order their handlers were registered.
+        let start = match text_sinks.iter().position(|sink| sink.depth == depth) {
+            Some(start) => start,
+            None => return
+        };
+
+        let node = node.to_owned();
+        for sink in text_sinks.drain(start..) {
+            match &mut self.handlers[sink.handler].1 {
+                Handler::Text(handler) => handler(&sink.text, &node),
+                Handler::Close(handler) => handler(&node),
+                _ => {}
+            }
+        }
+    }
+}
+
+type NodeHandler<'f> = Box<dyn FnMut(&ParsedNode) + 'f>;
+type StackHandler<'f> = Box<dyn FnMut(&[ParsedNode]) + 'f>;
+type TextHandler<'f> = Box<dyn FnMut(&str, &ParsedNode) + 'f>;
+type CloseHandler<'f> = Box<dyn FnMut(&ParsedNode) + 'f>;
+type CompleteHandler<'f> = Box<dyn FnMut(SkimStats) + 'f>;
+
+/// Counts of what a [`Skimmer`] read from a source. Given to the [`on_complete()`](Skimmer::on_complete) handlers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SkimStats {
+    /// How many nodes were opened (including self-closing nodes).
+    pub nodes: usize,
+    /// How many times a handler matched a node (see [`SkimOptions::max_matches`]).
+    pub matches: usize,
+    /// The length of the stack with the most nested node.
+    pub max_depth: usize,
+    /// How many closing tags were skipped because no open node had their tag. Only with [`SkimOptions::lenient`].
+    pub stray_closing_tags: usize,
+}
+
+/// Find the closest node in the **stack** that matches **selector**, starting from the last node (including it) and going up through its ancestors.
+/// Similar to [`Element.closest()`](https://developer.mozilla.org/en-US/docs/Web/API/Element/closest) in the DOM.
+/// 
+/// Each node is matched with the part of the stack that ends at that node,
+/// so selectors with combinators can also be used.
+/// 
+/// ```
+/// use xml_skimmer::{closest, ParsedNode, selector::Selector};
+/// 
+/// let stack = [ParsedNode::new("table").with_attr("id", "t"), ParsedNode::new("tr"), ParsedNode::new("td")];
+/// let table = closest(&stack, &"table".parse::<Selector>().unwrap());
+/// assert_eq!(table.unwrap().attributes["id"], "t");
+/// ```
+pub fn closest<'a>(stack: &'a [ParsedNode], selector: &Selector) -> Option<&'a ParsedNode> {
+    (1..=stack.len()).rev()
+        .find(|&len| selector.match_node(&stack[..len]))
+        .map(|len| &stack[len - 1])
+}
+
+/// The ancestors of the matched node (the last node of the **stack**), from the nearest (its parent) to the farthest (the root).
+/// The matched node itself is not included.
+/// 
+/// ```
+/// use xml_skimmer::{ancestors, ParsedNode};
+/// 
+/// let stack = [ParsedNode::new("html"), ParsedNode::new("body"), ParsedNode::new("p")];
+/// let tags = ancestors(&stack).map(|node| node.tag.as_str()).collect::<Vec<_>>();
+/// assert_eq!(tags, ["body", "html"]);
+/// ```
+pub fn ancestors(stack: &[ParsedNode]) -> impl Iterator<Item = &ParsedNode> {
+    stack[..stack.len().saturating_sub(1)].iter().rev()
+}
+
+/// Whether one of the [`ancestors()`] of the matched node (the last node of the **stack**) has the tag **tag**.
+/// Useful to know where a node is without building a [`Selector`] (e.g. "am I inside a `<table>`?").
+/// 
+/// ```
+/// use xml_skimmer::{has_ancestor, ParsedNode};
+/// 
+/// let stack = [ParsedNode::new("table"), ParsedNode::new("tr"), ParsedNode::new("td")];
+/// assert!(has_ancestor(&stack, "table"));
+/// assert!(!has_ancestor(&stack, "td"));
+/// ```
+pub fn has_ancestor(stack: &[ParsedNode], tag: &str) -> bool {
+    ancestors(stack).any(|node| node.tag == tag)
+}
+
+
+/// A closure registered to a [`Skimmer`].
+enum Handler<'f> {
+    /// Called when the opening tag of a matched node is read.
+    Node(NodeHandler<'f>),
+    /// Called with the stack when the opening tag of a matched node is read.
+    Stack(StackHandler<'f>),
+    /// Called with the text of the matched node when it is closed.
+    Text(TextHandler<'f>),
+    /// Called with the matched node when it is closed.
+    Close(CloseHandler<'f>)
+}
+
+/// Buffers the text of a node matched by an `on_text_of` handler, or waits for a node matched by an `on_close` handler to close.
+struct TextSink {
+    /// The length of the stack when the node was pushed.
+    depth: usize,
+    /// Index of the handler in [`Skimmer`].
+    handler: usize,
+    text: String
+}
+
+
+/// Iterator over the nodes matched by the selectors of a [`Skimmer`]. Created with [`Skimmer::matches()`].
+pub struct Matches<'a> {
+    parser: Parser<'a>,
+    selectors: Vec<&'a CommaSeparated<Selector>>,
+    /// Indices of the selectors that matched the last node of the stack, which have not been given yet.
+    pending: VecDeque<usize>,
+    /// How many more matches can be given, for [`SkimOptions::max_matches`].
+    remaining: usize,
+    /// An error was found, the source ended, or there can't be more matches.
+    done: bool
+}
+impl Iterator for Matches<'_> {
+    type Item = Result<(usize, ParsedNode), SkimError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(index) = self.pending.pop_front() {
+                return Some(Ok((index, self.parser.stack().last().unwrap().to_owned())))
+            }
+            if self.done || self.remaining == 0 {
+                return None
+            }
+
+            match self.parser.next_token() {
+                Ok(Some(Token::Open { .. })) => {
+                    let stack = self.parser.stack();
+                    for (index, selector) in self.selectors.iter().enumerate() {
+                        if self.remaining > 0 && selector.match_node(stack) {
+                            self.remaining -= 1;
+                            self.pending.push_back(index);
                         }
-                        _ => {}
                     }
                 }
+                Ok(Some(_)) => {},
+                Ok(None) => self.done = true,
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error))
+                }
+            }
+        }
+    }
+}
+
+/// Skims an XML source one match at a time, keeping the parser's state between calls to [`next_match()`](SkimCursor::next_match).
+/// Like a lazy [`find_nth()`] that continues where the last match was found, so the source is only parsed once.
+/// 
+/// ```
+/// use xml_skimmer::{ParsedNode, SkimCursor};
+/// 
+/// let mut cursor = SkimCursor::new("<ul><li id='a'/><li id='b'><li id='c'/></li></ul>", "li").unwrap();
+/// assert_eq!(cursor.next_match().unwrap(), Some(ParsedNode::new("li").with_attr("id", "a")));
+/// assert_eq!(cursor.next_match().unwrap(), Some(ParsedNode::new("li").with_attr("id", "b")));
+/// // The stack stays at the last match until the cursor advances
+/// assert_eq!(cursor.stack().len(), 2);
+/// assert_eq!(cursor.next_match().unwrap(), Some(ParsedNode::new("li").with_attr("id", "c")));
+/// assert_eq!(cursor.next_match().unwrap(), None);
+/// ```
+pub struct SkimCursor<'a> {
+    parser: Parser<'a>,
+    selector: CommaSeparated<Selector>,
+    /// How many more matches can be given, for [`SkimOptions::max_matches`].
+    remaining: usize,
+    /// An error was found, the source ended, or there can't be more matches.
+    done: bool
+}
+impl<'a> SkimCursor<'a> {
+    pub fn new(xml_src: &'a str, selector: &str) -> Result<Self, SelectorParseError> {
+        Self::with_options(xml_src, selector, SkimOptions::default())
+    }
+
+    /// The **selector** is parsed with the [namespaces](SkimOptions::namespaces) of the **options**, like in a [`Skimmer`].
+    pub fn with_options(xml_src: &'a str, selector: &str, options: SkimOptions) -> Result<Self, SelectorParseError> {
+        let mut selector = parse_unscoped(selector)?;
+        selector.resolve_namespaces(&options.namespaces);
+        Ok(Self {
+            remaining: options.max_matches.unwrap_or(usize::MAX),
+            parser: Parser::new(xml_src, options),
+            selector,
+            done: false
+        })
+    }
+
+    /// Parse until the next node that matches the selector, and return a copy of it.
+    /// Returns [`None`] when there are no more matches.
+    /// 
+    /// After an error is returned, the cursor can't continue and always returns [`None`].
+    pub fn next_match(&mut self) -> Result<Option<ParsedNode>, SkimError> {
+        while !self.done && self.remaining > 0 {
+            match self.parser.next_token() {
+                Ok(Some(Token::Open { .. })) => {
+                    let stack = self.parser.stack();
+                    if self.selector.match_node(stack) {
+                        self.remaining -= 1;
+                        return Ok(Some(stack.last().unwrap().to_owned()))
+                    }
+                },
+                Ok(Some(_)) => {},
+                Ok(None) => self.done = true,
+                Err(error) => {
+                    self.done = true;
+                    return Err(error)
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The nodes that are currently open. After a match, the last one is the matched node and the rest are its ancestors.
+    pub fn stack(&self) -> &[ParsedNodeRef<'a>] {
+        self.parser.stack()
+    }
+}
+
+/// Creates an iterator over the [`Event`]s of an XML source.
+/// 
+/// The iterator stops after the first error.
+/// 
+/// ```
+/// use xml_skimmer::{events_with_options, Event, ParsedNode, SkimOptions};
+/// 
+/// let options = SkimOptions { emit_comments: true, ..Default::default() };
+/// let events = events_with_options("<p>a<!--c-->b</p>", options).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(events, [
+///     Event::Open(ParsedNode::new("p")),
+///     Event::Text("a".to_string()),
+///     Event::Comment("c".to_string()),
+///     Event::Text("b".to_string()),
+///     Event::Close("p".to_string()),
+/// ]);
+/// ```
+pub fn events(xml_src: &str) -> Events<'_> {
+    events_with_options(xml_src, SkimOptions::default())
+}
+
+/// Same as [`events`], but the parser will behave according to **options**.
+pub fn events_with_options(xml_src: &str, options: SkimOptions) -> Events<'_> {
+    Events {
+        parser: Parser::new(xml_src, options),
+        self_closing: false,
+        done: false
+    }
+}
+
+/// Something found in the XML source, in the order it appears. See [`events`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// An opening tag `<tag>`.
+    Open(ParsedNode),
+    /// A self-closing tag `<tag/>`. There is no [`Close`](Event::Close) event for it.
+    /// With [`SkimOptions::expand_self_closing`], it is an [`Open`](Event::Open) and a [`Close`](Event::Close) event instead.
+    SelfClose(ParsedNode),
+    /// A closing tag `</tag>`. Contains the tag name.
+    Close(String),
+    /// A run of text between tags (or comments), or the content of a CDATA section.
+    /// Entities are decoded (except in CDATA), and the text is handled according to [`SkimOptions::text_mode`].
+    /// It is never empty.
+    Text(String),
+    /// The content of a comment `<!--content-->`.
+    /// Only emitted when [`SkimOptions::emit_comments`] is `true`.
+    Comment(String),
+    /// A processing instruction `<?target data?>`. Contains the target and the data (without the whitespace before it).
+    /// Only emitted when [`SkimOptions::emit_processing_instructions`] is `true`.
+    ProcessingInstruction(String, String),
+}
+
+impl Event {
+    /// The kind of tag of this event, or [`None`] for text, comments, and processing instructions.
+    /// 
+    /// ```
+    /// use xml_skimmer::{Event, NodeType, ParsedNode};
+    /// 
+    /// assert_eq!(Event::SelfClose(ParsedNode::new("br")).node_type(), Some(NodeType::SelfClosing));
+    /// assert_eq!(Event::Text("text".to_string()).node_type(), None);
+    /// ```
+    pub fn node_type(&self) -> Option<NodeType> {
+        match self {
+            Self::Open(_) => Some(NodeType::Opening),
+            Self::SelfClose(_) => Some(NodeType::SelfClosing),
+            Self::Close(_) => Some(NodeType::Closing),
+            Self::Text(_) | Self::Comment(_) | Self::ProcessingInstruction(..) => None
+        }
+    }
+}
+
+/// Iterator over the [`Event`]s of an XML source. Created with [`events`].
+pub struct Events<'a> {
+    parser: Parser<'a>,
+    /// The last event was a [`Event::SelfClose`], so the [`Token::Close`] of that node is skipped.
+    self_closing: bool,
+    /// An error was found or the source ended.
+    done: bool
+}
+impl<'a> Events<'a> {
+    /// The nodes that are currently open. The last one is the innermost.
+    pub fn stack(&self) -> &[ParsedNodeRef<'a>] {
+        self.parser.stack()
+    }
+}
+impl Iterator for Events<'_> {
+    type Item = Result<Event, SkimError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let token = match self.parser.next_token() {
+                Ok(Some(token)) => token,
+                Ok(None) => break,
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error))
+                }
+            };
+
+            let event = match token {
+                Token::Open { self_closing } => {
+                    let node = self.parser.stack().last().unwrap().to_owned();
+                    // An expanded node gets the Close event of its Token::Close
+                    self.self_closing = self_closing && !self.parser.options.expand_self_closing;
+                    if self.self_closing {
+                        Event::SelfClose(node)
+                    } else {
+                        Event::Open(node)
+                    }
+                }
+                // The node was already in the SelfClose event
+                Token::Close(_) if self.self_closing => {
+                    self.self_closing = false;
+                    continue
+                }
+                Token::Close(node) => Event::Close(node.tag.into_owned()),
+                Token::Text(text) => Event::Text(text.into_owned()),
+                Token::Comment(content) => Event::Comment(content.to_string()),
+                Token::ProcessingInstruction(target, data) => Event::ProcessingInstruction(target.to_string(), data.to_string()),
+            };
+            return Some(Ok(event))
+        }
+
+        self.done = true;
+        None
+    }
+}
+
+
+/// What the [`Parser`] read from the source.
+enum Token<'a> {
+    /// The opening tag of the node that is now the last in the stack.
+    Open { self_closing: bool },
+    /// The node that was popped from the stack.
+    /// Self-closing nodes are also popped (on the call after [`Token::Open`]).
+    Close(ParsedNodeRef<'a>),
+    /// A run of text or CDATA, already decoded and handled according to [`SkimOptions::text_mode`]. Never empty.
+    Text(Cow<'a, str>),
+    /// Content of a comment. Only if [`SkimOptions::emit_comments`] is `true`.
+    Comment(&'a str),
+    /// Target and data of a processing instruction. Only if [`SkimOptions::emit_processing_instructions`] is `true`.
+    ProcessingInstruction(&'a str, &'a str),
+}
+
+/// Reads the XML source one [`Token`] at a time, and keeps the stack of open nodes.
+struct Parser<'a> {
+    src: &'a str,
+    iter: Chars<'a>,
+    stack: Vec<ParsedNodeRef<'a>>,
+    options: SkimOptions,
+    /// How many nodes have to be popped from the stack (one on each call) before reading further.
+    /// Is 1 after a self-closing node was pushed, and can be more when [`SkimOptions::lenient`] closes nodes implicitly.
+    pending_closes: usize,
+    /// Whether a node was opened at some point (see [`SkimOptions::require_root_element`]).
+    found_element: bool,
+    /// Depths (stack lengths) of the nodes with an `xml:space` attribute, so the closest one is found without going through the stack.
+    /// Can end with depths of nodes that were already popped, which are ignored (and removed when another node is pushed).
+    xml_space: Vec<usize>,
+    /// Taken from [`SkimOptions::retain_attributes`], to share it with the nodes in the stack.
+    retain_attributes: Option<Arc<HashSet<String>>>,
+    /// How many closing tags were skipped because no open node had their tag (see [`SkimOptions::lenient`]).
+    stray_closing_tags: usize,
+    /// Keep whitespace-only text even with [`SkimOptions::ignore_whitespace_text`], because it is part of a text that is being captured.
+    keep_whitespace_text: bool,
+}
+impl<'a> Parser<'a> {
+    fn new(xml_src: &'a str, mut options: SkimOptions) -> Self {
+        Self {
+            retain_attributes: options.retain_attributes.take().map(Arc::new),
+            src: xml_src,
+            iter: xml_src.chars(),
+            stack: vec![],
+            options,
+            pending_closes: 0,
+            found_element: false,
+            xml_space: vec![],
+            stray_closing_tags: 0,
+            keep_whitespace_text: false,
+        }
+    }
+
+    fn stack(&self) -> &[ParsedNodeRef<'a>] {
+        &self.stack
+    }
+
+    /// Byte offset in the source of the next character that will be read.
+    fn offset(&self) -> usize {
+        self.src.len() - self.iter.as_str().len()
+    }
+
+    /// Read until the next [`Token`]. Returns [`None`] when the source ended.
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, SkimError> {
+        // When is self-closing, node is pushed, matched, then removed.
+        if self.pending_closes > 0 {
+            self.pending_closes -= 1;
+            return Ok(self.stack.pop().map(Token::Close))
+        }
+
+        if self.options.require_prolog && self.offset() == 0 {
+            self.check_prolog()?;
+            // The BOM is not part of the text
+            if self.src.starts_with('\u{FEFF}') {
+                self.iter.next();
             }
-            // Switch from writing to attr.name -> writing to attr.value
-            '=' => {
-                // = Only allowed to separate AttrName and AttrVal, when writing AttrVal, and text Content
-                // WriteTo::AttrVal and WriteTo::Content will never be reached here
-                if node_type == NodeType::Opening && writing_to == WriteTo::AttrName {
-                    writing_to = WriteTo::AttrVal;
+        }
+
+        loop {
+            let remaining = self.iter.as_str();
+
+            if remaining.is_empty() {
+                /* There should be no ParsedNodes left in the stack at this point.
+                   If there is, it means the xml is not written properly */
+                return if !self.stack.is_empty() {
+                    Err(SkimError::UnclosedNode)
+                } else if self.options.require_root_element && !self.found_element {
+                    Err(SkimError::NoRootElement)
                 } else {
-                    return Err(SkimError::BadEqSign)
+                    Ok(None)
                 }
             }
-            // Switch from writing to attr.val -> writing to attr.name
-            '"' | '\'' => {
-                // Quotes (single or double) should only be used in AttrVal and text Content
-                match writing_to  {
-                    WriteTo::AttrVal => {
-                        // AttrVal starts at the quote, and should end at the next quote of the same type (single or double)
-                        // Start and end quotes are ignored
-                        let remaining = match iter.as_str().split_once(character) {
-                            Some((attr_val, remaining)) => {
-                                // AttrVal is the slice before the end quote
-                                current_node.attributes.insert(current_attr.name, String::from(attr_val));
-                                remaining
-                            }
-                            None => return Err(SkimError::UnclosedString(current_attr.name, current_node))
-                        };
-                        // Finished reading AttrVal, proceed to next Attr
-                        current_attr = Attr::default();
-                        writing_to = WriteTo::AttrName;
-                        // skip iteration of AttrVal; continue over the rest of the xml_src
-                        iter = remaining.chars();
+
+            // Anything goes in a TextNode (except `<`)
+            if !remaining.starts_with('<') {
+                let (text, remaining) = remaining.split_at(remaining.find('<').unwrap_or(remaining.len()));
+                self.iter = remaining.chars();
+                if self.options.reject_cdata_end_in_text && text.contains("]]>") {
+                    return Err(SkimError::IllegalCharSequence(text.to_string()))
+                }
+                self.check_text_outside_root(text)?;
+                if self.options.ignore_whitespace_text && !self.keep_whitespace_text && text.chars().all(char::is_whitespace) && !self.preserves_space() {
+                    continue
+                }
+
+                let text = match self.decode_entities(text)? {
+                    Cow::Borrowed(text) => self.apply_text_mode(text),
+                    Cow::Owned(text) => Cow::Owned(self.apply_text_mode(&text).into_owned())
+                };
+                if !text.is_empty() {
+                    return Ok(Some(Token::Text(text)))
+                }
+                continue
+            }
+
+            /* Check if the next 3 characters are !-- to initiate a comment.
+               Save a slice of the remaining characters after !-- */
+            if let Some(remaining) = remaining.strip_prefix("<!--") {
+                if !self.options.allow_comments {
+                    return Err(SkimError::CommentsNotAllowed(self.offset()))
+                }
+                /* Look for the end-of-comment delimeter (-->) */
+                let (content, remaining) = match remaining.split_once("-->") {
+                    Some(split) => split,
+                    // The rest of xml_src is the comment
+                    None => return Err(SkimError::UnclosedComment(remaining.to_string()))
+                };
+
+                // skip the comment and its delimeters
+                self.iter = remaining.chars();
+                if self.options.emit_comments {
+                    return Ok(Some(Token::Comment(content)))
+                }
+            }
+            // CDATA is text where entities are not decoded and `<` is allowed
+            else if let Some(remaining) = remaining.strip_prefix("<![CDATA[") {
+                let (content, remaining) = match remaining.split_once("]]>") {
+                    Some(split) => split,
+                    None => return Err(SkimError::UnclosedCData(remaining.to_string()))
+                };
+
+                // skip the CDATA and its delimeters
+                self.iter = remaining.chars();
+                self.check_text_outside_root(content)?;
+                let text = self.apply_text_mode(content);
+                if !text.is_empty() {
+                    return Ok(Some(Token::Text(text)))
+                }
+            }
+            // Other declarations (`<!DOCTYPE ...>`, `<!ENTITY ...>`, or unknown ones) are skipped like comments
+            else if let Some(remaining) = remaining.strip_prefix("<!") {
+                let end = match declaration_end(remaining) {
+                    Some(end) => end,
+                    // The rest of xml_src is the declaration
+                    None => return Err(SkimError::UnclosedComment(remaining.to_string()))
+                };
+
+                // skip the declaration and its `>`
+                self.iter = remaining[end + 1..].chars();
+            }
+            // Processing instructions and the prolog <?xml?> are skipped like comments
+            else if let Some(remaining) = remaining.strip_prefix("<?") {
+                // Question-mark (?) is used as a delimiter, look for the ending one
+                let (content, remaining) = match remaining.split_once("?>") {
+                    Some(split) => split,
+                    // The rest of xml_src is the comment
+                    None => return Err(SkimError::UnclosedComment(remaining.to_string()))
+                };
+
+                // skip the prolog and its delimeter
+                self.iter = remaining.chars();
+                if self.options.emit_processing_instructions {
+                    let (target, data) = content.split_once(char::is_whitespace).unwrap_or((content, ""));
+                    if target != "xml" {
+                        return Ok(Some(Token::ProcessingInstruction(target, data.trim_start())))
                     }
-                    // WriteTo::Content will never be reached here
-                    _ => return Err(SkimError::BadQuote)
+                }
+            } else {
+                // skip the `<`
+                self.iter.next();
+                if let Some(token) = self.read_tag()? {
+                    self.found_element |= !self.stack.is_empty();
+                    return Ok(Some(token))
                 }
             }
+        }
+    }
+
+    /// Check the XML declaration at the start of the source, for [`SkimOptions::require_prolog`].
+    fn check_prolog(&self) -> Result<(), SkimError> {
+        let src = self.src.strip_prefix('\u{FEFF}').unwrap_or(self.src);
+        let declaration = xml_declaration(src).ok_or(SkimError::MissingProlog)?;
+        match declaration_value(declaration, "version") {
+            Some("1.0" | "1.1") => Ok(()),
+            Some(version) => Err(SkimError::UnsupportedVersion(version.to_string())),
+            // The version is required
+            None => Err(SkimError::MissingProlog)
+        }
+    }
+
+    /// Check that text with something other than whitespace is inside the root element, for [`SkimOptions::reject_text_outside_root`].
+    fn check_text_outside_root(&self, text: &str) -> Result<(), SkimError> {
+        if self.options.reject_text_outside_root && self.stack.is_empty() && !text.chars().all(char::is_whitespace) {
+            return Err(SkimError::TextOutsideRoot(text.to_string()))
+        }
+        Ok(())
+    }
+
+    /// Decode the entities in text or an attribute value, according to [`SkimOptions::strict_entities`].
+    fn decode_entities<'t>(&self, s: &'t str) -> Result<Cow<'t, str>, SkimError> {
+        if self.options.strict_entities {
+            decode_entities_strict(s)
+        } else {
+            Ok(decode_entities(s))
+        }
+    }
+
+    /// Handle the whitespace of a run of text inside the last node of the stack.
+    fn apply_text_mode<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        match self.options.text_mode {
+            TextMode::Preserve => Cow::Borrowed(text),
+            _ if self.preserves_space() => Cow::Borrowed(text),
+            mode => mode.apply(text)
+        }
+    }
+
+    /// Whether the closest node in the stack with an `xml:space` attribute has it set to `preserve`,
+    /// so the whitespace of the text inside the last node must be kept.
+    fn preserves_space(&self) -> bool {
+        match self.xml_space.iter().rev().find(|&&depth| depth <= self.stack.len()) {
+            Some(&depth) => self.stack[depth - 1].attribute("xml:space") == Some("preserve"),
+            None => false
+        }
+    }
+
+    /// Check the name of an attribute once it was read completely: it must be a valid XML name (see [`is_name()`]),
+    /// unless [`SkimOptions::lenient`] is set, and it can't be repeated if [`SkimOptions::reject_duplicate_attributes`] is set.
+    /// **names** are the attribute names that were read before in the same tag (only kept to find duplicates).
+    fn check_attr(&self, names: &mut Vec<&'a str>, name: &'a str) -> Result<(), SkimError> {
+        if !self.options.lenient && !is_name(name) {
+            return Err(SkimError::InvalidAttributeName(name.to_string()))
+        }
+        if self.options.reject_duplicate_attributes {
+            let html_lowercase = self.options.html_lowercase;
+            if names.iter().any(|other| if html_lowercase { other.eq_ignore_ascii_case(name) } else { *other == name }) {
+                let name = if html_lowercase { name.to_ascii_lowercase() } else { name.to_string() };
+                return Err(SkimError::DuplicateAttribute(name))
+            }
+            names.push(name);
+        }
+        Ok(())
+    }
+
+    /// Read an OPENING_NODE or CLOSING_NODE (after its `<`), then Push or Pop from stack.
+    /// 
+    /// The tag and attributes are not copied: the node keeps the slices of the source where they are (see [`ParsedNodeRef`]).
+    /// Returns [`None`] when the tag was skipped (a stray closing tag with [`SkimOptions::lenient`]).
+    fn read_tag(&mut self) -> Result<Option<Token<'a>>, SkimError> {
+        // The node borrows from the source, not from the parser
+        let src = self.src;
+        // Offset of the `<` that was skipped before calling this fn
+        let tag_start = self.offset() - 1;
+        // Byte range of the tag name in the source. Starts empty right after the `<`.
+        let mut tag = self.offset()..self.offset();
+        // Byte range of the name of the attribute being read. Empty when there is no attribute being read.
+        let mut attr_name = 0..0;
+        // Names of the attributes that were already read (see Parser::check_attr())
+        let mut attr_names = vec![];
+        // Byte offset of the `/` of a self-closing node, where its attributes end
+        let mut self_close_at = None;
+        // Creating an OPENING_NODE
+        let mut node_type = NodeType::Opening;
+        // Whether the characters being read are appended to the tag, an attribute name, or an attribute value
+        let mut writing_to = WriteTo::Tag;
+
+        while let Some(character) = self.iter.next() {
+            match character {
+                // A tag can't start before the previous one ended
+                '<' => return Err(SkimError::UnclosedTag(src[tag].to_string())),
+                // A `/` only changes the node type right after the `<`, or after the tag name or an attribute.
+                // After an `=`, it starts a value that is not quoted (e.g. `<a path=/foo/bar>`).
+                '/' if writing_to == WriteTo::AttrVal => return Err(SkimError::UnquotedAttrValue(src[attr_name].to_string())),
+                // Right between characters of an attribute name (e.g. `<a x/y="1">`), it is part of the name,
+                // which is checked like any other name (so it is only accepted with SkimOptions::lenient).
+                '/' if writing_to == WriteTo::AttrName
+                    && !attr_name.is_empty()
+                    && attr_name.end == self.offset() - 1
+                    && self.iter.as_str().starts_with(|c: char| !c.is_whitespace() && c != '>' && c != '=') => attr_name.end = self.offset(),
+                // Change OPENING_NODE to CLOSING_NODE
+                '/' => {
+                    /* Empty tag at this point means this is a regular closing node.
+                       If tag has content it means this is a self-closing node */
+                    if tag.is_empty() {
+                        node_type = NodeType::Closing;
+                    } else {
+                        node_type = NodeType::SelfClosing;
+                        self_close_at.get_or_insert(self.offset() - 1);
+
+                        // Only whitespace can be between the `/` and `>` of a self-closing node.
+                        // If the tag was not closed at all (source ended or another tag started), that is an UnclosedTag.
+                        let remaining = self.iter.as_str();
+                        let trimmed = remaining.trim_start();
+                        if trimmed.is_empty() || trimmed.starts_with(['>', '<']) {
+                            self.iter = trimmed.chars();
+                        } else if self.options.lenient {
+                            // Ignore everything until the `>` (or until the tag is found to be unclosed)
+                            let end = remaining.find(['>', '<']).unwrap_or(remaining.len());
+                            self.iter = remaining[end..].chars();
+                        } else {
+                            return Err(SkimError::ContentAfterSelfClose(src[tag].to_string()))
+                        }
+                    }
+                }
+                // Stop creating the OPENING_NODE or CLOSING_NODE. Then Push or Pop from stack
+                '>' => {
+                    // Check any remaining attribute
+                    if !attr_name.is_empty() {
+                        self.check_attr(&mut attr_names, &src[attr_name])?;
+                    }
+                    let mut name = Cow::Borrowed(&src[tag.clone()]);
+                    if self.options.html_lowercase && name.bytes().any(|b| b.is_ascii_uppercase()) {
+                        name = Cow::Owned(name.to_ascii_lowercase());
+                    }
+                    if node_type == NodeType::Opening && self.options.self_closing_tags.contains(name.as_ref()) {
+                        node_type = NodeType::SelfClosing;
+                    }
+
+                    // Managing XML Stack
+                    return match node_type {
+                        NodeType::Opening | NodeType::SelfClosing => {
+                            let self_closing = node_type == NodeType::SelfClosing;
+                            // The attributes end at the `/` of a self-closing node, or at this `>`
+                            let attributes_end = self_close_at.unwrap_or(self.offset() - 1);
+                            let mut node = ParsedNodeRef {
+                                tag: name,
+                                attributes_src: &src[tag.end..attributes_end],
+                                // The content starts right after the `>`, and a self-closing node has no content
+                                start: tag_start,
+                                inner_start: self.offset(),
+                                inner_end: None,
+                                raw_attributes: self.options.lazy_attributes,
+                                lowercase_attributes: self.options.html_lowercase,
+                                retain_attributes: self.retain_attributes.clone(),
+                            };
+                            if self_closing {
+                                node.inner_end = Some(node.inner_start);
+                            }
+                            // Forget the nodes that were popped
+                            while self.xml_space.last().is_some_and(|&depth| depth > self.stack.len()) {
+                                self.xml_space.pop();
+                            }
+                            if node.attribute("xml:space").is_some() {
+                                self.xml_space.push(self.stack.len() + 1);
+                            }
+                            self.stack.push(node);
+                            self.pending_closes = self_closing as usize;
+                            Ok(Some(Token::Open { self_closing }))
+                        }
+                        // Pop last ParsedNode.
+                        NodeType::Closing => {
+                            // Close the nodes above an open node with the same tag, and then that node.
+                            if self.options.lenient {
+                                if let Some(i) = self.stack.iter().rposition(|node| node.tag == name) {
+                                    // The content of the nodes closed implicitly also ends at this tag
+                                    for node in &mut self.stack[i..] {
+                                        node.inner_end = Some(tag_start);
+                                    }
+                                    // This call pops one, the rest are popped in the next calls
+                                    self.pending_closes = self.stack.len() - i - 1;
+                                    return Ok(Some(Token::Close(self.stack.pop().unwrap())))
+                                }
+                                // No open node has this tag, so the closing tag is skipped
+                                self.stray_closing_tags += 1;
+                                return Ok(None)
+                            }
+
+                            // Tag of last ParsedNode must be identical to the current/CLOSING_NODE
+                            match self.stack.pop() {
+                                Some(mut node) if name == node.tag => {
+                                    node.inner_end = Some(tag_start);
+                                    Ok(Some(Token::Close(node)))
+                                },
+                                Some(node) => Err(SkimError::CantCloseNode(name.into_owned(), Some(Box::new(node.to_owned())), tag_start)),
+                                None => Err(SkimError::CantCloseNode(name.into_owned(), None, tag_start))
+                            }
+                        },
+                    }
+                }
             
-            _ => {
-                match writing_to {
-                    WriteTo::Tag => current_node.tag.push(character),
-                    WriteTo::AttrName => current_attr.name.push(character),
+                // The tag name must be right after the `<` (or `</`): `< a>` is not valid
+                _ if character.is_whitespace() && writing_to == WriteTo::Tag && tag.is_empty() =>
+                    if !self.options.lenient {
+                        return Err(SkimError::WhitespaceBeforeTagName)
+                    },
+                _ if character.is_whitespace() => {
+                    // Whitespace only matters in an OPENING_NODE
+                    if node_type == NodeType::Opening {
+                        match writing_to {
+                            // Switch from writing to tag -> writing to attr_name
+                            WriteTo::Tag if !tag.is_empty() => writing_to = WriteTo::AttrName,
+                            // Case of Boolean Attributes (e.g.: <tag attr1 attr2>)
+                            WriteTo::AttrName => {
+                                // Skip the rest of the whitespace, and look at the char after it without reading it.
+                                // `=` begins the AttrVal of this attribute, and `/` or `>` end the tag (all handled by their own arms).
+                                let remaining = self.iter.as_str().trim_start();
+                                self.iter = remaining.chars();
+                                // Any other char means a different attribute has been reached
+                                if !remaining.starts_with(['=', '/', '>']) && !attr_name.is_empty() {
+                                    // Attr will have an empty value
+                                    self.check_attr(&mut attr_names, &src[attr_name])?;
+                                    attr_name = 0..0;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                // Switch from writing to attr.name -> writing to attr.value
+                '=' => {
+                    // = Only allowed to separate AttrName and AttrVal, when writing AttrVal, and text Content
                     // WriteTo::AttrVal will never be reached here
-                    // WriteTo::Content will never be reached here
-                    _ => panic!("{writing_to:?} should have not been reached")
+                    match (&node_type, &writing_to) {
+                        (NodeType::Opening, WriteTo::AttrName) if !attr_name.is_empty() => {
+                            // The name is complete, so don't wait until the value is read to check it
+                            self.check_attr(&mut attr_names, &src[attr_name.clone()])?;
+                            writing_to = WriteTo::AttrVal
+                        },
+                        // No attribute name before the `=`: `<=foo>`, `<a=b>`, or `<a =b>`
+                        (NodeType::Opening, WriteTo::Tag | WriteTo::AttrName) => return Err(SkimError::UnexpectedEquals(src[tag].to_string())),
+                        _ => return Err(SkimError::BadEqSign)
+                    }
                 }
-            }    
+                // Switch from writing to attr.val -> writing to attr.name
+                '"' | '\'' => {
+                    // Quotes (single or double) should only be used in AttrVal and text Content
+                    match writing_to  {
+                        WriteTo::AttrVal => {
+                            // AttrVal starts at the quote, and should end at the next quote of the same type (single or double)
+                            // Start and end quotes are ignored
+                            let remaining = match self.iter.as_str().split_once(character) {
+                                Some((attr_val, remaining)) => {
+                                    if self.options.reject_markup_in_attributes && attr_val.contains(['<', '>']) {
+                                        return Err(SkimError::SuspiciousAttrValue(src[attr_name].to_string(), attr_val.to_string()))
+                                    }
+                                    // The value is decoded when it is read, but unknown entities are an error now
+                                    if self.options.strict_entities && !self.options.lazy_attributes {
+                                        decode_entities_strict(attr_val)?;
+                                    }
+                                    remaining
+                                }
+                                None => {
+                                    // The node has the attributes before this one
+                                    let node = ParsedNodeRef {
+                                        tag: Cow::Borrowed(&src[tag.clone()]),
+                                        attributes_src: &src[tag.end..attr_name.start],
+                                        start: tag_start,
+                                        inner_start: tag_start,
+                                        inner_end: None,
+                                        raw_attributes: self.options.lazy_attributes,
+                                        lowercase_attributes: self.options.html_lowercase,
+                                        retain_attributes: self.retain_attributes.clone(),
+                                    };
+                                    return Err(SkimError::UnclosedString(src[attr_name].to_string(), Box::new(node.to_owned())))
+                                }
+                            };
+                            // Finished reading AttrVal, proceed to next Attr
+                            attr_name = 0..0;
+                            writing_to = WriteTo::AttrName;
+                            // skip iteration of AttrVal; continue over the rest of the xml_src
+                            self.iter = remaining.chars();
+                        }
+                        _ => return Err(SkimError::BadQuote)
+                    }
+                }
+            
+                _ => {
+                    // The names are slices of the source, which grow with each character
+                    let end = self.offset();
+                    let name = match writing_to {
+                        WriteTo::Tag => &mut tag,
+                        WriteTo::AttrName => &mut attr_name,
+                        // AttrVal must start with a quote
+                        WriteTo::AttrVal => return Err(SkimError::UnquotedAttrValue(src[attr_name].to_string()))
+                    };
+                    if Range::is_empty(name) {
+                        name.start = end - character.len_utf8();
+                    }
+                    name.end = end;
+                }    
+            }
         }
+
+        // Input ended while still reading a tag (e.g. `<tag attr`)
+        Err(SkimError::UnclosedTag(src[tag].to_string()))
     }
+}
 
-    /* There should be no ParsedNodes left in the stack at this point.
-       If there is, it means the xml is not written properly */
-    if stack.len() > 0 {
-        Err(SkimError::UnclosedNode)
-    } else {
-        Ok(())
+
+
+/// Whether **name** is a valid XML name: it starts with a letter, `_`, or `:`,
+/// and the rest can also have digits, `-`, and `.`. Characters that are not ASCII are allowed anywhere.
+/// 
+/// This is looser than the [spec](https://www.w3.org/TR/xml/#NT-Name) for characters that are not ASCII.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let is_start_char = |c: char| c.is_alphabetic() || matches!(c, '_' | ':') || !c.is_ascii();
+    chars.next().is_some_and(is_start_char)
+        && chars.all(|c| is_start_char(c) || c.is_ascii_digit() || matches!(c, '-' | '.'))
+}
+
+/// Find the `>` that ends a declaration like `<!DOCTYPE ...>` (**s** starts after the `<!`).
+/// The `>` can't be inside quotes, or inside the `[...]` of a DOCTYPE (which has more declarations).
+fn declaration_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut brackets = 0usize;
+
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => brackets += 1,
+            (None, ']') => brackets = brackets.saturating_sub(1),
+            (None, '>') if brackets == 0 => return Some(i),
+            _ => {}
+        }
     }
+
+    None
 }
 
+/// The content of the XML declaration (`<?xml version="1.0"?>`) at the start of **text**, between `<?xml` and `?>`, if there is one.
+pub(crate) fn xml_declaration(text: &str) -> Option<&str> {
+    let declaration = text.strip_prefix("<?xml")?;
+    if !declaration.starts_with(char::is_whitespace) && !declaration.starts_with("?>") {
+        return None
+    }
+    Some(&declaration[..declaration.find("?>")?])
+}
 
+/// The value of a pseudo-attribute (like `version` or `encoding`) in the content of an XML declaration (see [`xml_declaration()`]).
+pub(crate) fn declaration_value<'d>(declaration: &'d str, name: &str) -> Option<&'d str> {
+    let value = declaration.split_once(name)?.1
+        .trim_start()
+        .strip_prefix('=')?
+        .trim_start();
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    value[1..].split_once(quote).map(|(value, _)| value)
+}
 
-#[derive(PartialEq, Eq)]
-enum NodeType {
-    /* OPENING_NODEs contain all of a ParsedNode's information like `tag` and `attributes`.
-       Are created when parser encounters the pattern "<"
-       Once an OPENING_NODE is finished reading (because it will encounter a '>'), a ParsedNode will be pushed to the stack */
+/// The kind of tag the parser read. See [`Event::node_type()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeType {
+    /// OPENING_NODEs contain all of a ParsedNode's information like `tag` and `attributes`.
+    /// Are created when parser encounters the pattern `<`.
+    /// Once an OPENING_NODE is finished reading (because it will encounter a `>`), a ParsedNode will be pushed to the stack.
     Opening,
-    /* CLOSING_NODEs represent just a tag.
-       Are created when the parser encounters the pattertn "</".
-       Once a CLOSING_NODE is finished reading, a ParsedNode will be popped from the stack */
+    /// CLOSING_NODEs represent just a tag.
+    /// Are created when the parser encounters the pattern `</`.
+    /// Once a CLOSING_NODE is finished reading, a ParsedNode will be popped from the stack.
     Closing,
-    /* Similar to OPENING_NODEs, but will not be pushed to the stack.
-       Are created when parser encounters the patternn "/" within an OPENING_NODE, but node already has a tag */
+    /// Similar to OPENING_NODEs, but will not stay in the stack.
+    /// Are created when parser encounters the pattern `/` within an OPENING_NODE, but node already has a tag.
     SelfClosing,
-    // Not creating a node, mostly used for ignoring characters or creating text node
-    None
 }
 
+/// What part of a tag the parser is reading. This is internal to the parser and is not exposed.
 #[derive(Debug, PartialEq, Eq)]
 enum WriteTo {
-    Tag, AttrName, AttrVal, Content
+    Tag, AttrName, AttrVal
 }
 
 // struct TextNode {
@@ -254,55 +1774,563 @@ enum WriteTo {
 // }
 
 /// A pair of strings
-#[derive(Default)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Attr {
     pub name: String,
     pub value: String
 }
+impl Attr {
+    /// Create an attribute with a **name** and **value**.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into()
+        }
+    }
+}
 
 
-#[derive(Debug, Default)]
+/// A node copied out of the source, which is what handlers get.
+#[derive(Debug, Default, Clone, Eq)]
 pub struct ParsedNode {
     pub tag: String,
-    pub attributes: HashMap<String, String>
+    pub attributes: HashMap<String, String>,
+    /// The names of the attributes that are written without a value (`<tag attr>`), which are in **attributes** with an empty value.
+    /// See [`Element::is_boolean_attribute()`].
+    pub boolean_attributes: HashSet<String>,
+    /// Where the node is in the source it was parsed from.
+    /// Is [`None`] for nodes that were not parsed from a source (like the ones made with [`ParsedNode::new()`]).
+    pub span: Option<NodeSpan>,
 }
 impl ParsedNode {
+    /// Create a node with a **tag** and no attributes.
+    /// Attributes can be added with [`ParsedNode::with_attr()`] and [`ParsedNode::with_class()`].
+    /// 
+    /// ```
+    /// use xml_skimmer::ParsedNode;
+    /// 
+    /// let node = ParsedNode::new("li").with_attr("id", "first").with_class("item").with_class("done");
+    /// assert_eq!(node.attributes["id"], "first");
+    /// assert_eq!(node.attributes["class"], "item done");
+    /// ```
+    pub fn new(tag: impl Into<String>) -> Self {
+        Self {
+            tag: tag.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Add an attribute to the node, replacing any attribute with the same **name**.
+    pub fn with_attr(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        self.boolean_attributes.remove(&name);
+        self.attributes.insert(name, value.into());
+        self
+    }
+
+    /// Add an attribute without a value (`<tag attr>`), replacing any attribute with the same **name**.
+    pub fn with_boolean_attr(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        self.attributes.insert(name.clone(), String::new());
+        self.boolean_attributes.insert(name);
+        self
+    }
+
+    /// Add a class to the node's `class` attribute (classes are separated by space).
+    pub fn with_class(mut self, class: impl AsRef<str>) -> Self {
+        let class = class.as_ref();
+        self.attributes.entry(String::from("class"))
+            .and_modify(|list| {
+                if !list.is_empty() {
+                    list.push(' ');
+                }
+                list.push_str(class);
+            })
+            .or_insert_with(|| class.to_string());
+        self
+    }
+
+    /// The attributes of the node as `(name, value)` pairs, sorted by name.
+    /// 
+    /// ```
+    /// use xml_skimmer::ParsedNode;
+    /// 
+    /// let node = ParsedNode::new("a").with_attr("href", "/").with_attr("class", "link");
+    /// assert_eq!(node.attributes_sorted(), [("class", "link"), ("href", "/")]);
+    /// ```
+    pub fn attributes_sorted(&self) -> Vec<(&str, &str)> {
+        let mut attributes = self.attributes.iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        attributes.sort_unstable_by_key(|(name, _)| *name);
+        attributes
+    }
+
+    /// The byte offset of the `<` of the opening tag in the source the node was parsed from.
+    /// Use [`line_col()`] to get the line and column.
+    pub fn start(&self) -> Option<usize> {
+        Some(self.span.as_ref()?.start)
+    }
+
+    /// The byte range of the content of the node in the source it was parsed from:
+    /// from right after the `>` of the opening tag, to right before the `<` of the closing tag.
+    /// The range is empty for self-closing nodes.
+    /// 
+    /// Is [`None`] if the closing tag had not been read when the node was copied out of the parser,
+    /// like in handlers called on the opening tag (use [`Skimmer::on_close()`] for those), or if the node was not parsed from a source.
+    pub fn inner_span(&self) -> Option<Range<usize>> {
+        let span = self.span.as_ref()?;
+        Some(span.inner_start..span.inner_end?)
+    }
+
+    /// The markup inside the node, exactly as it is in **xml_src** (the source the node was parsed from).
+    /// See [`ParsedNode::inner_span()`].
+    /// 
+    /// ```
+    /// use xml_skimmer::find_first;
+    /// 
+    /// let xml_src = "<p>Some <b>bold</b> text</p>";
+    /// let node = find_first(xml_src, "p").unwrap().unwrap();
+    /// assert_eq!(node.inner_xml(xml_src), Some("Some <b>bold</b> text"));
+    /// ```
+    pub fn inner_xml<'s>(&self, xml_src: &'s str) -> Option<&'s str> {
+        xml_src.get(self.inner_span()?)
+    }
+
+    /// The namespace URI and local name of the node's tag,
+    /// using the `xmlns` declarations of the node and its ancestors in **stack** (the stack can end with the node itself).
+    /// 
+    /// ```
+    /// use xml_skimmer::ParsedNode;
+    /// 
+    /// let stack = [
+    ///     ParsedNode::new("feed").with_attr("xmlns", "http://www.w3.org/2005/Atom"),
+    ///     ParsedNode::new("dc:creator").with_attr("xmlns:dc", "http://purl.org/dc/elements/1.1/"),
+    /// ];
+    /// assert_eq!(stack[1].resolved_name(&stack), (Some("http://purl.org/dc/elements/1.1/".to_string()), "creator".to_string()));
+    /// assert_eq!(stack[0].resolved_name(&stack[..1]), (Some("http://www.w3.org/2005/Atom".to_string()), "feed".to_string()));
+    /// ```
+    pub fn resolved_name(&self, stack: &[ParsedNode]) -> (Option<String>, String) {
+        let (prefix, local_name) = namespace::split_name(&self.tag);
+        let uri = namespace::lookup_prefix(prefix, std::iter::once(self).chain(stack.iter().rev()));
+        (uri.map(str::to_string), local_name.to_string())
+    }
+
+    /// The namespace URI and local name of one of the node's attributes (see [`ParsedNode::resolved_name()`]).
+    /// Attributes without a prefix are not in any namespace (not even the default one).
+    pub fn resolved_attribute_name(&self, name: &str, stack: &[ParsedNode]) -> (Option<String>, String) {
+        match namespace::split_name(name) {
+            (None, "xmlns") => (Some(namespace::XMLNS_NAMESPACE.to_string()), name.to_string()),
+            (None, local_name) => (None, local_name.to_string()),
+            (prefix, local_name) => {
+                let uri = namespace::lookup_prefix(prefix, std::iter::once(self).chain(stack.iter().rev()));
+                (uri.map(str::to_string), local_name.to_string())
+            }
+        }
+    }
+
+    /// The classes in the `class` attribute. See [`Element::class_list()`].
     pub fn class_list(&self) -> HashSet<&str> {
         match self.attributes.get("class") {
-            // Classes are separated by space
-            Some(list) => list.split(' ').collect(),
+            Some(list) => split_classes(list).collect(),
             None => HashSet::new()
         }
     }
 }
+
+/// The classes in the value of a `class` attribute, which are separated by any whitespace.
+/// Empty classes (from repeated or leading whitespace) are skipped.
+/// Used by [`Element::class_list()`] and by [`Selector`]s, so `.a` and `[class~=a]` see the same classes.
+pub(crate) fn split_classes(list: &str) -> impl Iterator<Item = &str> {
+    list.split_ascii_whitespace()
+}
+
+/// Build an array of [`ParsedNode`]s (a stack or path) to match selectors against, with a syntax similar to selectors.
+/// Each node is a tag string, followed by an optional `#id`, any `.class`es, and any `[attr = "value"]`s.
+/// Ids, classes, and attribute names must be Rust identifiers.
+/// 
+/// ```
+/// use xml_skimmer::{node_path, ParsedNode, selector::Selector};
+/// 
+/// let path = node_path!["ul" #menu, "li" .item .active [lang = "en"]];
+/// assert_eq!(path, [
+///     ParsedNode::new("ul").with_attr("id", "menu"),
+///     ParsedNode::new("li").with_class("item").with_class("active").with_attr("lang", "en"),
+/// ]);
+/// assert!("#menu > li.active".parse::<Selector>().unwrap().matches_path(&path));
+/// ```
+#[macro_export]
+macro_rules! node_path {
+    ($( $tag:literal $(# $id:ident)? $(. $class:ident)* $([$attr:ident = $value:literal])* ),* $(,)?) => {
+        [$(
+            $crate::ParsedNode::new($tag)
+                $(.with_attr("id", stringify!($id)))?
+                $(.with_class(stringify!($class)))*
+                $(.with_attr(stringify!($attr), $value))*
+        ),*]
+    };
+}
+
+/// Nodes are equal when they have the same tag and attributes, no matter where they are in the source.
+impl PartialEq for ParsedNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag == other.tag && self.attributes == other.attributes && self.boolean_attributes == other.boolean_attributes
+    }
+}
+
+/// Writes the node as an opening tag, with the attributes sorted by name: `<tag a="1" b="2">`.
+/// 
+/// The alternate form (`{:#}`) colors the output with ANSI escape codes.
 impl Display for ParsedNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<\x1b[92m{tag}\x1b[0m \x1b[36m{attrs:?}\x1b[0m>", tag=self.tag, attrs=self.attributes)
+        if f.alternate() {
+            write!(f, "<\x1b[92m{}\x1b[0m", self.tag)?;
+            for (name, value) in self.attributes_sorted() {
+                write!(f, " \x1b[36m{name}\x1b[0m=\"{value}\"")?;
+            }
+        } else {
+            write!(f, "<{}", self.tag)?;
+            for (name, value) in self.attributes_sorted() {
+                write!(f, " {name}=\"{value}\"")?;
+            }
+        }
+        write!(f, ">")
     }
 }
 
+/// Where a node is in the source it was parsed from, as byte offsets. See [`ParsedNode::span`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSpan {
+    /// The offset of the `<` of the opening tag.
+    pub start: usize,
+    /// The offset right after the `>` of the opening tag, where the content of the node starts.
+    pub inner_start: usize,
+    /// The offset of the `<` of the closing tag, where the content of the node ends.
+    /// Is the same as **inner_start** for self-closing nodes, and [`None`] if the closing tag was not read yet.
+    pub inner_end: Option<usize>,
+}
+
 
-#[derive(Debug)]
+/// The tag and attributes of a node, which is what [`Selector`]s match.
+/// Implemented by [`ParsedNode`] and [`ParsedNodeRef`], so nodes can be matched without copying them out of the source.
+pub trait Element {
+    fn tag(&self) -> &str;
+    /// The value of an attribute, as it is stored in the node (see [`SkimOptions::lazy_attributes`]).
+    fn attribute(&self, name: &str) -> Option<&str>;
+    /// The value of an attribute with its entities decoded.
+    fn attribute_decoded(&self, name: &str) -> Option<Cow<'_, str>>;
+    /// Whether the attribute is written without a value (`<tag attr>`), which is read as an empty value.
+    /// Selectors with a value (like `[attr=""]`) don't match boolean attributes.
+    fn is_boolean_attribute(&self, _name: &str) -> bool {
+        false
+    }
+    /// The classes in the `class` attribute (separated by whitespace).
+    /// An empty `class` attribute has no classes.
+    fn class_list(&self) -> HashSet<&str> {
+        match self.attribute("class") {
+            Some(list) => split_classes(list).collect(),
+            None => HashSet::new()
+        }
+    }
+}
+impl Element for ParsedNode {
+    fn tag(&self) -> &str {
+        &self.tag
+    }
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+    /// The values of a [`ParsedNode`] are already decoded, unless it was parsed with [`SkimOptions::lazy_attributes`].
+    fn attribute_decoded(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.attribute(name).map(Cow::Borrowed)
+    }
+    fn is_boolean_attribute(&self, name: &str) -> bool {
+        self.boolean_attributes.contains(name)
+    }
+}
+/// A tag and its attributes kept outside of this crate (e.g. by another parser),
+/// so that a stack of them can be matched without building [`ParsedNode`]s (see [`Selector::match_path_parts()`]).
+/// The attribute values are taken as already decoded.
+impl Element for (&str, &HashMap<String, String>) {
+    fn tag(&self) -> &str {
+        self.0
+    }
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.1.get(name).map(String::as_str)
+    }
+    fn attribute_decoded(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.attribute(name).map(Cow::Borrowed)
+    }
+}
+
+/// A node that borrows its tag and attributes from the source it was parsed from, instead of copying them.
+/// This is how the parser keeps the stack, so that nodes are only copied (with [`ParsedNodeRef::to_owned()`])
+/// when a handler is called with them.
+/// 
+/// The attributes are read from the source every time they are accessed,
+/// so [`ParsedNode`] is better for nodes that are kept (and is what handlers get).
+/// 
+/// ```
+/// use xml_skimmer::{events, Element, ParsedNode};
+/// 
+/// let mut events = events("<a href='/'><b/></a>");
+/// events.next();
+/// let node = &events.stack()[0];
+/// assert_eq!(node.tag, "a");
+/// assert_eq!(node.attribute("href"), Some("/"));
+/// assert_eq!(node.to_owned(), ParsedNode::new("a").with_attr("href", "/"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParsedNodeRef<'a> {
+    /// Only owned if it was lowercased (see [`SkimOptions::html_lowercase`]).
+    pub tag: Cow<'a, str>,
+    /// The source of the attributes, from the end of the tag name to the `>` (or `/>`). See [`attribute_pairs()`].
+    attributes_src: &'a str,
+    /// Byte offset in the source of the `<` of the opening tag.
+    start: usize,
+    /// Byte offset in the source right after the `>` of the opening tag.
+    inner_start: usize,
+    /// Byte offset in the source of the `<` of the closing tag, once it was read.
+    inner_end: Option<usize>,
+    /// Whether [`ParsedNodeRef::to_owned()`] keeps the entities of the attribute values (see [`SkimOptions::lazy_attributes`]).
+    raw_attributes: bool,
+    /// Whether the attribute names are lowercased when they are read (see [`SkimOptions::html_lowercase`]).
+    lowercase_attributes: bool,
+    /// The only attributes that [`ParsedNodeRef::to_owned()`] copies (see [`SkimOptions::retain_attributes`]).
+    retain_attributes: Option<Arc<HashSet<String>>>,
+}
+impl<'a> ParsedNodeRef<'a> {
+    /// The byte offset of the `<` of the opening tag in the source. Use [`line_col()`] to get the line and column.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The attributes of the node as `(name, value)` pairs, in the order they appear in the source.
+    /// The values are as they are in the source (with their entities). A repeated attribute is given every time it appears.
+    /// Boolean attributes have an empty value.
+    pub fn attributes(&self) -> impl Iterator<Item = (Cow<'a, str>, &'a str)> + '_ {
+        self.attribute_entries().map(|(name, value)| (name, value.unwrap_or_default()))
+    }
+
+    /// Like [`ParsedNodeRef::attributes()`], but boolean attributes have no value.
+    fn attribute_entries(&self) -> impl Iterator<Item = (Cow<'a, str>, Option<&'a str>)> + '_ {
+        attribute_pairs(self.attributes_src).map(|(name, value)| {
+            let name = if self.lowercase_attributes && name.bytes().any(|b| b.is_ascii_uppercase()) {
+                Cow::Owned(name.to_ascii_lowercase())
+            } else {
+                Cow::Borrowed(name)
+            };
+            (name, value)
+        })
+    }
+
+    /// Copy the node out of the source. The value of a repeated attribute is the last one, like in the parser.
+    /// Only the attributes in [`SkimOptions::retain_attributes`] are copied, if it was set.
+    pub fn to_owned(&self) -> ParsedNode {
+        let mut attributes = HashMap::new();
+        let mut boolean_attributes = HashSet::new();
+        let retained = self.attribute_entries()
+            .filter(|(name, _)| self.retain_attributes.as_ref().is_none_or(|retain| retain.contains(name.as_ref())));
+        for (name, value) in retained {
+            let name = name.into_owned();
+            let value = match value {
+                Some(value) => {
+                    boolean_attributes.remove(&name);
+                    if self.raw_attributes { value.to_string() } else { decode_entities(value).into_owned() }
+                },
+                None => {
+                    boolean_attributes.insert(name.clone());
+                    String::new()
+                }
+            };
+            attributes.insert(name, value);
+        }
+
+        ParsedNode {
+            tag: self.tag.to_string(),
+            attributes,
+            boolean_attributes,
+            span: Some(NodeSpan { start: self.start, inner_start: self.inner_start, inner_end: self.inner_end }),
+        }
+    }
+}
+impl Element for ParsedNodeRef<'_> {
+    fn tag(&self) -> &str {
+        &self.tag
+    }
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes()
+            .filter(|(attr_name, _)| attr_name == name)
+            .last()
+            .map(|(_, value)| value)
+    }
+    fn attribute_decoded(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.attribute(name).map(decode_entities)
+    }
+    fn is_boolean_attribute(&self, name: &str) -> bool {
+        self.attribute_entries()
+            .filter(|(attr_name, _)| attr_name == name)
+            .last()
+            .is_some_and(|(_, value)| value.is_none())
+    }
+}
+
+/// The `(name, value)` pairs of the attributes in **s**, the source of a tag between its name and its end
+/// (which was already checked by the parser). Boolean attributes have no value.
+fn attribute_pairs(s: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None
+        }
+
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+        let (name, after_name) = rest.split_at(name_end);
+        let after_name = after_name.trim_start();
+        match after_name.strip_prefix('=') {
+            Some(value) => {
+                // The value is in quotes, which are 1 byte
+                let value = value.trim_start();
+                let quote = value.chars().next()?;
+                let (value, after_value) = value[1..].split_once(quote)?;
+                rest = after_value;
+                Some((name, Some(value)))
+            }
+            None => {
+                rest = after_name;
+                Some((name, None))
+            }
+        }
+    })
+}
+
+
+#[derive(Debug, PartialEq)]
 pub enum SkimError {
     BadQuote,
     UnclosedNode,
+    /// The source ended (or another tag started) before the `>` of a tag was found.
+    /// Contains the tag name that was read (could be incomplete).
+    UnclosedTag(String),
     UnclosedComment(String),
+    /// Contains the rest of the source after `<![CDATA[`.
+    UnclosedCData(String),
     /// Contains [`Attr`]::name and [`ParsedNode`] that contains the [`Attr`].
-    UnclosedString(String, ParsedNode),
-    /// Conitans the attempted closing tag `</tag>` and the last [`ParsedNode`] in the stack.
-    CantCloseNode(String, Option<ParsedNode>),
+    /// The node is boxed since [`ParsedNode::span`] was added, to keep the error small.
+    UnclosedString(String, Box<ParsedNode>),
+    /// Conitans the attempted closing tag `</tag>`, the last [`ParsedNode`] in the stack,
+    /// and the byte offset of the `<` of the closing tag (see [`line_col()`]).
+    /// The position of the open node is [`ParsedNode::start()`].
+    /// The node is boxed since [`ParsedNode::span`] was added, to keep the error small.
+    CantCloseNode(String, Option<Box<ParsedNode>>, usize),
     BadEqSign,
+    /// An `=` was found where there is no attribute name before it,
+    /// like before or right after the tag name (`<=foo>`, `<a=b>`, `<a =b>`).
+    /// Contains the tag name that was read (could be empty).
+    UnexpectedEquals(String),
+    /// There is whitespace between the `<` (or `</`) and the tag name (e.g. `< a>`).
+    /// With [`SkimOptions::lenient`] the whitespace is ignored instead.
+    WhitespaceBeforeTagName,
+    /// Something other than whitespace was found between the `/` and `>` of a self-closing node (e.g. `<a/ b>`).
+    /// Contains the tag name. With [`SkimOptions::lenient`] those characters are ignored instead.
+    ContentAfterSelfClose(String),
+    /// An `&` in text or an attribute value does not start a predefined entity or character reference.
+    /// Contains the entity (e.g. `&foo;`), or just `&`. Only with [`SkimOptions::strict_entities`].
+    UnknownEntity(String),
+    /// A node has the same attribute more than once. Contains the attribute name.
+    /// Only with [`SkimOptions::reject_duplicate_attributes`].
+    DuplicateAttribute(String),
+    /// The value of an attribute has a `<` or `>`, so its closing quote was probably missing.
+    /// Contains [`Attr`]::name and the value as it was read. Only with [`SkimOptions::reject_markup_in_attributes`].
+    SuspiciousAttrValue(String, String),
+    /// Text has the sequence `]]>` (which can only end a CDATA section). Contains the text as it was read.
+    /// Only with [`SkimOptions::reject_cdata_end_in_text`].
+    IllegalCharSequence(String),
+    /// There is text other than whitespace before or after the root element. Contains the text as it was read.
+    /// Only with [`SkimOptions::reject_text_outside_root`].
+    TextOutsideRoot(String),
+    /// The value of an attribute did not start with a quote (single or double).
+    /// Contains [`Attr`]::name.
+    UnquotedAttrValue(String),
+    /// An attribute name is not a valid XML name, like one that starts with a digit (`<a 1x="y">`) or has a symbol (`<a b$="y">`).
+    /// Contains the name. With [`SkimOptions::lenient`] the attribute is accepted instead.
+    InvalidAttributeName(String),
+    /// A comment was found, and [`SkimOptions::allow_comments`] is `false`.
+    /// Contains the byte offset of the `<!--` (see [`line_col()`]).
+    CommentsNotAllowed(usize),
+    /// The document has no elements. Only with [`SkimOptions::require_root_element`].
+    NoRootElement,
+    /// The source doesn't start with an XML declaration, or the declaration has no `version`.
+    /// Only with [`SkimOptions::require_prolog`].
+    MissingProlog,
+    /// The XML declaration has a version other than `1.0` or `1.1`. Contains the version.
+    /// Only with [`SkimOptions::require_prolog`].
+    UnsupportedVersion(String),
+    /// The encoding declared in the prolog is not the one of the bytes (from their BOM, or what they look like).
+    /// Contains the declared encoding. See [`encoding::decode()`].
+    EncodingMismatch(String),
+    /// The bytes are not valid in their encoding. Contains the byte offset of the first invalid byte.
+    /// See [`encoding::decode()`].
+    InvalidEncoding(usize),
+    /// A selector of a handler could not be parsed.
+    BadSelector(SelectorParseError),
+    /// A matched node could not be converted with [`FromNode`] (in [`find_all_as()`]).
+    FromNode(FromNodeError),
 }
 impl Display for SkimError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::BadQuote => write!(f, "Quotes (single or double) not supposed to be here!"),
             Self::UnclosedNode => write!(f, "One or more Nodes were not closed"),
+            Self::UnclosedTag(tag) => write!(f, "Reached the end while reading tag <{tag}"),
             Self::UnclosedComment(content) => write!(f, "Unclosed comment: -> {content}"),
+            Self::UnclosedCData(content) => write!(f, "Unclosed CDATA: -> {content}"),
             Self::UnclosedString(attr_name, node) => write!(f, "Missing closing quote (single or double) of attribute {attr_name} in node {node} (perhaps wrong quote was used to close)"),
-            Self::CantCloseNode(closing_tag, Some(last_node)) => write!(f, "Rogue Closing_Node <{closing_tag}>, last ParsedNode is <{last_node}>"),
-            Self::CantCloseNode(closing_tag, None) => write!(f, "Rogue Closing_Node <{closing_tag}>"),
+            Self::CantCloseNode(closing_tag, Some(last_node), offset) => match last_node.start() {
+                Some(start) => write!(f, "Rogue Closing_Node <{closing_tag}> at byte {offset}, last ParsedNode is <{last_node}> at byte {start}"),
+                None => write!(f, "Rogue Closing_Node <{closing_tag}> at byte {offset}, last ParsedNode is <{last_node}>"),
+            },
+            Self::CantCloseNode(closing_tag, None, offset) => write!(f, "Rogue Closing_Node <{closing_tag}> at byte {offset}"),
             Self::BadEqSign => write!(f, "Equal_Sign (=) not supposed to be here!"),
+            Self::UnexpectedEquals(tag) => write!(f, "Equal_Sign (=) without an attribute name in tag <{tag}"),
+            Self::WhitespaceBeforeTagName => write!(f, "The tag name must be right after < or </, without whitespace"),
+            Self::ContentAfterSelfClose(tag) => write!(f, "Only whitespace can be between the / and > of self-closing node <{tag}"),
+            Self::UnknownEntity(entity) => write!(f, "Unknown entity {entity}"),
+            Self::DuplicateAttribute(attr_name) => write!(f, "Attribute {attr_name} is repeated in the same node"),
+            Self::SuspiciousAttrValue(attr_name, value) => write!(f, "Value {value:?} of attribute {attr_name} has markup in it (perhaps its closing quote is missing)"),
+            Self::IllegalCharSequence(text) => write!(f, "Text {text:?} has ]]> outside of a CDATA section"),
+            Self::TextOutsideRoot(text) => write!(f, "Text {text:?} is outside of the root element"),
+            Self::UnquotedAttrValue(attr_name) => write!(f, "Value of attribute {attr_name} must be in quotes (single or double)"),
+            Self::InvalidAttributeName(attr_name) => write!(f, "{attr_name} is not a valid attribute name"),
+            Self::CommentsNotAllowed(offset) => write!(f, "Comments are not allowed, found one at byte {offset}"),
+            Self::NoRootElement => write!(f, "The document has no root element"),
+            Self::MissingProlog => write!(f, "The document must start with an XML declaration with a version (<?xml version=\"1.0\"?>)"),
+            Self::UnsupportedVersion(version) => write!(f, "XML version {version} is not supported (only 1.0 and 1.1)"),
+            Self::EncodingMismatch(declared) => write!(f, "The document declares encoding {declared}, but it is not encoded with it"),
+            Self::InvalidEncoding(offset) => write!(f, "Invalid encoded character at byte {offset}"),
+            Self::BadSelector(error) => write!(f, "Could not parse selector: {error:?}"),
+            Self::FromNode(error) => write!(f, "Could not convert node: {error}"),
+        }
+    }
+}
+
+/// Why a node could not be converted with [`FromNode`].
+#[derive(Debug, PartialEq)]
+pub enum FromNodeError {
+    /// The node doesn't have an attribute that is required. Contains the attribute name.
+    MissingAttribute(String),
+    /// The value of an attribute could not be converted. Contains the attribute name and its value.
+    BadAttribute(String, String),
+    /// Any other reason, described by the message.
+    Other(String),
+}
+impl Display for FromNodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingAttribute(attr_name) => write!(f, "Missing attribute {attr_name}"),
+            Self::BadAttribute(attr_name, value) => write!(f, "Bad value {value:?} of attribute {attr_name}"),
+            Self::Other(message) => write!(f, "{message}"),
         }
     }
 }
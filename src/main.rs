@@ -0,0 +1,37 @@
+use std::process::ExitCode;
+use xml_skimmer::{ParsedNode, PrintConfig, Skimmer};
+
+/// Print the nodes of an XML file that match a selector.
+///
+/// Usage: `xml-skimmer [file] [selector]`.
+/// Uses `tests/sample.xml` and `tag` when they are not given.
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| String::from("tests/sample.xml"));
+    let selector = args.next().unwrap_or_else(|| String::from("tag"));
+
+    let xml_src = match std::fs::read_to_string(&path) {
+        Ok(xml_src) => xml_src,
+        Err(error) => {
+            eprintln!("Could not read {path}: {error}");
+            return ExitCode::FAILURE
+        }
+    };
+
+    let mut skimmer = Skimmer::new();
+    if let Err(error) = skimmer.on_with_stack(&selector, |stack: &[ParsedNode]| {
+        // Indent the node by its depth
+        println!("{}{:#}", " ".repeat(PrintConfig::DEFAULT_INDENT * (stack.len() - 1)), stack.last().unwrap());
+    }) {
+        eprintln!("Bad selector {selector:?}: {error:?}");
+        return ExitCode::FAILURE
+    }
+
+    match skimmer.skim(&xml_src) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Could not parse {path}: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Resolve the namespaces of elements and attributes from the `xmlns` declarations of their ancestors.
+//!
+//! The parser doesn't resolve namespaces, it keeps names as they are in the source (e.g. `atom:entry`).
+//! Use [`ParsedNode::resolved_name()`](crate::ParsedNode::resolved_name) with the stack of a matched node,
+//! or a [`Namespace`](crate::selector::Namespace) in a selector (`atom|entry`).
+use crate::Element;
+
+/// The namespace bound to the `xml` prefix (e.g. in `xml:lang`). It doesn't need to be declared.
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+/// The namespace of the `xmlns` attributes that declare namespaces.
+pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+/// Split a qualified name into its prefix (if any) and local name: `atom:entry` is `(Some("atom"), "entry")`.
+pub fn split_name(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((prefix, local_name)) => (Some(prefix), local_name),
+        None => (None, name)
+    }
+}
+
+/// Find the namespace URI bound to **prefix** (or the default namespace if it is [`None`])
+/// by the `xmlns:prefix` (or `xmlns`) attributes of **nodes**, from the nearest node to the farthest.
+///
+/// Returns [`None`] if the prefix is not declared,
+/// or for the default namespace if it was undeclared with `xmlns=""`.
+///
+/// ```
+/// use xml_skimmer::{ParsedNode, namespace::lookup_prefix};
+///
+/// let stack = [
+///     ParsedNode::new("feed").with_attr("xmlns", "http://www.w3.org/2005/Atom"),
+///     ParsedNode::new("entry"),
+/// ];
+/// assert_eq!(lookup_prefix(None, stack.iter().rev()), Some("http://www.w3.org/2005/Atom"));
+/// assert_eq!(lookup_prefix(Some("dc"), stack.iter().rev()), None);
+/// ```
+pub fn lookup_prefix<'a, N: Element + 'a>(prefix: Option<&str>, nodes: impl IntoIterator<Item = &'a N>) -> Option<&'a str> {
+    let attribute = match prefix {
+        Some("xml") => return Some(XML_NAMESPACE),
+        Some("xmlns") => return Some(XMLNS_NAMESPACE),
+        Some(prefix) => format!("xmlns:{prefix}"),
+        None => String::from("xmlns")
+    };
+
+    nodes.into_iter()
+        .find_map(|node| node.attribute(&attribute))
+        // `xmlns=""` means that there is no default namespace
+        .filter(|uri| !uri.is_empty())
+}
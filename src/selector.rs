@@ -1,13 +1,16 @@
-use std::{str::FromStr, collections::{HashMap, HashSet}};
-use crate::ParsedNode;
+use std::{str::{FromStr, Chars}, collections::{HashMap, HashSet}};
+use crate::{namespace, Element, ParsedNode, SkimError};
 
 
 /// Parses a string where a type that can be parsed is separated by commas.
-/// Ignores commas inside **strings** (delimited by single `'` or double `"` quotes).
+/// Ignores commas inside **strings** (delimited by single `'` or double `"` quotes, where `\` escapes the next character),
+/// and inside parenthesis (e.g. `:is(.a, .b)`).
 /// Also accepts 1 end trailing comma.
 /// 
 /// When matching with a [`ParsedNode`], if any of the inner selectors match the node,
 /// then [`CommaSeparated::match_node()`] returns `true`.
+/// This is how to match a node that has *any* of several classes: `.a, .b` (or `div.a, div.b` for a specific tag),
+/// while `.a.b` matches a node that has *all* of them.
 /// 
 /// ## Example
 /// 
@@ -32,17 +35,44 @@ use crate::ParsedNode;
 ///         .. Default::default() },
 /// ])));
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 pub struct CommaSeparated<T: FromStr>(pub Vec<T>);
 impl CommaSeparated<Selector> {
-    pub fn match_node(&self, stack: &[ParsedNode]) -> bool {
-        for selector in &self.0 {
-            if selector.match_node(stack) {
-                return true
-            }
+    /// Whether any of the inner selectors matches the last node of the **stack**.
+    pub fn match_node<N: Element + Clone>(&self, stack: &[N]) -> bool {
+        self.matching(stack).next().is_some()
+    }
+
+    /// The index of the first inner selector that matches the last node of the **stack**.
+    pub fn match_first<N: Element + Clone>(&self, stack: &[N]) -> Option<usize> {
+        self.matching(stack).next()
+    }
+
+    /// The indices of all the inner selectors that match the last node of the **stack**, in order.
+    /// 
+    /// ```
+    /// use xml_skimmer::{ParsedNode, selector::{CommaSeparated, Selector}};
+    /// 
+    /// let selector = "li, .b, #c".parse::<CommaSeparated<Selector>>().unwrap();
+    /// assert_eq!(selector.match_all(&[ParsedNode::new("li").with_attr("id", "c")]), [0, 2]);
+    /// ```
+    pub fn match_all<N: Element + Clone>(&self, stack: &[N]) -> Vec<usize> {
+        self.matching(stack).collect()
+    }
+
+    /// Call [`Selector::resolve_namespaces()`] on every inner selector.
+    pub fn resolve_namespaces(&mut self, namespaces: &HashMap<String, String>) {
+        for selector in &mut self.0 {
+            selector.resolve_namespaces(namespaces);
         }
+    }
 
-        false
+    /// Lazily find the indices of the inner selectors that match, so that callers can stop at the first one.
+    fn matching<'s, N: Element + Clone>(&'s self, stack: &'s [N]) -> impl Iterator<Item = usize> + 's {
+        self.0.iter()
+            .enumerate()
+            .filter(|(_, selector)| selector.match_node(stack))
+            .map(|(index, _)| index)
     }
 }
 impl<T: FromStr> FromStr for CommaSeparated<T> {
@@ -51,30 +81,35 @@ impl<T: FromStr> FromStr for CommaSeparated<T> {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut rtrn = vec![];
 
+        // Byte index where the current T starts
         let mut start = 0;
-        let mut i = 0;
         let mut string_quote: Option<char> = None;
+        // How many parenthesis are open
+        let mut depth = 0usize;
 
-        for c in s.chars() {
-            i += 1;
-
+        let mut char_indices = s.char_indices();
+        while let Some((i, c)) = char_indices.next() {
             match (c, string_quote) {
                 // Open string with single or double quotes
                 ('\'' | '"', None) => string_quote = Some(c),
+                // Skip the escaped character inside a string (could be a quote)
+                ('\\', Some(_)) => { char_indices.next(); },
                 // String opened with single or double quotes, and it closes with that same quote
                 ('\'', Some('\'')) | ('"', Some('"')) => string_quote = None,
-                // Found a comma, not in string
-                (',', None) => {
-                    // subtract i - 1 to exclude the comma
-                    rtrn.push(T::from_str(&s[start..(i - 1)].trim())?);
-                    start = i;
+                ('(', None) => depth += 1,
+                (')', None) => depth = depth.saturating_sub(1),
+                // Found a comma, not in string or parenthesis
+                (',', None) if depth == 0 => {
+                    // exclude the comma
+                    rtrn.push(T::from_str(s[start..i].trim())?);
+                    start = i + 1;
                 },
                 _ => {}
             }
         }
 
         // See if there is a T after the last comma
-        let s = s[start..i].trim();
+        let s = s[start..].trim();
         if !s.is_empty() {
             rtrn.push(T::from_str(s)?);
         }
@@ -82,12 +117,34 @@ impl<T: FromStr> FromStr for CommaSeparated<T> {
         Ok(Self(rtrn))
     }
 }
+/// Writes the inner values separated by `, `.
+/// 
+/// ```
+/// use xml_skimmer::selector::{CommaSeparated, Selector};
+/// 
+/// let group = "a,b.c  >  d".parse::<CommaSeparated<Selector>>().unwrap();
+/// assert_eq!(group.to_string(), "a, b.c > d");
+/// ```
+impl<T: FromStr + std::fmt::Display> std::fmt::Display for CommaSeparated<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, inner) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{inner}")?;
+        }
+        Ok(())
+    }
+}
 
 
 /// A CSS selector that can be matched against an XML node.
 /// 
 /// Supported tokens are: `tag`, `#id`, `.class`, `[attr]`,
-/// `[attr=val]`, `[attr="val"]` (single or double quotes).
+/// `[attr=val]`, `[attr="val"]` (single or double quotes),
+/// `:not(token)` where **token** is one of the other tokens,
+/// `:is(selectors)` and `:where(selectors)` where **selectors** are comma-separated selectors without combinators,
+/// and the pseudo-classes in [`PseudoClass`] (e.g. `:first-child`).
 /// 
 /// When an **attribute** in the selector has no value (`[attr]`),
 /// it means that when matching whith an XML node
@@ -95,6 +152,7 @@ impl<T: FromStr> FromStr for CommaSeparated<T> {
 /// But when an **attribute** in the selector has an empty value (`[attr=""]`),
 /// it will check if it has that attribute,
 /// and also requires that the XML node specifically has an empty string as the value for that attribute.
+/// Boolean attributes (`<tag attr>`) don't have a value, so they are matched by `[attr]` but not by `[attr=""]`.
 /// 
 /// # Combinators
 /// 
@@ -111,134 +169,569 @@ impl<T: FromStr> FromStr for CommaSeparated<T> {
 /// 
 /// The tag always goes first in the string.
 /// There must only be 1 **tag** and **id** in the string.
+/// Names can contain any non-ASCII character (e.g. `café` or `名前`);
+/// only ASCII punctuation (other than `-` and `_`) is reserved for the selector syntax.
 /// 
 /// **classes** is a [`HashSet`] and `attributes` is a [`HashMap`],
 /// so a class or attribute name must not be found in the string more than once.
 /// **attributes** can have no value `[attr]`, or Some value `[attr=val]`,
 /// where the value can be wrapped in single `'` or double `"` quotes.
+/// Inside quotes, a backslash `\` escapes the next character, so `[title="say \"hi\""]` has the value `say "hi"`,
+/// and `\\` is a single backslash. This is only part of the selector syntax; XML attribute values use entities instead (`&quot;`).
+/// A value that is not quoted can only contain alphanumeric characters, `-`, and `_`;
+/// any other character (like whitespace or `]`) requires quotes.
+/// The name of an **attribute** can contain `.`, `#`, and `:` (e.g. `[xml:lang]`),
+/// which are read literally instead of starting a **class** or **id**.
+/// But it can't contain `[`, which returns [`SelectorParseError::UnclosedBracket`].
+/// 
+/// An attribute can also be compared with a number with `>`, `<`, `>=`, or `<=` (e.g. `[count>5]` or `[price <= 9.99]`),
+/// which is different from the `>` combinator because it is inside the brackets. See [`Comparison`].
+/// 
+/// `[class~=value]` matches nodes that have `value` among the whitespace-separated classes in their `class` attribute,
+/// so it is parsed as the class `.value`. `~=` can't be used with other attributes ([`SelectorParseError::UnknownPrefix`]),
+/// and the value must be a single, non-empty class ([`SelectorParseError::EmptyToken`] or [`SelectorParseError::BadChar`]).
 /// 
 /// See [`SelectorParseError`] for possible errors when parsing from a string.
 #[derive(Debug, Default, PartialEq)]
 pub struct Selector {
     pub tag: Option<String>,
+    /// The namespace of the node, from `prefix|tag`. When it is set, **tag** is matched with the local name of the node. See [`Namespace`].
+    pub namespace: Option<Namespace>,
     pub id: Option<String>,
     pub classes: HashSet<String>,
     pub attributes: HashMap<String, Option<String>>,
+    /// Attributes compared with a number, in the order they were written (`[attr>5]`). See [`Comparison`].
+    pub comparisons: Vec<(String, Comparison, f64)>,
+    /// Simple selectors from `:not()`. The node must match none of them.
+    pub not: Vec<Selector>,
+    /// Pseudo-classes that depend on the siblings of a node, or on where the query started. See [`PseudoClass`].
+    pub pseudo_classes: Vec<PseudoClass>,
+    /// Groups from `:is()`. The node must match at least one selector of each group.
+    pub is: Vec<CommaSeparated<Selector>>,
+    /// Groups from `:where()`. Match like [`is`](Selector::is), but don't add to the [`specificity`](Selector::specificity()).
+    pub where_: Vec<CommaSeparated<Selector>>,
     pub parent: Option<(Box<Selector>, Combinator)>
 }
 impl Selector {
-    pub fn match_node(&self, stack: &[ParsedNode]) -> bool {
-        let mut node_iter = stack.iter().rev();
-        let mut sel_iter = Some(self);
-        let mut combinator = None;
+    /// Whether the last node of the **stack** matches this selector.
+    /// The rest of the stack are the ancestors of that node, which are matched by the parents of this selector.
+    /// 
+    /// [`PseudoClass`]es need to know about the siblings of a node,
+    /// so a selector with them never matches a stack. Use the [`tree`](crate::tree) instead.
+    pub fn match_node<N: Element + Clone>(&self, stack: &[N]) -> bool {
+        // The siblings of the nodes in the stack are not known
+        self.match_path(stack, &|selector, path| {
+            // match_path never calls this with an empty path
+            let node = path.last().unwrap();
+            let lookup_namespace = |prefix: Option<&str>| namespace::lookup_prefix(prefix, path.iter().rev());
+            selector.match_simple(node, &lookup_namespace, &|_| false)
+        }, &|_| vec![])
+    }
 
-        while let Some(selector) = sel_iter {
-            match combinator {
-                // Some ancestor node in the stack has to match
-                Some(Combinator::Descendant) => {
-                    let mut matched = false;
-                    // Try again for every node until one matches
-                    while let Some(node) = node_iter.next() {
-                        if selector.match_simple(node) {
-                            matched = true;
-                            break
-                        }
-                    }
+    /// Same as [`match_node()`](Selector::match_node), with a name that is clearer when the **path** is built by hand
+    /// (e.g. with [`node_path!`](crate::node_path)) instead of being the parser's stack.
+    pub fn matches_path(&self, path: &[ParsedNode]) -> bool {
+        self.match_node(path)
+    }
 
-                    // No node in the stack matched
-                    if !matched {
-                        return false
-                    }
-                },
-                // The directly next node in the stack has to match.
-                // This also happens with the first selector: e.g. "... tag".
-                Some(Combinator::Child) | None =>
-                    match node_iter.next() {
-                        Some(node) =>
-                            if !selector.match_simple(node) {
-                                return false
-                            },
-                        // stack was empty
-                        None => return false
-                    }
-            }
+    /// Same as [`match_node()`](Selector::match_node), for a stack of nodes that is kept by the caller as borrowed tags and attributes.
+    /// 
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xml_skimmer::selector::Selector;
+    /// 
+    /// let attributes = HashMap::from([("class".to_string(), "item".to_string())]);
+    /// let no_attributes = HashMap::new();
+    /// let sel = "ul > li.item".parse::<Selector>().unwrap();
+    /// assert!(sel.match_path_parts(&[("ul", &no_attributes), ("li", &attributes)]));
+    /// assert!(!sel.match_path_parts(&[("ol", &no_attributes), ("li", &attributes)]));
+    /// ```
+    pub fn match_path_parts(&self, parts: &[(&str, &HashMap<String, String>)]) -> bool {
+        self.match_node(parts)
+    }
 
-            sel_iter = match &selector.parent {
-                Some((parent, comb)) => {
-                    combinator = Some(*comb);
-                    Some(&*parent)
+    /// Same as [`match_node()`](Selector::match_node), but on a match tells the index in the **stack** of the node
+    /// that the outermost parent of this selector (the leftmost part, like `ul` in `ul li > a`) matched.
+    /// A selector without parents matches the last node, so its index is `stack.len() - 1`.
+    /// 
+    /// When more than one ancestor could match a descendant combinator, the closest ones are tried first,
+    /// so the index is the deepest where the selector can be anchored.
+    /// 
+    /// ```
+    /// use xml_skimmer::{node_path, selector::Selector};
+    /// 
+    /// let path = node_path!["div", "ul", "li", "a"];
+    /// assert_eq!("a".parse::<Selector>().unwrap().match_node_depth(&path), Some(3));
+    /// assert_eq!("ul a".parse::<Selector>().unwrap().match_node_depth(&path), Some(1));
+    /// assert_eq!("ol a".parse::<Selector>().unwrap().match_node_depth(&path), None);
+    /// ```
+    pub fn match_node_depth<N: Element + Clone>(&self, stack: &[N]) -> Option<usize> {
+        let (node, ancestors) = stack.split_last()?;
+
+        let lookup_namespace = |prefix: Option<&str>| namespace::lookup_prefix(prefix, stack.iter().rev());
+        if !self.match_simple(node, &lookup_namespace, &|_| false) {
+            return None
+        }
+
+        match &self.parent {
+            None => Some(ancestors.len()),
+            Some((parent, Combinator::Child)) => parent.match_node_depth(ancestors),
+            Some((parent, Combinator::Descendant)) => (1..=ancestors.len()).rev()
+                .find_map(|len| parent.match_node_depth(&ancestors[..len])),
+            // The siblings of the nodes in the stack are not known
+            Some(_) => None,
+        }
+    }
+
+    /// Same as [`match_node()`](Selector::match_node), but when the node doesn't match,
+    /// tells the first part of the selector that did not match (in the order they are checked). Useful for debugging selectors.
+    /// 
+    /// ```
+    /// use xml_skimmer::{node_path, selector::{Combinator, MatchFailure, Selector}};
+    /// 
+    /// let sel = "ul > li.item".parse::<Selector>().unwrap();
+    /// assert_eq!(sel.match_explain(&node_path!["ul", "li" .item]), Ok(()));
+    /// assert_eq!(sel.match_explain(&node_path!["ul", "li"]), Err(MatchFailure::MissingClass("item".to_string())));
+    /// assert_eq!(sel.match_explain(&node_path!["ol", "li" .item]), Err(MatchFailure::Combinator {
+    ///     combinator: Combinator::Child,
+    ///     depth: 1,
+    ///     reason: Box::new(MatchFailure::Tag { expected: "ul".to_string(), found: "ol".to_string() })
+    /// }));
+    /// ```
+    pub fn match_explain<N: Element + Clone>(&self, stack: &[N]) -> Result<(), MatchFailure> {
+        let (node, ancestors) = match stack.split_last() {
+            Some(split) => split,
+            None => return Err(MatchFailure::EmptyStack)
+        };
+
+        let lookup_namespace = |prefix: Option<&str>| namespace::lookup_prefix(prefix, stack.iter().rev());
+        self.check_simple(node, &lookup_namespace, &|_| false)
+            .map_err(|mismatch| mismatch.explain(node))?;
+
+        let combinator_failure = |combinator: Combinator, reason: MatchFailure| MatchFailure::Combinator {
+            combinator,
+            depth: ancestors.len(),
+            reason: Box::new(reason)
+        };
+        match &self.parent {
+            None => Ok(()),
+            Some((parent, Combinator::Child)) => parent.match_explain(ancestors)
+                .map_err(|reason| combinator_failure(Combinator::Child, reason)),
+            // The closest ancestor is explained when none of them match
+            Some((parent, Combinator::Descendant)) =>
+                if (1..ancestors.len()).any(|len| parent.match_node(&ancestors[..len])) {
+                    Ok(())
+                } else {
+                    parent.match_explain(ancestors)
+                        .map_err(|reason| combinator_failure(Combinator::Descendant, reason))
                 },
-                // finish
-                None => None
-            }
+            // The siblings of the nodes in the stack are not known
+            Some((_, combinator)) => Err(combinator_failure(*combinator, MatchFailure::UnknownSiblings)),
         }
+    }
+
+    /// Match this selector and its parents against a **path** of nodes,
+    /// where the last node is the one being matched, and the rest are its ancestors.
+    /// 
+    /// **match_simple** matches a single selector (without considering combinators) against the last node of a path
+    /// (the rest of the path is only used to resolve namespaces).
+    /// **previous_siblings** returns the element siblings that come before a node of the path (the nearest first).
+    pub(crate) fn match_path<T: Clone>(&self, path: &[T], match_simple: &impl Fn(&Selector, &[T]) -> bool, previous_siblings: &impl Fn(&T) -> Vec<T>) -> bool {
+        let (node, ancestors) = match path.split_last() {
+            Some(split) => split,
+            // path was empty
+            None => return false
+        };
 
-        true
+        if !match_simple(self, path) {
+            return false
+        }
+
+        match &self.parent {
+            None => true,
+            // The directly next node in the path has to match
+            Some((parent, Combinator::Child)) => parent.match_path(ancestors, match_simple, previous_siblings),
+            // Some ancestor node in the path has to match.
+            // Try again for every node until one matches (along with the parent's own parents).
+            Some((parent, Combinator::Descendant)) => (1..=ancestors.len()).rev()
+                .any(|len| parent.match_path(&ancestors[..len], match_simple, previous_siblings)),
+            // The sibling takes the place of the node in the path, since they have the same ancestors.
+            Some((parent, combinator)) => {
+                // Only the directly previous sibling can match `+`
+                let count = if *combinator == Combinator::NextSibling { 1 } else { usize::MAX };
+                previous_siblings(node).into_iter().take(count).any(|sibling| {
+                    let mut path = ancestors.to_vec();
+                    path.push(sibling);
+                    parent.match_path(&path, match_simple, previous_siblings)
+                })
+            }
+        }
     }
 
     /// Match a single selector without considering combinators.
-    fn match_simple(&self, node: &ParsedNode) -> bool {
-        if let Some(ref tag) = self.tag {
-            if node.tag != *tag {
-                return false
+    /// **lookup_namespace** finds the namespace URI bound to a prefix (see [`namespace::lookup_prefix()`]) where the node is.
+    /// **match_pseudo_class** tells whether the node matches a [`PseudoClass`], since that depends on where the node is.
+    pub(crate) fn match_simple<'n>(
+        &self,
+        node: &impl Element,
+        lookup_namespace: &impl Fn(Option<&str>) -> Option<&'n str>,
+        match_pseudo_class: &impl Fn(&PseudoClass) -> bool
+    ) -> bool {
+        self.check_simple(node, lookup_namespace, match_pseudo_class).is_ok()
+    }
+
+    /// Same as [`match_simple()`](Selector::match_simple), but tells what part of the selector did not match.
+    /// The [`Mismatch`] only borrows from the selector, so nothing is allocated when the node doesn't match.
+    fn check_simple<'n>(
+        &self,
+        node: &impl Element,
+        lookup_namespace: &impl Fn(Option<&str>) -> Option<&'n str>,
+        match_pseudo_class: &impl Fn(&PseudoClass) -> bool
+    ) -> Result<(), Mismatch<'_>> {
+        match &self.namespace {
+            // The whole tag is matched, as it is in the source
+            None => if let Some(ref tag) = self.tag {
+                if node.tag() != tag {
+                    return Err(Mismatch::Tag(tag))
+                }
+            },
+            Some(namespace) => {
+                let (prefix, local_name) = namespace::split_name(node.tag());
+                if let Some(tag) = self.tag.as_ref().filter(|tag| *tag != local_name) {
+                    return Err(Mismatch::Tag(tag))
+                }
+
+                let uri = match namespace {
+                    Namespace::Uri(uri) => uri.as_str(),
+                    Namespace::Prefix(prefix) => match lookup_namespace(Some(prefix)) {
+                        Some(uri) => uri,
+                        None => return Err(Mismatch::Namespace)
+                    }
+                };
+                if lookup_namespace(prefix) != Some(uri) {
+                    return Err(Mismatch::Namespace)
+                }
             }
         }
         
-        match (node.attributes.get("id"), &self.id) {
+        match (node.attribute_decoded("id"), &self.id) {
             // Both node and selector have an id to match
-            (Some(node_id), Some(id)) =>
-                if *node_id != *id {
-                    return false
-                },
+            (Some(node_id), Some(id)) if *node_id != *id => return Err(Mismatch::Id(id)),
             // Node doesn't have id
-            (None, Some(_)) => return false,
+            (None, Some(id)) => return Err(Mismatch::Id(id)),
             _ => {}
         }
             
-        let class_list = node.class_list();
+        // Only look for the classes in the attribute, without collecting them
+        let class_list = node.attribute("class").unwrap_or_default();
 
         for class in self.classes.iter() {
-            if !class_list.contains(class.as_str()) {
-                return false
+            if !crate::split_classes(class_list).any(|node_class| node_class == class) {
+                return Err(Mismatch::Class(class))
             }
         }
 
         for attr in self.attributes.iter() {
             match attr.1 {
                 // [attr = val]
-                Some(attr_val) => match node.attributes.get(attr.0) {
+                Some(attr_val) => match node.attribute_decoded(attr.0) {
                     Some(node_attr_val) =>
-                        if *node_attr_val != *attr_val {
-                            return false
+                        if *node_attr_val != *attr_val || node.is_boolean_attribute(attr.0) {
+                            return Err(Mismatch::Attribute(attr.0, Some(attr_val)))
                         },
                     // Node does not have attribute
-                    None => return false
+                    None => return Err(Mismatch::Attribute(attr.0, Some(attr_val)))
                 },
                 // [attr]
                 None =>
-                    if !node.attributes.contains_key(attr.0) {
-                        return false
+                    if node.attribute(attr.0).is_none() {
+                        return Err(Mismatch::Attribute(attr.0, None))
                     }
             }
         }
 
-        true
+        for (name, comparison, number) in &self.comparisons {
+            let value = node.attribute_decoded(name)
+                .and_then(|value| value.trim().parse::<f64>().ok());
+            if !value.is_some_and(|value| comparison.compare(value, *number)) {
+                return Err(Mismatch::Comparison(name, *comparison, *number))
+            }
+        }
+
+        for (i, negated) in self.not.iter().enumerate() {
+            if negated.match_simple(node, lookup_namespace, match_pseudo_class) {
+                return Err(Mismatch::Not(i))
+            }
+        }
+
+        if let Some(pseudo_class) = self.pseudo_classes.iter().find(|pseudo_class| !match_pseudo_class(pseudo_class)) {
+            return Err(Mismatch::PseudoClass(pseudo_class))
+        }
+
+        // :is() and :where(), any of the selectors in each group must match
+        let group_matches = |group: &CommaSeparated<Selector>| group.0.iter()
+            .any(|sel| sel.match_simple(node, lookup_namespace, match_pseudo_class));
+        if let Some(i) = self.is.iter().position(|group| !group_matches(group)) {
+            return Err(Mismatch::Is(i))
+        }
+        if let Some(i) = self.where_.iter().position(|group| !group_matches(group)) {
+            return Err(Mismatch::Where(i))
+        }
+        Ok(())
+    }
+
+    /// How specific this selector is, as the count of `(ids, classes, tags)`, including its parents.
+    /// Classes also count attributes and pseudo-classes.
+    /// 
+    /// `:not()` and `:is()` count as their most specific argument, and `:where()` counts as nothing.
+    /// 
+    /// ```
+    /// use xml_skimmer::selector::Selector;
+    /// 
+    /// let sel = "ul > li#first.item:is(.a, #b)".parse::<Selector>().unwrap();
+    /// assert_eq!(sel.specificity(), (2, 1, 2));
+    /// ```
+    pub fn specificity(&self) -> (usize, usize, usize) {
+        let max = |group: &CommaSeparated<Selector>| group.0.iter()
+            .map(Selector::specificity)
+            .max()
+            .unwrap_or_default();
+        let add = |a: (usize, usize, usize), b: (usize, usize, usize)| (a.0 + b.0, a.1 + b.1, a.2 + b.2);
+
+        let mut specificity = (
+            self.id.iter().count(),
+            self.classes.len() + self.attributes.len() + self.comparisons.len() + self.pseudo_classes.len(),
+            self.tag.iter().count()
+        );
+        for negated in &self.not {
+            specificity = add(specificity, negated.specificity());
+        }
+        for group in &self.is {
+            specificity = add(specificity, max(group));
+        }
+        if let Some((parent, _)) = &self.parent {
+            specificity = add(specificity, parent.specificity());
+        }
+        specificity
+    }
+
+    /// Whether this selector has only 1 token (**tag**, **id**, **class**, or **attribute**) and no combinators.
+    fn is_single_token(&self) -> bool {
+        self.parent.is_none()
+        && self.tag.iter().count()
+            + self.id.iter().count()
+            + self.classes.len()
+            + self.attributes.len()
+            + self.comparisons.len()
+            + self.not.len() == 1
+            && self.pseudo_classes.is_empty()
+            && self.is.is_empty()
+            && self.where_.is_empty()
+    }
+
+    /// Replace the [`Namespace::Prefix`]es in this selector (and its parents and arguments) with the URI that **namespaces** maps them to,
+    /// so the selector matches the namespace no matter what prefix the document uses for it.
+    /// Prefixes that are not in **namespaces** are kept.
+    /// 
+    /// ```
+    /// use std::collections::HashMap;
+    /// use xml_skimmer::selector::{Namespace, Selector};
+    /// 
+    /// let mut sel = "atom|entry".parse::<Selector>().unwrap();
+    /// sel.resolve_namespaces(&HashMap::from([("atom".to_string(), "http://www.w3.org/2005/Atom".to_string())]));
+    /// assert_eq!(sel.namespace, Some(Namespace::Uri("http://www.w3.org/2005/Atom".to_string())));
+    /// ```
+    pub fn resolve_namespaces(&mut self, namespaces: &HashMap<String, String>) {
+        if let Some(Namespace::Prefix(prefix)) = &self.namespace {
+            if let Some(uri) = namespaces.get(prefix) {
+                self.namespace = Some(Namespace::Uri(uri.clone()));
+            }
+        }
+
+        for selector in self.not.iter_mut().chain(self.is.iter_mut().chain(&mut self.where_).flat_map(|group| &mut group.0)) {
+            selector.resolve_namespaces(namespaces);
+        }
+        if let Some((parent, _)) = &mut self.parent {
+            parent.resolve_namespaces(namespaces);
+        }
+    }
+
+    /// Whether this selector (or any of its parents or arguments) uses `:scope`,
+    /// which includes selectors that start with a combinator (e.g. `> li`).
+    pub(crate) fn uses_scope(&self) -> bool {
+        let group_uses_scope = |group: &CommaSeparated<Selector>| group.0.iter().any(Selector::uses_scope);
+
+        self.pseudo_classes.contains(&PseudoClass::Scope)
+            || self.not.iter().any(Selector::uses_scope)
+            || self.is.iter().chain(&self.where_).any(group_uses_scope)
+            || self.parent.as_ref().is_some_and(|(parent, _)| parent.uses_scope())
     }
 }
 impl FromStr for Selector {
     type Err = SelectorParseError;
 
-    fn from_str(mut s: &str) -> Result<Self, Self::Err> {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_partial(s).0
+    }
+}
+/// Writes the selector in the syntax it is parsed from, so it can be parsed again into an equal selector.
+/// Classes and attributes are sorted by name, attribute values are always quoted,
+/// and a leading combinator is written with `:scope` (e.g. `> li` is written as `:scope > li`).
+/// 
+/// A [`Namespace::Uri`] can't be written in a selector, so it is written in braces (`{uri}|tag`), which can't be parsed.
+/// 
+/// ```
+/// use xml_skimmer::selector::Selector;
+/// 
+/// let sel = "ul#menu  >  li[lang=en].b.a:not(.x)".parse::<Selector>().unwrap();
+/// assert_eq!(sel.to_string(), r#"ul#menu > li.a.b[lang="en"]:not(.x)"#);
+/// ```
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some((parent, combinator)) = &self.parent {
+            match combinator {
+                Combinator::Child => write!(f, "{parent} > ")?,
+                Combinator::Descendant => write!(f, "{parent} ")?,
+                Combinator::NextSibling => write!(f, "{parent} + ")?,
+                Combinator::SubsequentSibling => write!(f, "{parent} ~ ")?,
+            }
+        }
+
+        match &self.namespace {
+            Some(Namespace::Prefix(prefix)) => write!(f, "{prefix}|")?,
+            Some(Namespace::Uri(uri)) => write!(f, "{{{uri}}}|")?,
+            None => {}
+        }
+        if let Some(tag) = &self.tag {
+            write!(f, "{tag}")?;
+        }
+        if let Some(id) = &self.id {
+            write!(f, "#{id}")?;
+        }
+
+        let mut classes = self.classes.iter().collect::<Vec<_>>();
+        classes.sort();
+        for class in classes {
+            write!(f, ".{class}")?;
+        }
+        let mut attributes = self.attributes.iter().collect::<Vec<_>>();
+        attributes.sort();
+        for (name, value) in attributes {
+            match value {
+                Some(value) => write!(f, "[{name}={}]", QuotedString(value))?,
+                None => write!(f, "[{name}]")?,
+            }
+        }
+        for (name, comparison, number) in &self.comparisons {
+            write!(f, "[{name}{comparison}{number}]")?;
+        }
+
+        for negated in &self.not {
+            write!(f, ":not({negated})")?;
+        }
+        for pseudo_class in &self.pseudo_classes {
+            match pseudo_class {
+                PseudoClass::FirstChild => write!(f, ":first-child")?,
+                PseudoClass::LastChild => write!(f, ":last-child")?,
+                PseudoClass::OnlyChild => write!(f, ":only-child")?,
+                PseudoClass::OnlyOfType => write!(f, ":only-of-type")?,
+                PseudoClass::NthChild(expr) => write!(f, ":nth-child({expr})")?,
+                PseudoClass::NthLastChild(expr) => write!(f, ":nth-last-child({expr})")?,
+                PseudoClass::NthOfType(expr) => write!(f, ":nth-of-type({expr})")?,
+                PseudoClass::NthLastOfType(expr) => write!(f, ":nth-last-of-type({expr})")?,
+                PseudoClass::Scope => write!(f, ":scope")?,
+                PseudoClass::Text(text) => write!(f, ":text({})", QuotedString(text))?,
+                PseudoClass::ExactText(text) => write!(f, ":exact-text({})", QuotedString(text))?,
+                PseudoClass::Leaf => write!(f, ":leaf")?,
+            }
+        }
+        for group in &self.is {
+            write!(f, ":is({group})")?;
+        }
+        for group in &self.where_ {
+            write!(f, ":where({group})")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a string in double quotes, escaping `"` and `\` with a backslash, like strings in selectors.
+struct QuotedString<'s>(&'s str);
+impl std::fmt::Display for QuotedString<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"")?;
+        for character in self.0.chars() {
+            if character == '"' || character == '\\' {
+                write!(f, "\\")?;
+            }
+            write!(f, "{character}")?;
+        }
+        write!(f, "\"")
+    }
+}
+impl Selector {
+    /// Parse a single selector (not a group of selectors separated by commas), to match it many times.
+    /// Same as `s.parse::<Selector>()`.
+    /// 
+    /// ```
+    /// use xml_skimmer::selector::Selector;
+    /// 
+    /// let item = Selector::compile("div.item").unwrap();
+    /// assert!(item.matches_in("<div class='item'/>").unwrap());
+    /// assert!(!item.matches_in("<div class='other'/>").unwrap());
+    /// ```
+    pub fn compile(s: &str) -> Result<Self, SelectorParseError> {
+        s.parse()
+    }
+
+    /// Skim **xml_src** until a node that matches this selector is found. Returns whether there is one.
+    /// 
+    /// Parsing stops at the first match, so errors in the rest of the source are not found.
+    /// A selector that uses `:scope` returns [`SkimError::BadSelector`] (see [`SelectorParseError::NoScope`]).
+    pub fn matches_in(&self, xml_src: &str) -> Result<bool, SkimError> {
+        if self.uses_scope() {
+            return Err(SkimError::BadSelector(SelectorParseError::NoScope))
+        }
+        Ok(crate::find_nth_where(xml_src, |stack| self.match_node(stack), 0, |_| ())?.is_some())
+    }
+
+    /// Same as [`from_str()`](Selector::from_str), but also returns the rest of the string that was not parsed.
+    /// When there is an error, it is the part of the string after the character where the error was found,
+    /// which is useful to point at where parsing stopped.
+    /// 
+    /// ```
+    /// use xml_skimmer::selector::{Selector, SelectorParseError};
+    /// 
+    /// let (result, remaining) = Selector::parse_partial("ul > li..item:first-child");
+    /// assert_eq!(result, Err(SelectorParseError::EmptyToken));
+    /// assert_eq!(remaining, "item:first-child");
+    /// ```
+    pub fn parse_partial(s: &str) -> (Result<Self, SelectorParseError>, &str) {
         if s.is_empty() {
-            return Err(Self::Err::EmptyString)
+            return (Err(SelectorParseError::EmptyString), s)
         }
-        s = s.trim_start();
 
+        let mut chars = s.trim_start().chars();
+        let result = Self::parse_chars(&mut chars);
+        (result, chars.as_str())
+    }
+
+    /// Parse a selector from **chars**, leaving the characters that were not parsed (only when there is an error).
+    fn parse_chars(chars: &mut Chars) -> Result<Self, SelectorParseError> {
         let mut current_sel = Self::default();
 
-        let mut chars = s.chars();
         let mut push_to = PushTo::Tag;
         let mut buf = String::new();
+        // Whether the attribute in the brackets uses `~=` instead of `=`
+        let mut includes = false;
+
+        // A selector that starts with a combinator is relative to the scope of the query,
+        // e.g. `> li` is the same as `:scope > li`.
+        let mut leading_combinator = chars.clone().next().and_then(combinator_of).is_some();
+        if leading_combinator {
+            current_sel.pseudo_classes.push(PseudoClass::Scope);
+        }
 
         /// Assign a string to whatever part of the selector it needs to.
         /// 
@@ -266,7 +759,7 @@ impl FromStr for Selector {
                     } else {
                         return Err(SelectorParseError::EmptyToken)
                     },
-                // When one of these chars is in the attribute: [at#tr] or [at.tr] or [at[tr] 
+                // Attribute names are only pushed by `=` or `]`, so reaching this means the bracket was never closed.
                 PushTo::AttrName => return Err(SelectorParseError::UnclosedBracket)
             }
             Ok(())
@@ -274,6 +767,41 @@ impl FromStr for Selector {
 
         while let Some(character) = chars.next() {
             match character {
+                // `[attr>5]`, `[attr<=5]`, etc.
+                '>' | '<' if push_to == PushTo::AttrName => {
+                    if buf.is_empty() {
+                        return Err(SelectorParseError::EmptyToken)
+                    }
+                    let or_equal = chars.as_str().starts_with('=');
+                    if or_equal {
+                        chars.next();
+                    }
+                    let comparison = match (character, or_equal) {
+                        ('>', false) => Comparison::Greater,
+                        ('>', true) => Comparison::GreaterOrEqual,
+                        ('<', false) => Comparison::Less,
+                        (_, _) => Comparison::LessOrEqual,
+                    };
+
+                    let (number, remaining) = match chars.as_str().split_once(']') {
+                        Some(split) => split,
+                        None => return Err(SelectorParseError::UnclosedBracket)
+                    };
+                    let number = number.trim().parse::<f64>()
+                        .map_err(|_| SelectorParseError::BadNumber)?;
+                    *chars = remaining.chars();
+                    current_sel.comparisons.push((buf, comparison, number));
+
+                    // reset buffers
+                    buf = String::new();
+                    push_to = PushTo::Tag;
+                },
+                // `[class~=value]`, handled by `=`
+                '~' if push_to == PushTo::AttrName && chars.as_str().starts_with('=') => includes = true,
+                // Inside brackets these are part of the attribute name (e.g. `[xml:lang]` or `[data.value]`)
+                '#' | '.' | ':' if push_to == PushTo::AttrName => buf.push(character),
+                // A bracket can't be opened before the previous one was closed: `[at[tr]`
+                '[' if push_to == PushTo::AttrName => return Err(SelectorParseError::UnclosedBracket),
                 '#' | '.' | '[' => {
                     // buf could be empty if its the first char in s, or right after a `]`.
                     push(push_to, &mut current_sel, buf)?;
@@ -284,11 +812,11 @@ impl FromStr for Selector {
                 '=' => match push_to {
                     PushTo::AttrName => {
                         if buf.is_empty() {
-                            return Err(Self::Err::EmptyToken)
+                            return Err(SelectorParseError::EmptyToken)
                         }
                         // skip whitespace before attribute
                         let mut next = None;
-                        while let Some(character) = chars.next() {
+                        for character in chars.by_ref() {
                             if !character.is_whitespace() {
                                 next = Some(character);
                                 break
@@ -300,8 +828,8 @@ impl FromStr for Selector {
                             Some('"') => Some('"'),
                             Some('\'') => Some('\''),
                             // When there is nothing after EqSign '=': [attr=]
-                            Some(']' | '=') => return Err(Self::Err::BadChar),
-
+                            Some(']' | '=') => return Err(SelectorParseError::BadChar),
+                            Some(character) if !is_unquoted_value_char(character) => return Err(SelectorParseError::UnquotedSpecialChar),
                             Some(character) => {
                                 val_buf.push(character);
                                 None
@@ -318,21 +846,29 @@ impl FromStr for Selector {
                                     found_closing_quote = true;
                                     break
                                 }
+                                // A backslash escapes the next character (`\"` or `\\`)
+                                if character == '\\' {
+                                    match chars.next() {
+                                        Some(escaped) => val_buf.push(escaped),
+                                        None => break
+                                    }
+                                    continue
+                                }
                                 val_buf.push(character)
                             }
                             // also find ']'
-                            while let Some(character) = chars.next() {
+                            for character in chars.by_ref() {
                                 if character == ']' {
                                     found_closing_bracket = true;
                                     break
                                 }
                                 if !character.is_whitespace() {
-                                    return Err(Self::Err::BadChar)
+                                    return Err(SelectorParseError::BadChar)
                                 }
                             }
                         } else {
                             // The value is every character until ']' or whitespace
-                            while let Some(character) = chars.next() {
+                            for character in chars.by_ref() {
                                 if character.is_whitespace() {
                                     break
                                 }
@@ -340,55 +876,81 @@ impl FromStr for Selector {
                                     found_closing_bracket = true;
                                     break
                                 }
+                                if !is_unquoted_value_char(character) {
+                                    return Err(SelectorParseError::UnquotedSpecialChar)
+                                }
                                 val_buf.push(character)
                             }
                             // also find ']'
                             if !found_closing_bracket {
-                                while let Some(character) = chars.next() {
+                                for character in chars.by_ref() {
                                     if character == ']' {
                                         found_closing_bracket = true;
                                         break
                                     }
+                                    // More of the value after whitespace: [attr=a b]
                                     if !character.is_whitespace() {
-                                        return Err(Self::Err::BadChar)
+                                        return Err(SelectorParseError::UnquotedSpecialChar)
                                     }
                                 }
                             }
                         }
 
                         if opening_quote.is_some() && !found_closing_quote {
-                            return Err(Self::Err::UnclosedString)
+                            return Err(SelectorParseError::UnclosedString)
                         }
                         if !found_closing_bracket {
-                            return Err(Self::Err::UnclosedBracket)
+                            return Err(SelectorParseError::UnclosedBracket)
                         }
 
-                        current_sel.attributes.insert(buf, Some(val_buf));
+                        if includes {
+                            // Only `[class~=value]` is supported, and it is the same as `.value`
+                            if buf != "class" {
+                                return Err(SelectorParseError::UnknownPrefix)
+                            }
+                            if val_buf.is_empty() {
+                                return Err(SelectorParseError::EmptyToken)
+                            }
+                            // A class can't have whitespace, so it would never match
+                            if val_buf.contains(|c: char| c.is_ascii_whitespace()) {
+                                return Err(SelectorParseError::BadChar)
+                            }
+                            current_sel.classes.insert(val_buf);
+                            includes = false;
+                        } else {
+                            if current_sel.attributes.insert(buf, Some(val_buf)).is_some() {
+                                return Err(SelectorParseError::DuplicateAttr)
+                            }
+                        }
 
                         // reset buffers
                         buf = String::new();
                         push_to = PushTo::Tag;
                     },
-                    _ => return Err(Self::Err::BadChar)
+                    _ => return Err(SelectorParseError::BadChar)
                 },
                 // When attr has no value: [attr]
                 ']' => match push_to {
                     PushTo::AttrName => {
                         if buf.is_empty() {
-                            return Err(Self::Err::EmptyToken)
+                            return Err(SelectorParseError::EmptyToken)
+                        }
+                        if current_sel.attributes.insert(buf, None).is_some() {
+                            return Err(SelectorParseError::DuplicateAttr)
                         }
-                        current_sel.attributes.insert(buf, None);
 
                         // Reset buffers
                         buf = String::new();
                         push_to = PushTo::Tag;
                     },
-                    _ => return Err(Self::Err::BadChar/*(c)*/)
+                    _ => return Err(SelectorParseError::BadChar/*(c)*/)
                 },
                 // Whitespace inside attributes is ignored,
                 // otherwise, it means the next tokens will go to a child selector 
-                _ if character.is_whitespace() =>
+                // (or a leading combinator, which is handled the same way)
+                _ if character.is_whitespace() || leading_combinator =>
                     if push_to != PushTo::AttrName {
+                        leading_combinator = false;
                         // buf could be empty if its the first char in s, or right after a `]`.
                         push(push_to, &mut current_sel, buf)?;
                         // reset buffer
@@ -396,22 +958,19 @@ impl FromStr for Selector {
 
                         // the first char of the next child selector
                         let mut first_c = None;
-                        let mut combinator = Combinator::Descendant;
+                        let mut combinator = combinator_of(character);
                         // Look for the combinator within the whitespace.
                         // If there is only whitespace, combinator is Descendant.
                         // Also parse through the trailing whitespace of the combinator.
-                        while let Some(c) = chars.next() {
-                            match c {
-                                '>' => // Check if a combinator was already found
-                                    if combinator == Combinator::Descendant {
-                                        combinator = Combinator::Child;
-                                    } else {
-                                        // When have this situation: "tag > >..."
-                                        // Combinators cannot be used as prefixes.
-                                        return Err(SelectorParseError::UnknownPrefix)
-                                    },
-                                _ if c.is_whitespace() => {},
-                                _ => {
+                        for c in chars.by_ref() {
+                            match combinator_of(c) {
+                                // Check if a combinator was already found
+                                Some(found) if combinator.is_none() => combinator = Some(found),
+                                // When have this situation: "tag > >..."
+                                // Combinators cannot be used as prefixes.
+                                Some(_) => return Err(SelectorParseError::UnknownPrefix),
+                                None if c.is_whitespace() => {},
+                                None => {
                                     first_c = Some(c);
                                     break
                                 }
@@ -421,24 +980,41 @@ impl FromStr for Selector {
                         let c = match first_c {
                             Some(c) => c,
                             // Selector ends with trailing whitespace
-                            None if combinator == Combinator::Descendant => return Ok(current_sel),
-                            None => return Err(Self::Err::NoOtherSideCombinator)
+                            None if combinator.is_none() => return Ok(current_sel),
+                            None => return Err(SelectorParseError::NoOtherSideCombinator)
                         };
-                        push_to = PushTo::new(c);
-                        if push_to == PushTo::Tag {
-                            buf.push(c)
-                        }
-
                         // Set current selector to parent of a new selector.
                         current_sel = Self {
-                            parent: Some((Box::new(current_sel), combinator)),
+                            parent: Some((Box::new(current_sel), combinator.unwrap_or(Combinator::Descendant))),
                             ..Default::default()
+                        };
+
+                        push_to = PushTo::new(c);
+                        if c == ':' {
+                            parse_pseudo_class(chars, &mut current_sel)?;
+                        } else if push_to == PushTo::Tag {
+                            buf.push(c)
                         }
                     },
+                // Namespace prefix before the tag, e.g. `atom|entry`
+                '|' if push_to == PushTo::Tag && !buf.is_empty() && current_sel.namespace.is_none() => {
+                    current_sel.namespace = Some(Namespace::Prefix(buf));
+                    buf = String::new();
+                },
+                // Pseudo-class, e.g. `:not(.class)`
+                ':' => {
+                    // buf could be empty if its the first char in s, or right after a `]`.
+                    push(push_to, &mut current_sel, buf)?;
+                    // Reset buffers
+                    buf = String::new();
+                    push_to = PushTo::Tag;
+
+                    parse_pseudo_class(chars, &mut current_sel)?;
+                },
                 // Any punct char (except `-` and `_`) is considered a prefix or combinator
                 _ if character.is_ascii_punctuation()
                     && character != '-'
-                    && character != '_' => return Err(Self::Err::UnknownPrefix),
+                    && character != '_' => return Err(SelectorParseError::UnknownPrefix),
                 _ => buf.push(character)
             }
         }
@@ -451,10 +1027,242 @@ impl FromStr for Selector {
 }
 
 
+/// Parse **s** as a [`Selector`] and describe what it matches in words, to understand complex selectors.
+/// 
+/// ```
+/// use xml_skimmer::selector::explain_selector;
+/// 
+/// assert_eq!(
+///     explain_selector("ul#menu > li.active").unwrap(),
+///     "match <li> with class 'active' that is a direct child of <ul> with id 'menu'"
+/// );
+/// ```
+pub fn explain_selector(s: &str) -> Result<String, SelectorParseError> {
+    let mut selector = &s.parse::<Selector>()?;
+    let mut explanation = format!("match {}", describe(selector));
+
+    while let Some((parent, combinator)) = &selector.parent {
+        explanation.push_str(match combinator {
+            Combinator::Child => " that is a direct child of ",
+            Combinator::Descendant => " that is a descendant of ",
+            Combinator::NextSibling => " that directly follows ",
+            Combinator::SubsequentSibling => " that follows ",
+        });
+        explanation.push_str(&describe(parent));
+        selector = parent;
+    }
+
+    Ok(explanation)
+}
+
+/// Describe a single selector (without its parents) for [`explain_selector()`].
+fn describe(selector: &Selector) -> String {
+    let mut parts = vec![];
+
+    match &selector.namespace {
+        Some(Namespace::Prefix(prefix)) => parts.push(format!("in namespace prefix '{prefix}'")),
+        Some(Namespace::Uri(uri)) => parts.push(format!("in namespace '{uri}'")),
+        None => {}
+    }
+
+    if let Some(id) = &selector.id {
+        parts.push(format!("with id '{id}'"));
+    }
+
+    let mut classes = selector.classes.iter().collect::<Vec<_>>();
+    classes.sort();
+    for class in classes {
+        parts.push(format!("with class '{class}'"));
+    }
+
+    let mut attributes = selector.attributes.iter().collect::<Vec<_>>();
+    attributes.sort();
+    for (name, value) in attributes {
+        parts.push(match value {
+            Some(value) => format!("with attribute {name} = '{value}'"),
+            None => format!("with attribute {name}"),
+        });
+    }
+    for (name, comparison, number) in &selector.comparisons {
+        parts.push(format!("with attribute {name} {comparison} {number}"));
+    }
+
+    for negated in &selector.not {
+        parts.push(format!("not {}", describe(negated)));
+    }
+
+    for pseudo in &selector.pseudo_classes {
+        parts.push(match pseudo {
+            PseudoClass::FirstChild => "that is the first child".to_string(),
+            PseudoClass::LastChild => "that is the last child".to_string(),
+            PseudoClass::OnlyChild => "that is the only child".to_string(),
+            PseudoClass::OnlyOfType => "that is the only one of its tag in its parent".to_string(),
+            PseudoClass::NthChild(expr) => format!("that is the child at position {expr}"),
+            PseudoClass::NthLastChild(expr) => format!("that is the child at position {expr} from the end"),
+            PseudoClass::NthOfType(expr) => format!("that is at position {expr} among the children with its tag"),
+            PseudoClass::NthLastOfType(expr) => format!("that is at position {expr} from the end among the children with its tag"),
+            PseudoClass::Scope => "that is the scope of the query".to_string(),
+            PseudoClass::Text(text) => format!("with the text '{text}'"),
+            PseudoClass::ExactText(text) => format!("with exactly the text '{text}'"),
+            PseudoClass::Leaf => "that has no element children".to_string(),
+        });
+    }
+
+    for group in selector.is.iter().chain(&selector.where_) {
+        let options = group.0.iter().map(describe).collect::<Vec<_>>();
+        parts.push(format!("that is any of ({})", options.join(" or ")));
+    }
+
+    let mut description = match &selector.tag {
+        Some(tag) => format!("<{tag}>"),
+        None => "any element".to_string(),
+    };
+    for part in parts {
+        description.push(' ');
+        description.push_str(&part);
+    }
+    description
+}
+
+/// Read a group in parenthesis from **chars**, which is positioned right after the opening `(`.
+/// Parenthesis inside the group must also be closed, and the ones inside quotes are ignored (like in `:text("a)b")`).
+/// Returns the group without the parenthesis and leaves **chars** after the closing `)`.
+fn read_parenthesized<'a>(chars: &mut Chars<'a>) -> Result<&'a str, SelectorParseError> {
+    let group = chars.as_str();
+    let mut depth = 0usize;
+    let mut string_quote: Option<char> = None;
+
+    let mut char_indices = group.char_indices();
+    while let Some((i, c)) = char_indices.next() {
+        match (c, string_quote) {
+            ('\'' | '"', None) => string_quote = Some(c),
+            ('\\', Some(_)) => { char_indices.next(); },
+            ('\'', Some('\'')) | ('"', Some('"')) => string_quote = None,
+            ('(', None) => depth += 1,
+            (')', None) if depth == 0 => {
+                *chars = group[i + 1..].chars();
+                return Ok(&group[..i])
+            },
+            (')', None) => depth -= 1,
+            _ => {}
+        }
+    }
+
+    Err(SelectorParseError::UnclosedParenthesis)
+}
+
+/// Parse a pseudo-class **argument** that is a string in quotes (single or double), where `\` escapes the next character.
+fn parse_string_argument(argument: &str) -> Result<String, SelectorParseError> {
+    let mut chars = argument.chars();
+    let quote = match chars.next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => return Err(SelectorParseError::BadPseudoClassArgument)
+    };
+
+    let mut string = String::new();
+    while let Some(character) = chars.next() {
+        match character {
+            // Nothing else can be after the string
+            _ if character == quote => return if chars.as_str().is_empty() {
+                Ok(string)
+            } else {
+                Err(SelectorParseError::BadPseudoClassArgument)
+            },
+            '\\' => match chars.next() {
+                Some(escaped) => string.push(escaped),
+                None => break
+            },
+            _ => string.push(character)
+        }
+    }
+
+    Err(SelectorParseError::UnclosedString)
+}
+
+/// Whether **c** can be in an attribute value of a selector without quotes.
+fn is_unquoted_value_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Parse a pseudo-class (after its `:`) and add it to **sel**.
+/// 
+/// Supported pseudo-classes are:
+///  - `:not(token)`, where **token** is a single **tag**, **id**, **class**, or **attribute**.
+///  - `:is(selectors)` and `:where(selectors)`, where **selectors** don't have combinators.
+///  - The [`PseudoClass`]es.
+fn parse_pseudo_class(chars: &mut Chars, sel: &mut Selector) -> Result<(), SelectorParseError> {
+    let remaining = chars.as_str();
+    let name_len = remaining.find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+        .unwrap_or(remaining.len());
+    let (name, remaining) = remaining.split_at(name_len);
+    *chars = remaining.chars();
+
+    // Read the argument in parenthesis
+    let argument = match remaining.strip_prefix('(') {
+        Some(_) => {
+            chars.next();
+            Some(read_parenthesized(chars)?.trim())
+        },
+        None => None
+    };
+
+    match (name, argument) {
+        ("", _) => return Err(SelectorParseError::EmptyToken),
+        ("not", Some("")) => return Err(SelectorParseError::EmptyToken),
+        ("not", Some(argument)) => {
+            let negated = argument.parse::<Selector>()?;
+            if !negated.is_single_token() {
+                return Err(SelectorParseError::BadPseudoClassArgument)
+            }
+            sel.not.push(negated);
+        },
+        ("not", None) => return Err(SelectorParseError::BadPseudoClassArgument),
+        ("first-child", None) => sel.pseudo_classes.push(PseudoClass::FirstChild),
+        ("last-child", None) => sel.pseudo_classes.push(PseudoClass::LastChild),
+        ("only-child", None) => sel.pseudo_classes.push(PseudoClass::OnlyChild),
+        ("only-of-type", None) => sel.pseudo_classes.push(PseudoClass::OnlyOfType),
+        ("scope", None) => sel.pseudo_classes.push(PseudoClass::Scope),
+        ("leaf", None) => sel.pseudo_classes.push(PseudoClass::Leaf),
+        ("first-child" | "last-child" | "only-child" | "only-of-type" | "scope" | "leaf", Some(_)) => return Err(SelectorParseError::BadPseudoClassArgument),
+        ("nth-child", Some(argument)) => sel.pseudo_classes.push(PseudoClass::NthChild(argument.parse()?)),
+        ("nth-last-child", Some(argument)) => sel.pseudo_classes.push(PseudoClass::NthLastChild(argument.parse()?)),
+        ("nth-of-type", Some(argument)) => sel.pseudo_classes.push(PseudoClass::NthOfType(argument.parse()?)),
+        ("nth-last-of-type", Some(argument)) => sel.pseudo_classes.push(PseudoClass::NthLastOfType(argument.parse()?)),
+        ("nth-child" | "nth-last-child" | "nth-of-type" | "nth-last-of-type", None) => return Err(SelectorParseError::BadPseudoClassArgument),
+        ("text", Some(argument)) => sel.pseudo_classes.push(PseudoClass::Text(parse_string_argument(argument)?.trim().to_string())),
+        ("exact-text", Some(argument)) => sel.pseudo_classes.push(PseudoClass::ExactText(parse_string_argument(argument)?)),
+        ("text" | "exact-text", None) => return Err(SelectorParseError::BadPseudoClassArgument),
+        ("is" | "where", Some(argument)) => {
+            let group = argument.parse::<CommaSeparated<Selector>>()?;
+            if group.0.is_empty() {
+                return Err(SelectorParseError::EmptyToken)
+            }
+            // Only the node itself is matched, so there can't be combinators
+            if group.0.iter().any(|sel| sel.parent.is_some()) {
+                return Err(SelectorParseError::BadPseudoClassArgument)
+            }
+            if name == "is" {
+                sel.is.push(group)
+            } else {
+                sel.where_.push(group)
+            }
+        },
+        ("is" | "where", None) => return Err(SelectorParseError::BadPseudoClassArgument),
+        _ => return Err(SelectorParseError::UnknownPseudoClass)
+    }
+
+    Ok(())
+}
+
+
 #[derive(Debug, PartialEq)]
 pub enum SelectorParseError {
     MultipleTags,
     MultipleIDs,
+    /// When the same attribute name is in more than one bracket group (`[a][a=1]`, `[a=1][a=2]`).
+    /// Conditions on different attributes must all match, but one attribute can only have one condition
+    /// (except for comparisons, like `[n>=1][n<5]`).
+    DuplicateAttr,
     /// When the last char of the string was `#`, or `.`,
     /// Or when have empty brackets: `[]`. Therefore,
     /// this happens when trying to create an **Id**, **Class**, or **Attribute**
@@ -464,16 +1272,271 @@ pub enum SelectorParseError {
     /// `#`, `.`, or `[` in the selector string.
     UnknownPrefix,
     UnclosedString,
+    /// When a `[` was not closed with a `]`,
+    /// or when a `[` is found inside of brackets (`[a[b]`).
     UnclosedBracket,
+    /// When a `(` of a pseudo-class argument was not closed with a `)`.
+    UnclosedParenthesis,
+    /// When the name after a `:` is not a supported pseudo-class.
+    UnknownPseudoClass,
+    /// When a pseudo-class is missing its argument,
+    /// or the argument is not what the pseudo-class expects.
+    BadPseudoClassArgument,
     /// When found a combinator, but there is no selector after it.
     NoOtherSideCombinator,
     /// A [`char`] was found in a position
     /// that it wasn't supposed to be in.
     BadChar,
+    /// An attribute value that is not quoted contains a character that is not alphanumeric, `-`, or `_`
+    /// (e.g. whitespace in `[attr=a b]`). The value must be quoted: `[attr="a b"]`.
+    UnquotedSpecialChar,
+    /// The value compared with an attribute (e.g. in `[count>5]`) is not a number.
+    BadNumber,
+    /// The selector uses `:scope` (or starts with a combinator, like `> li`),
+    /// but it is not used in a query that starts from a node (e.g. in [`Skimmer`](crate::Skimmer)).
+    NoScope,
     WhiteSpace,
     EmptyString,
 }
 
+/// Why a node did not match a [`Selector`]. See [`Selector::match_explain()`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchFailure {
+    /// There is no node to match.
+    EmptyStack,
+    /// The tag of the node is not the one in the selector (or its local name, when the selector has a namespace).
+    Tag { expected: String, found: String },
+    /// The node is not in the namespace of the selector. Contains the tag of the node.
+    Namespace(String),
+    /// The node doesn't have the id in the selector. **found** is the id it has, if any.
+    Id { expected: String, found: Option<String> },
+    /// The node doesn't have a class of the selector. Contains the class.
+    MissingClass(String),
+    /// The node doesn't have an attribute of the selector. Contains the attribute name.
+    MissingAttribute(String),
+    /// An attribute of the node doesn't have the value in the selector.
+    AttributeValue { name: String, expected: String, found: String },
+    /// An attribute of the node is not a number, or the **comparison** with the **number** in the selector is false.
+    Comparison { name: String, comparison: Comparison, number: f64, found: String },
+    /// The node matches the selector of a `:not()`. Contains the index of that selector in [`Selector::not`].
+    Not(usize),
+    /// The node doesn't match any selector of an `:is()` group. Contains the index of that group in [`Selector::is`].
+    Is(usize),
+    /// The node doesn't match any selector of a `:where()` group. Contains the index of that group in [`Selector::where_`].
+    Where(usize),
+    /// The selector has a [`PseudoClass`], which can't be matched in a stack.
+    PseudoClass(PseudoClass),
+    /// The selector has a sibling [`Combinator`] (`+` or `~`), and the siblings of the nodes in a stack are not known.
+    UnknownSiblings,
+    /// The node matched, but its ancestors didn't match the selector before the **combinator**.
+    /// **depth** is the index in the stack of the node that matched (the ancestors are the nodes before it),
+    /// and **reason** is why the closest ancestor did not match.
+    Combinator { combinator: Combinator, depth: usize, reason: Box<MatchFailure> },
+}
+impl std::fmt::Display for MatchFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyStack => write!(f, "There is no node to match"),
+            Self::Tag { expected, found } => write!(f, "Expected tag <{expected}>, found <{found}>"),
+            Self::Namespace(tag) => write!(f, "Node <{tag}> is not in the namespace of the selector"),
+            Self::Id { expected, found: Some(found) } => write!(f, "Expected id {expected:?}, found {found:?}"),
+            Self::Id { expected, found: None } => write!(f, "Expected id {expected:?}, but the node has no id"),
+            Self::MissingClass(class) => write!(f, "Missing class {class:?}"),
+            Self::MissingAttribute(attr_name) => write!(f, "Missing attribute {attr_name}"),
+            Self::AttributeValue { name, expected, found } => write!(f, "Expected attribute {name}={expected:?}, found {found:?}"),
+            Self::Comparison { name, comparison, number, found } => write!(f, "Expected attribute {name} {comparison} {number}, found {found:?}"),
+            Self::Not(i) => write!(f, "Node matches the selector of :not() number {}", i + 1),
+            Self::Is(i) => write!(f, "Node matches no selector of :is() number {}", i + 1),
+            Self::Where(i) => write!(f, "Node matches no selector of :where() number {}", i + 1),
+            Self::PseudoClass(pseudo_class) => write!(f, "Pseudo-class {pseudo_class:?} can only be matched in a tree"),
+            Self::UnknownSiblings => write!(f, "Sibling combinators can only be matched in a tree"),
+            Self::Combinator { combinator, depth, reason } => write!(f, "{combinator:?} combinator failed at depth {depth}: {reason}"),
+        }
+    }
+}
+
+/// The part of a [`Selector`] that a node did not match, borrowed from the selector. See [`Selector::check_simple()`].
+enum Mismatch<'s> {
+    Tag(&'s str),
+    Namespace,
+    Id(&'s str),
+    Class(&'s str),
+    /// The attribute is missing, or it has a value other than the one in the selector (if any).
+    Attribute(&'s str, Option<&'s str>),
+    /// The attribute is missing, is not a number, or the comparison is false.
+    Comparison(&'s str, Comparison, f64),
+    Not(usize),
+    Is(usize),
+    Where(usize),
+    PseudoClass(&'s PseudoClass),
+}
+impl Mismatch<'_> {
+    /// Describe the mismatch with what was found in the **node**.
+    fn explain(self, node: &impl Element) -> MatchFailure {
+        match self {
+            Self::Tag(tag) => MatchFailure::Tag { expected: tag.to_string(), found: node.tag().to_string() },
+            Self::Namespace => MatchFailure::Namespace(node.tag().to_string()),
+            Self::Id(id) => MatchFailure::Id {
+                expected: id.to_string(),
+                found: node.attribute_decoded("id").map(|id| id.into_owned())
+            },
+            Self::Class(class) => MatchFailure::MissingClass(class.to_string()),
+            Self::Attribute(name, expected) => match (node.attribute_decoded(name), expected) {
+                (Some(found), Some(expected)) => MatchFailure::AttributeValue {
+                    name: name.to_string(),
+                    expected: expected.to_string(),
+                    found: found.into_owned()
+                },
+                _ => MatchFailure::MissingAttribute(name.to_string())
+            },
+            Self::Comparison(name, comparison, number) => match node.attribute_decoded(name) {
+                Some(found) => MatchFailure::Comparison {
+                    name: name.to_string(),
+                    comparison,
+                    number,
+                    found: found.into_owned()
+                },
+                None => MatchFailure::MissingAttribute(name.to_string())
+            },
+            Self::Not(i) => MatchFailure::Not(i),
+            Self::Is(i) => MatchFailure::Is(i),
+            Self::Where(i) => MatchFailure::Where(i),
+            Self::PseudoClass(pseudo_class) => MatchFailure::PseudoClass(pseudo_class.clone()),
+        }
+    }
+}
+
+/// The namespace that a [`Selector`] requires a node to be in, written as `prefix|tag` (e.g. `atom|entry`).
+/// 
+/// The namespace of a node is resolved with the `xmlns` declarations of the node and its ancestors (see [`namespace`](crate::namespace)),
+/// so `<entry xmlns="http://www.w3.org/2005/Atom">` and `<a:entry xmlns:a="http://www.w3.org/2005/Atom">` are in the same namespace.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Namespace {
+    /// A prefix as it was parsed. It is looked up in the document, where the node is (so the document must use the same prefix).
+    Prefix(String),
+    /// A namespace URI. Prefixes are replaced with URIs by [`Selector::resolve_namespaces()`].
+    Uri(String),
+}
+
+/// Pseudo-classes that match a node depending on its siblings, its content, or on where the query started.
+/// Only **element** siblings are considered (not text or comments).
+/// 
+/// These are only known once the parent of the node is closed,
+/// so they are only matched in the [`tree`](crate::tree).
+#[derive(Debug, Clone, PartialEq)]
+pub enum PseudoClass {
+    /// `:first-child`, the node is the first element in its parent.
+    FirstChild,
+    /// `:last-child`, the node is the last element in its parent.
+    LastChild,
+    /// `:only-child`, the node is the only element in its parent.
+    OnlyChild,
+    /// `:only-of-type`, no other element in the parent has the same tag as the node.
+    OnlyOfType,
+    /// `:nth-child(an+b)`, the position of the node among the elements in its parent matches the [`NthExpr`].
+    NthChild(NthExpr),
+    /// `:nth-last-child(an+b)`, same as [`NthChild`](PseudoClass::NthChild), but counting from the last element.
+    NthLastChild(NthExpr),
+    /// `:nth-of-type(an+b)`, same as [`NthChild`](PseudoClass::NthChild), but only counting elements with the same tag as the node.
+    NthOfType(NthExpr),
+    /// `:nth-last-of-type(an+b)`, same as [`NthOfType`](PseudoClass::NthOfType), but counting from the last element.
+    NthLastOfType(NthExpr),
+    /// `:scope`, the node that the query started from (e.g. with [`Node::select()`](crate::tree::Node::select)).
+    /// `:scope > li` selects the direct `li` children of that node.
+    Scope,
+    /// `:text("OK")`, the [direct text](crate::tree::Node::direct_text) of the node (not the text of its descendants)
+    /// is the string, without leading and trailing whitespace (the string is also trimmed).
+    /// So `<b> OK </b>` matches, but `<b><i>OK</i></b>` does not.
+    Text(String),
+    /// `:exact-text(" OK ")`, same as [`Text`](PseudoClass::Text), but the whitespace must also be the same.
+    ExactText(String),
+    /// `:leaf`, the node has no element children, like the innermost cells of a table.
+    /// Unlike `:empty` in CSS, the node can have text and comments (so `<td>1</td>` matches, but `<td><b>1</b></td>` does not).
+    Leaf,
+}
+
+/// The argument of the `:nth-*` [`PseudoClass`]es, `an+b`.
+/// Matches the positions (starting at 1) that are equal to `a*n + b` for some `n >= 0`.
+/// 
+/// Can be parsed from `odd`, `even`, `b`, or `an+b` (where **a** and **b** can be negative, and either can be omitted).
+/// 
+/// ```
+/// use xml_skimmer::selector::NthExpr;
+/// 
+/// let expr = "2n+1".parse::<NthExpr>().unwrap();
+/// assert_eq!(expr, NthExpr { a: 2, b: 1 });
+/// assert!(expr.matches(3));
+/// assert!(!expr.matches(4));
+/// assert_eq!("odd".parse::<NthExpr>().unwrap(), expr);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NthExpr {
+    pub a: i32,
+    pub b: i32,
+}
+impl NthExpr {
+    /// Whether **position** (starting at 1) is one of the positions this expression matches.
+    pub fn matches(&self, position: usize) -> bool {
+        let diff = position as i64 - self.b as i64;
+        let a = self.a as i64;
+        match a {
+            0 => diff == 0,
+            // n = diff / a must be a whole number that is not negative
+            _ => diff % a == 0 && diff / a >= 0
+        }
+    }
+}
+/// Writes the expression as `an+b` (e.g. `2n+1`, `-n+3`, or `3` when **a** is 0).
+impl std::fmt::Display for NthExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.a, self.b) {
+            (0, b) => write!(f, "{b}"),
+            (a, b) => {
+                match a {
+                    1 => write!(f, "n")?,
+                    -1 => write!(f, "-n")?,
+                    a => write!(f, "{a}n")?,
+                }
+                match b {
+                    0 => Ok(()),
+                    b => write!(f, "{b:+}"),
+                }
+            }
+        }
+    }
+}
+impl FromStr for NthExpr {
+    type Err = SelectorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.split_whitespace().collect::<String>().to_ascii_lowercase();
+
+        let parse_int = |s: &str| s.parse::<i32>().map_err(|_| SelectorParseError::BadPseudoClassArgument);
+
+        match s.as_str() {
+            "" => Err(SelectorParseError::EmptyToken),
+            "odd" => Ok(Self { a: 2, b: 1 }),
+            "even" => Ok(Self { a: 2, b: 0 }),
+            _ => match s.split_once('n') {
+                Some((a, b)) => Ok(Self {
+                    a: match a {
+                        "" | "+" => 1,
+                        "-" => -1,
+                        _ => parse_int(a)?
+                    },
+                    b: match b {
+                        "" => 0,
+                        // b must have a sign after the n: `2n+1`
+                        _ if b.starts_with(['+', '-']) => parse_int(b)?,
+                        _ => return Err(SelectorParseError::BadPseudoClassArgument)
+                    }
+                }),
+                None => Ok(Self { a: 0, b: parse_int(&s)? })
+            }
+        }
+    }
+}
+
 /// Separates [`Selector`]s to match [`Node`](ParsedNode)s in different ways.
 /// `SelectorA <Combinator> SelectorB`.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -481,12 +1544,75 @@ pub enum Combinator {
     /// Is denoted by `>`.
     /// The selector will only match nodes `B`
     /// that are direct children of a node that matches `A`.
+    /// Only elements are parents, so text or comments between `A` and `B` don't matter.
     Child,
     /// Is denoted by `whitespace`.
     /// The selector nodes `B` if one of its ancestors matches `A`.
-    Descendant
+    Descendant,
+    /// Is denoted by `+`.
+    /// The selector will only match nodes `B` whose previous element sibling matches `A`.
+    /// Siblings are only known in a [`tree`](crate::tree), so this never matches while skimming.
+    NextSibling,
+    /// Is denoted by `~`.
+    /// The selector will only match nodes `B` that come after a sibling that matches `A`.
+    /// Siblings are only known in a [`tree`](crate::tree), so this never matches while skimming.
+    SubsequentSibling,
 }
 
+/// How an attribute is compared with a number in a [`Selector`] (`[attr>5]`).
+/// The value of the attribute is parsed as a number (ignoring whitespace around it),
+/// so a node whose value is not a number never matches.
+/// 
+/// ```
+/// use xml_skimmer::{node_path, selector::Selector};
+/// 
+/// let sel = "item[qty>=3]".parse::<Selector>().unwrap();
+/// assert!(sel.matches_path(&node_path!["item"[qty = "5"]]));
+/// assert!(!sel.matches_path(&node_path!["item"[qty = "2"]]));
+/// assert!(!sel.matches_path(&node_path!["item"[qty = "abc"]]));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// Is denoted by `>`.
+    Greater,
+    /// Is denoted by `>=`.
+    GreaterOrEqual,
+    /// Is denoted by `<`.
+    Less,
+    /// Is denoted by `<=`.
+    LessOrEqual,
+}
+impl Comparison {
+    /// Compare the **value** of an attribute with the **number** in the selector.
+    pub fn compare(self, value: f64, number: f64) -> bool {
+        match self {
+            Self::Greater => value > number,
+            Self::GreaterOrEqual => value >= number,
+            Self::Less => value < number,
+            Self::LessOrEqual => value <= number,
+        }
+    }
+}
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Greater => write!(f, ">"),
+            Self::GreaterOrEqual => write!(f, ">="),
+            Self::Less => write!(f, "<"),
+            Self::LessOrEqual => write!(f, "<="),
+        }
+    }
+}
+
+/// The [`Combinator`] denoted by **c**, if any. Whitespace is not included.
+fn combinator_of(c: char) -> Option<Combinator> {
+    match c {
+        '>' => Some(Combinator::Child),
+        '+' => Some(Combinator::NextSibling),
+        '~' => Some(Combinator::SubsequentSibling),
+        _ => None
+    }
+}
 
 #[derive(PartialEq)]
 enum PushTo {
@@ -0,0 +1,506 @@
+//! Parse a whole XML source into a tree of [`Node`]s.
+//!
+//! Unlike the [`Skimmer`](crate::Skimmer), the tree knows the siblings of every node,
+//! so it can match [`PseudoClass`]es like `:first-child` or `:nth-of-type(2)`.
+use std::{fmt::Write, str::FromStr};
+use crate::{events_with_options, Event, ParsedNode, SkimError, SkimOptions, entities::{escape_attr_canonical, escape_text}, namespace::lookup_prefix, selector::{CommaSeparated, PseudoClass, Selector}};
+
+
+/// Parse **xml_src** into a [`Document`].
+///
+/// ```
+/// use xml_skimmer::tree::parse_tree;
+///
+/// let doc = parse_tree("<ul><li>a</li><li>b</li></ul>").unwrap();
+/// let items = doc.select(&"li:first-child".parse().unwrap());
+/// assert_eq!(items.len(), 1);
+/// assert_eq!(items[0].text(), "a");
+/// ```
+pub fn parse_tree(xml_src: &str) -> Result<Document, SkimError> {
+    parse_tree_with_options(xml_src, SkimOptions::default())
+}
+
+/// Same as [`parse_tree`], but the parser will behave according to **options**.
+/// 
+/// [`SkimOptions::lazy_attributes`] is ignored: the whole tree is kept, so its attribute values are always decoded.
+pub fn parse_tree_with_options(xml_src: &str, mut options: SkimOptions) -> Result<Document, SkimError> {
+    options.lazy_attributes = false;
+    // The children of every open node. The first one is for the document.
+    let mut open: Vec<(Option<ParsedNode>, Vec<NodeChild>)> = vec![(None, vec![])];
+    let merge_adjacent_text = options.merge_adjacent_text;
+
+    for event in events_with_options(xml_src, options) {
+        match event? {
+            Event::Open(element) => open.push((Some(element), vec![])),
+            Event::SelfClose(element) => open.last_mut().unwrap().1
+                .push(NodeChild::Element(Node { element, children: vec![] })),
+            Event::Close(_) => {
+                // The parser already checked that the tags match
+                let (element, children) = open.pop().unwrap();
+                open.last_mut().unwrap().1
+                    .push(NodeChild::Element(Node { element: element.unwrap(), children }));
+            },
+            Event::Text(text) => {
+                let children = &mut open.last_mut().unwrap().1;
+                match children.last_mut() {
+                    Some(NodeChild::Text(previous)) if merge_adjacent_text => previous.push_str(&text),
+                    _ => children.push(NodeChild::Text(text))
+                }
+            },
+            Event::Comment(content) => open.last_mut().unwrap().1.push(NodeChild::Comment(content)),
+            // Processing instructions are not kept in the tree
+            Event::ProcessingInstruction(..) => {}
+        }
+    }
+
+    // The parser already checked that all nodes were closed
+    let (_, children) = open.pop().unwrap();
+    Ok(Document { children })
+}
+
+
+/// Re-write an XML source in a canonical form, so that 2 documents with the same content give the same string.
+///
+/// These normalizations are applied:
+///  - The prolog, processing instructions, and comments are removed.
+///  - Every run of whitespace in text becomes a single space (except inside `xml:space="preserve"`),
+///    so the spaces around inline nodes are kept. Whitespace-only text between nodes is removed.
+///  - CDATA sections become regular (escaped) text.
+///  - Attributes are sorted by name, quoted with `"`, and there is a single space before each one.
+///  - Nodes without content are self-closing (`<a></a>` becomes `<a/>`).
+///  - Only `&`, `<`, and `>` are escaped in text, and only `&`, `<`, and `"` in attribute values (see [`Node::to_xml()`]).
+///
+/// ```
+/// use xml_skimmer::tree::canonicalize;
+///
+/// assert_eq!(
+///     canonicalize("<?xml version='1.0'?>\n<a  y='2' x=\"1\">\n  text <b></b><!-- comment -->\n</a>").unwrap(),
+///     r#"<a x="1" y="2"> text <b/></a>"#
+/// );
+/// ```
+pub fn canonicalize(xml_src: &str) -> Result<String, SkimError> {
+    // Merged so that text split by a comment is collapsed like the text without it
+    let options = SkimOptions { merge_adjacent_text: true, ..Default::default() };
+    let mut doc = parse_tree_with_options(xml_src, options)?;
+    collapse_whitespace(&mut doc.children, false);
+    Ok(doc.to_xml())
+}
+
+/// Replace every run of whitespace in the text of **children** (and their descendants) with a single space,
+/// unless it is inside `xml:space="preserve"`. Unlike [`TextMode::Collapse`](crate::TextMode::Collapse), the text is not trimmed.
+fn collapse_whitespace(children: &mut [NodeChild], preserve: bool) {
+    for child in children {
+        match child {
+            NodeChild::Text(text) if !preserve => {
+                let mut collapsed = String::with_capacity(text.len());
+                for (i, word) in text.split(char::is_whitespace).enumerate() {
+                    // Empty words are between consecutive whitespace
+                    if i > 0 && !collapsed.ends_with(' ') {
+                        collapsed.push(' ');
+                    }
+                    collapsed.push_str(word);
+                }
+                *text = collapsed;
+            },
+            NodeChild::Element(node) => {
+                let preserve = match node.element.attributes.get("xml:space").map(String::as_str) {
+                    Some("preserve") => true,
+                    Some(_) => false,
+                    None => preserve
+                };
+                collapse_whitespace(&mut node.children, preserve);
+            },
+            _ => {}
+        }
+    }
+}
+
+
+/// The top level of a parsed XML source. Created with [`parse_tree`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Document {
+    pub children: Vec<NodeChild>,
+}
+impl Document {
+    /// Write the document as XML. See [`Node::to_xml()`].
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        for child in &self.children {
+            child.write_xml(&mut xml);
+        }
+        xml
+    }
+
+    /// Write the document as XML, with each top-level element and comment in its own line. See [`Node::to_pretty_xml()`].
+    pub fn to_pretty_xml(&self, indent: usize) -> String {
+        let mut lines = vec![];
+        for child in &self.children {
+            let mut xml = String::new();
+            match child {
+                NodeChild::Element(node) => node.write_pretty_xml(&mut xml, indent, 0, false),
+                NodeChild::Comment(_) => child.write_xml(&mut xml),
+                // Text outside of the root is only whitespace, unless the document is a fragment
+                NodeChild::Text(text) if text.trim().is_empty() => continue,
+                NodeChild::Text(_) => child.write_xml(&mut xml),
+            }
+            lines.push(xml);
+        }
+        lines.join("\n")
+    }
+
+    /// All the elements in the document that match **selector**, in the order they appear.
+    ///
+    /// The document is not an element, so `:scope` matches nothing here.
+    pub fn select(&self, selector: &CommaSeparated<Selector>) -> Vec<&Node> {
+        let mut found = vec![];
+        select_children(&self.children, &mut vec![], selector, &mut found);
+        found
+    }
+}
+
+/// An element and everything inside it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Node {
+    pub element: ParsedNode,
+    pub children: Vec<NodeChild>,
+}
+impl Node {
+    /// The elements inside this node that match **selector**, in the order they appear.
+    ///
+    /// This node is the first ancestor that the selector sees, but it does not know the siblings of this node.
+    /// This node is matched by `:scope`, so `:scope > li` selects the direct `li` children of this node.
+    pub fn select(&self, selector: &CommaSeparated<Selector>) -> Vec<&Node> {
+        let mut found = vec![];
+        let mut path = vec![Context { node: self, siblings: None, scope: true }];
+        select_children(&self.children, &mut path, selector, &mut found);
+        found
+    }
+
+    /// A copy of this node that only keeps the paths to the descendants that match **selector**.
+    ///
+    /// A descendant that matches is kept whole (with all its content).
+    /// A descendant that doesn't match is only kept if some of its descendants are, and only with those descendants (without its text or comments).
+    /// This node is always kept, and is matched like in [`select()`](Node::select).
+    pub fn retain(&self, selector: &CommaSeparated<Selector>) -> Node {
+        let mut path = vec![Context { node: self, siblings: None, scope: true }];
+        Node {
+            element: self.element.clone(),
+            children: retain_children(&self.children, &mut path, selector)
+        }
+    }
+
+    /// Write the node and its content as XML.
+    ///
+    /// Attributes are sorted by name and quoted with `"`, and a node without children is written as self-closing (`<a/>`).
+    /// Text and attribute values are escaped with entities where needed.
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        self.write_xml(&mut xml);
+        xml
+    }
+
+    /// Same as [`to_xml()`](Node::to_xml), but each element and comment goes in its own line,
+    /// indented with **indent** spaces for each level of depth (there is no newline at the end).
+    ///
+    /// The content of a node is written as it is (like in `to_xml()`) when the node has text that is not only whitespace
+    /// (so the text is never changed), or when it is inside `xml:space="preserve"`.
+    /// Otherwise, whitespace-only text is replaced with the indentation, so the output parses to the same tree
+    /// (when whitespace-only text is ignored, as it is by default).
+    ///
+    /// ```
+    /// use xml_skimmer::tree::parse_tree;
+    ///
+    /// let doc = parse_tree("<ul><li>a <b>b</b></li><li/></ul>").unwrap();
+    /// assert_eq!(doc.to_pretty_xml(2), "<ul>\n  <li>a <b>b</b></li>\n  <li/>\n</ul>");
+    /// ```
+    pub fn to_pretty_xml(&self, indent: usize) -> String {
+        let mut xml = String::new();
+        self.write_pretty_xml(&mut xml, indent, 0, false);
+        xml
+    }
+
+    /// Write this node for [`to_pretty_xml()`](Node::to_pretty_xml), where **depth** is the level of the node,
+    /// and **preserve** is whether its parent is inside `xml:space="preserve"`.
+    fn write_pretty_xml(&self, xml: &mut String, indent: usize, depth: usize, preserve: bool) {
+        let preserve = match self.element.attributes.get("xml:space").map(String::as_str) {
+            Some("preserve") => true,
+            Some("default") => false,
+            _ => preserve
+        };
+        let inline = preserve
+            || self.children.iter().all(|child| matches!(child, NodeChild::Text(_)))
+            || self.children.iter().any(|child| matches!(child, NodeChild::Text(text) if !text.trim().is_empty()));
+        if inline {
+            self.write_xml(xml);
+            return
+        }
+
+        self.write_start_tag(xml);
+        xml.push('>');
+        for child in &self.children {
+            match child {
+                NodeChild::Element(node) => {
+                    new_line(xml, indent * (depth + 1));
+                    node.write_pretty_xml(xml, indent, depth + 1, preserve);
+                },
+                NodeChild::Comment(_) => {
+                    new_line(xml, indent * (depth + 1));
+                    child.write_xml(xml);
+                },
+                // Only whitespace, which is replaced by the indentation
+                NodeChild::Text(_) => {}
+            }
+        }
+        new_line(xml, indent * depth);
+        let _ = write!(xml, "</{}>", self.element.tag);
+    }
+
+    /// Write `<tag` and the attributes of the node.
+    fn write_start_tag(&self, xml: &mut String) {
+        xml.push('<');
+        xml.push_str(&self.element.tag);
+        for (name, value) in self.element.attributes_sorted() {
+            let _ = write!(xml, " {name}=\"{}\"", escape_attr_canonical(value));
+        }
+    }
+
+    fn write_xml(&self, xml: &mut String) {
+        self.write_start_tag(xml);
+
+        if self.children.is_empty() {
+            xml.push_str("/>");
+            return
+        }
+
+        xml.push('>');
+        for child in &self.children {
+            child.write_xml(xml);
+        }
+        let _ = write!(xml, "</{}>", self.element.tag);
+    }
+
+    /// The element children of this node (skipping text and comments).
+    pub fn elements(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|child| match child {
+            NodeChild::Element(node) => Some(node),
+            _ => None
+        })
+    }
+
+    /// The element children of this node that match the last simple selector of **selector**, in the order they appear.
+    /// Combinators in **selector** are ignored, but pseudo-classes like `:first-child` can be used.
+    ///
+    /// ```
+    /// use xml_skimmer::tree::parse_tree;
+    ///
+    /// let doc = parse_tree("<ul><li/>text<li class='done'/><p/></ul>").unwrap();
+    /// let ul = doc.select(&"ul".parse().unwrap())[0];
+    /// assert_eq!(ul.count_children_matching(&"li".parse().unwrap()), 2);
+    /// assert_eq!(ul.children_matching(&".done".parse().unwrap()).count(), 1);
+    /// ```
+    pub fn children_matching<'s>(&'s self, selector: &'s Selector) -> impl Iterator<Item = &'s Node> + 's {
+        let parent = Context { node: self, siblings: None, scope: true };
+        self.children.iter().enumerate().filter_map(move |(index, child)| match child {
+            NodeChild::Element(node) => {
+                let path = [parent.clone(), Context { node, siblings: Some((&self.children, index)), scope: false }];
+                Context::match_simple(selector, &path).then_some(node)
+            },
+            _ => None
+        })
+    }
+
+    /// How many element children of this node match **selector**. See [`children_matching()`](Node::children_matching).
+    pub fn count_children_matching(&self, selector: &Selector) -> usize {
+        self.children_matching(selector).count()
+    }
+
+    /// All the text inside this node (and its descendants) joined together.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for child in &self.children {
+            match child {
+                NodeChild::Element(node) => text.push_str(&node.text()),
+                NodeChild::Text(t) => text.push_str(t),
+                NodeChild::Comment(_) => {}
+            }
+        }
+        text
+    }
+
+    /// Parse the [`text()`](Node::text) of this node (without the whitespace around it) into a **T**, like a number.
+    /// 
+    /// ```
+    /// use xml_skimmer::tree::parse_tree;
+    /// 
+    /// let doc = parse_tree("<item><price> 9.5 </price><count>many</count></item>").unwrap();
+    /// assert_eq!(doc.select(&"price".parse().unwrap())[0].inner_text_parsed::<f64>(), Ok(9.5));
+    /// assert!(doc.select(&"count".parse().unwrap())[0].inner_text_parsed::<u32>().is_err());
+    /// ```
+    pub fn inner_text_parsed<T: FromStr>(&self) -> Result<T, T::Err> {
+        self.text().trim().parse()
+    }
+
+    /// The text that is directly inside this node joined together, without the text of its descendants.
+    /// 
+    /// ```
+    /// use xml_skimmer::tree::parse_tree;
+    /// 
+    /// let doc = parse_tree("<p>Hello <b>big</b> world</p>").unwrap();
+    /// let p = doc.select(&"p".parse().unwrap())[0];
+    /// assert_eq!(p.direct_text(), "Hello  world");
+    /// assert_eq!(p.text(), "Hello big world");
+    /// ```
+    pub fn direct_text(&self) -> String {
+        self.children.iter()
+            .filter_map(|child| match child {
+                NodeChild::Text(text) => Some(text.as_str()),
+                _ => None
+            })
+            .collect()
+    }
+}
+
+/// Something inside a [`Node`] or [`Document`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeChild {
+    Element(Node),
+    Text(String),
+    /// Only present when [`SkimOptions::emit_comments`] is `true`.
+    Comment(String),
+}
+impl NodeChild {
+    fn write_xml(&self, xml: &mut String) {
+        match self {
+            Self::Element(node) => node.write_xml(xml),
+            Self::Text(text) => xml.push_str(&escape_text(text)),
+            Self::Comment(content) => {
+                let _ = write!(xml, "<!--{content}-->");
+            }
+        }
+    }
+}
+
+
+/// Start a new line in **xml**, indented with **spaces**.
+fn new_line(xml: &mut String, spaces: usize) {
+    xml.push('\n');
+    xml.extend(std::iter::repeat_n(' ', spaces));
+}
+
+/// A node in the path being matched, along with its siblings (when they are known).
+#[derive(Clone)]
+struct Context<'a> {
+    node: &'a Node,
+    /// The children of the parent of **node**, and the index of **node** among them.
+    siblings: Option<(&'a [NodeChild], usize)>,
+    /// Whether **node** is the one the query started from (matched by `:scope`).
+    scope: bool,
+}
+impl<'a> Context<'a> {
+    /// Whether the last node of **path** matches any of the selectors.
+    fn match_path(path: &[Self], selector: &CommaSeparated<Selector>) -> bool {
+        selector.0.iter().any(|sel| sel.match_path(path, &Self::match_simple, &Self::previous_siblings))
+    }
+
+    /// Match **selector** with the last node of **path**, where the rest are its ancestors.
+    fn match_simple(selector: &Selector, path: &[Self]) -> bool {
+        // match_path never calls this with an empty path
+        let context = path.last().unwrap();
+        let lookup_namespace = |prefix: Option<&str>| lookup_prefix(prefix, path.iter().rev().map(|context| &context.node.element));
+        selector.match_simple(&context.node.element, &lookup_namespace, &|pseudo| context.match_pseudo_class(pseudo))
+    }
+
+    /// The elements that come before **node** in its parent, from the nearest to the farthest.
+    fn previous_siblings(&self) -> Vec<Self> {
+        let (siblings, index) = match self.siblings {
+            Some(siblings) => siblings,
+            None => return vec![]
+        };
+
+        siblings[..index].iter().enumerate().rev()
+            .filter_map(|(index, child)| match child {
+                NodeChild::Element(node) => Some(Context { node, siblings: Some((siblings, index)), scope: false }),
+                _ => None
+            })
+            .collect()
+    }
+
+    fn match_pseudo_class(&self, pseudo: &PseudoClass) -> bool {
+        // These don't depend on the siblings
+        match pseudo {
+            PseudoClass::Scope => return self.scope,
+            PseudoClass::Text(text) => return self.node.direct_text().trim() == text,
+            PseudoClass::ExactText(text) => return self.node.direct_text() == *text,
+            PseudoClass::Leaf => return self.node.elements().next().is_none(),
+            _ => {}
+        }
+
+        let (siblings, index) = match self.siblings {
+            Some(siblings) => siblings,
+            None => return false
+        };
+        let elements = |children: &'_ [NodeChild]| children.iter()
+            .filter(|child| matches!(child, NodeChild::Element(_)))
+            .count();
+        let of_type = |children: &'_ [NodeChild]| children.iter()
+            .filter(|child| matches!(child, NodeChild::Element(node) if node.element.tag == self.node.element.tag))
+            .count();
+        let (before, after) = (&siblings[..index], &siblings[index + 1..]);
+
+        match pseudo {
+            PseudoClass::FirstChild => elements(before) == 0,
+            PseudoClass::LastChild => elements(after) == 0,
+            PseudoClass::OnlyChild => elements(siblings) == 1,
+            PseudoClass::OnlyOfType => of_type(siblings) == 1,
+            // Positions start at 1
+            PseudoClass::NthChild(expr) => expr.matches(elements(before) + 1),
+            PseudoClass::NthLastChild(expr) => expr.matches(elements(after) + 1),
+            PseudoClass::NthOfType(expr) => expr.matches(of_type(before) + 1),
+            PseudoClass::NthLastOfType(expr) => expr.matches(of_type(after) + 1),
+            // Returned above
+            PseudoClass::Scope | PseudoClass::Text(_) | PseudoClass::ExactText(_) | PseudoClass::Leaf => unreachable!(),
+        }
+    }
+}
+
+/// Copy the elements in **children** that match **selector**, or that have descendants that match. See [`Node::retain()`].
+/// **path** has the ancestors of **children**.
+fn retain_children<'a>(children: &'a [NodeChild], path: &mut Vec<Context<'a>>, selector: &CommaSeparated<Selector>) -> Vec<NodeChild> {
+    let mut kept = vec![];
+
+    for (index, child) in children.iter().enumerate() {
+        if let NodeChild::Element(node) = child {
+            path.push(Context { node, siblings: Some((children, index)), scope: false });
+
+            if Context::match_path(path, selector) {
+                kept.push(child.clone());
+            } else {
+                let children = retain_children(&node.children, path, selector);
+                if !children.is_empty() {
+                    kept.push(NodeChild::Element(Node { element: node.element.clone(), children }));
+                }
+            }
+
+            path.pop();
+        }
+    }
+
+    kept
+}
+
+/// Find the elements in **children** (and their descendants) that match **selector**.
+/// **path** has the ancestors of **children**.
+fn select_children<'a>(children: &'a [NodeChild], path: &mut Vec<Context<'a>>, selector: &CommaSeparated<Selector>, found: &mut Vec<&'a Node>) {
+    for (index, child) in children.iter().enumerate() {
+        if let NodeChild::Element(node) = child {
+            path.push(Context { node, siblings: Some((children, index)), scope: false });
+
+            if Context::match_path(path, selector) {
+                found.push(node);
+            }
+            select_children(&node.children, path, selector, found);
+
+            path.pop();
+        }
+    }
+}
@@ -1,5 +1,32 @@
-use std::collections::HashMap;
-use xml_skimmer::{ParsedNode, SkimError};
+use std::{alloc::{GlobalAlloc, Layout, System}, cell::Cell, collections::HashMap, time::Instant};
+use xml_skimmer::{events, find_first, find_first_map, Element, ParsedNode, Skimmer, SkimError, SkimOptions, entities::decode_entities};
+
+/// Counts the allocations of each thread, since tests run in parallel.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Run **f**, and count how many allocations the current thread made while running it.
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
 
 #[test]
 fn benchmark() -> Result<(), SkimError> {
@@ -14,4 +41,75 @@ fn closures() -> Result<(), SkimError> {
             println!("Call successful for {node}");
         })
     ]))
-}
\ No newline at end of file
+}
+
+#[test]
+fn eager_vs_lazy_attributes() -> Result<(), SkimError> {
+    let node = r#"<item a="&lt;1&gt;" b="&quot;2&quot;" c="3 &amp; 4" d="&#53;&#x36;" e="&apos;7&apos;"/>"#;
+    let xml_src = format!("<list>{}</list>", node.repeat(20_000));
+
+    for lazy_attributes in [false, true] {
+        // Only read one of the attributes
+        let mut total = 0;
+        let mut skimmer = Skimmer::with_options(SkimOptions { lazy_attributes, ..Default::default() });
+        skimmer.on("item", |node| {
+            let value = &node.attributes["c"];
+            total += if lazy_attributes { decode_entities(value).len() } else { value.len() };
+        }).map_err(SkimError::BadSelector)?;
+
+        let start = Instant::now();
+        skimmer.skim(&xml_src)?;
+        let elapsed = start.elapsed();
+        drop(skimmer);
+
+        assert_eq!(total, "3 & 4".len() * 20_000);
+        println!("lazy_attributes: {lazy_attributes}, took {elapsed:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn allocations_when_nodes_are_not_kept() -> Result<(), SkimError> {
+    let node = r#"<item id="x" class="a b" title="1 2">text</item>"#;
+    let xml_src = format!("<list>{}</list>", node.repeat(20_000));
+
+    // Nodes are only copied out of the source when a handler gets them
+    let mut skimmer = Skimmer::new();
+    skimmer
+        .on("list > item.c, item[title='1 3']", |_| unreachable!()).map_err(SkimError::BadSelector)?
+        .on_with_stack("missing item", |_| unreachable!()).map_err(SkimError::BadSelector)?;
+    let (result, unmatched) = allocations(|| skimmer.skim(&xml_src));
+    result?;
+
+    let mut count = 0;
+    let mut matching = Skimmer::new();
+    matching.on("item", |_| count += 1).map_err(SkimError::BadSelector)?;
+    let (result, matched) = allocations(|| matching.skim(&xml_src));
+    result?;
+    drop(matching);
+
+    let (_, owned) = allocations(|| assert!(events(&xml_src).all(|event| event.is_ok())));
+
+    assert_eq!(count, 20_000);
+    println!("allocations without matches: {unmatched}, matching every item: {matched}, events: {owned}");
+    assert!(unmatched < 100);
+    assert!(matched >= 20_000);
+    Ok(())
+}
+
+#[test]
+fn find_first_map_does_not_copy_the_node() -> Result<(), SkimError> {
+    let attributes = (0..1000).map(|i| format!(" a{i}=\"{i}\"")).collect::<String>();
+    let xml_src = format!("<list><item{attributes}/></list>");
+
+    let (node, copied) = allocations(|| find_first(&xml_src, "item"));
+    assert_eq!(node?.unwrap().attributes.len(), 1000);
+    let (value, mapped) = allocations(|| find_first_map(&xml_src, "item", |node| node.attribute("a999").map(str::len)));
+    assert_eq!(value?, Some(Some(3)));
+
+    println!("allocations of find_first: {copied}, find_first_map: {mapped}");
+    // Each attribute is copied by find_first, none by find_first_map
+    assert!(copied >= 2000);
+    assert!(mapped < 50);
+    Ok(())
+}
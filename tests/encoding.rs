@@ -0,0 +1,90 @@
+use xml_skimmer::{skim_bytes, ParsedNode, SkimError, encoding::{decode, detect_encoding, Encoding}};
+
+fn utf16_le(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+fn utf16_be(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+#[test]
+fn detection() {
+    // Byte order marks
+    assert_eq!(detect_encoding(&[0xEF, 0xBB, 0xBF, b'<']), (Encoding::Utf8, true));
+    assert_eq!(detect_encoding(&[0xFF, 0xFE, b'<', 0]), (Encoding::Utf16Le, true));
+    assert_eq!(detect_encoding(&[0xFE, 0xFF, 0, b'<']), (Encoding::Utf16Be, true));
+    // Zero bytes around the first character
+    assert_eq!(detect_encoding(&[b'<', 0, b'a', 0]), (Encoding::Utf16Le, false));
+    assert_eq!(detect_encoding(&[0, b'<', 0, b'a']), (Encoding::Utf16Be, false));
+    assert_eq!(detect_encoding(&[b' ', 0]), (Encoding::Utf16Le, false));
+    assert_eq!(detect_encoding(b"<a/>"), (Encoding::Utf8, false));
+    assert_eq!(detect_encoding(b""), (Encoding::Utf8, false));
+}
+
+#[test]
+fn utf16_without_bom() {
+    let src = "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\n<r><p lang='日本'>héllo 🦀</p></r>";
+    assert_eq!(decode(&utf16_le(src)).unwrap(), src);
+    assert_eq!(decode(&utf16_be(src)).unwrap(), src);
+    // Hand-written bytes of `<a>x</a>`, little endian
+    assert_eq!(decode(&[b'<', 0, b'a', 0, b'>', 0, b'x', 0, b'<', 0, b'/', 0, b'a', 0, b'>', 0]).unwrap(), "<a>x</a>");
+
+    let mut texts = vec![];
+    skim_bytes(&utf16_be(src), [("p", |node: &ParsedNode| texts.push(node.attributes["lang"].clone()))]).unwrap();
+    assert_eq!(texts, ["日本"]);
+}
+
+#[test]
+fn byte_order_marks() {
+    assert_eq!(decode(&[&[0xFF, 0xFE][..], &utf16_le("<a/>")].concat()).unwrap(), "<a/>");
+    assert_eq!(decode(&[&[0xFE, 0xFF][..], &utf16_be("<a/>")].concat()).unwrap(), "<a/>");
+    assert_eq!(decode(b"\xEF\xBB\xBF<a/>").unwrap(), "<a/>");
+    // UTF-8 is not copied
+    assert!(matches!(decode(b"<a/>"), Ok(std::borrow::Cow::Borrowed("<a/>"))));
+    // The declared byte order must be the one of the BOM
+    let src = "<?xml version='1.0' encoding='utf-16le'?><a/>";
+    assert!(decode(&[&[0xFF, 0xFE][..], &utf16_le(src)].concat()).is_ok());
+    assert_eq!(
+        decode(&[&[0xFE, 0xFF][..], &utf16_be(src)].concat()),
+        Err(SkimError::EncodingMismatch("utf-16le".to_string()))
+    );
+}
+
+#[test]
+fn encoding_mismatch() {
+    let declares = |encoding: &str| format!("<?xml version=\"1.0\" encoding=\"{encoding}\"?><a/>");
+
+    // Declared UTF-8, but the BOM (or the bytes) are UTF-16
+    assert_eq!(
+        decode(&[&[0xFF, 0xFE][..], &utf16_le(&declares("UTF-8"))].concat()),
+        Err(SkimError::EncodingMismatch("UTF-8".to_string()))
+    );
+    assert_eq!(decode(&utf16_be(&declares("UTF-8"))), Err(SkimError::EncodingMismatch("UTF-8".to_string())));
+    // Declared UTF-16, but the BOM (or the bytes) are UTF-8
+    assert_eq!(
+        decode(format!("\u{FEFF}{}", declares("UTF-16")).as_bytes()),
+        Err(SkimError::EncodingMismatch("UTF-16".to_string()))
+    );
+    assert_eq!(decode(declares("UTF-16").as_bytes()), Err(SkimError::EncodingMismatch("UTF-16".to_string())));
+    assert_eq!(
+        skim_bytes(declares("UTF-16").as_bytes(), [("a", |_: &ParsedNode| {})]),
+        Err(SkimError::EncodingMismatch("UTF-16".to_string()))
+    );
+
+    // Other encodings are not checked
+    assert!(decode(declares("ISO-8859-1").as_bytes()).is_ok());
+    assert!(decode(declares("utf-8").as_bytes()).is_ok());
+    assert!(decode(b"<?xml-stylesheet encoding='UTF-16'?><a/>").is_ok());
+}
+
+#[test]
+fn invalid_bytes() {
+    assert_eq!(decode(b"<a>\xFF</a>"), Err(SkimError::InvalidEncoding(3)));
+    assert_eq!(decode(b"\xEF\xBB\xBF<a>\xC3</a>"), Err(SkimError::InvalidEncoding(6)));
+    // Odd number of bytes
+    assert_eq!(decode(&[b'<', 0, b'a', 0, b'/']), Err(SkimError::InvalidEncoding(4)));
+    // Unpaired surrogate, after a character that takes 2 code units
+    let mut bytes = utf16_le("<a>🦀");
+    bytes.extend([0x00, 0xD8, b'<', 0]);
+    assert_eq!(decode(&bytes), Err(SkimError::InvalidEncoding(10)));
+}
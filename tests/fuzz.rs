@@ -0,0 +1,103 @@
+use std::{collections::HashMap, panic};
+use xml_skimmer::{ParsedNode, SkimOptions, selector::{CommaSeparated, Selector}};
+
+/// Pieces of XML that are more likely to find bugs than random bytes.
+const XML_TOKENS: &[&str] = &[
+    "<", ">", "/", "=", "\"", "'", " ", "\n", "\t", "!", "?", "-", "[", "]", "&", ";", "#", "x",
+    "a", "b", "tag", "attr", "é", "😀", "名前",
+    "<!--", "-->", "<?", "?>", "<![CDATA[", "]]>", "</", "/>", "&amp;", "&#x3C;", "&#60;", "&foo;",
+    "<a>", "</a>", "<b x='1'>", "</b>", "<c/>",
+];
+/// Pieces of selectors that are more likely to find bugs than random bytes.
+const SELECTOR_TOKENS: &[&str] = &[
+    "a", "b", "tag", "#", ".", "[", "]", "=", "'", "\"", " ", ">", ",", ":", "(", ")", "not", "é", "😀",
+    ":not(", "[attr", "[attr=", "=val]", "*", "+", "~", "\\", ":is(", ":nth-child(", "2n+1", "名前",
+];
+
+/// Deterministic pseudo-random number generator (xorshift64), so failures can be reproduced.
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    /// A string made of random **tokens**.
+    fn string_of(&mut self, tokens: &[&str], max_len: usize) -> String {
+        let len = self.below(max_len + 1);
+        (0..len).map(|_| tokens[self.below(tokens.len())]).collect()
+    }
+    /// Random bytes, as a string if they are valid UTF-8.
+    fn bytes(&mut self, max_len: usize) -> Option<String> {
+        let len = self.below(max_len + 1);
+        String::from_utf8((0..len).map(|_| self.next() as u8).collect()).ok()
+    }
+}
+
+/// Runs **f** and fails the test with the **input** that made it panic.
+fn assert_no_panic(input: &str, f: impl FnOnce() + panic::UnwindSafe) {
+    if panic::catch_unwind(f).is_err() {
+        panic!("panicked with input {input:?}");
+    }
+}
+
+#[test]
+fn skim_xml_never_panics() {
+    let mut rng = Rng(0x5EED_1234_ABCD_0001);
+
+    for _ in 0..20_000 {
+        let xml_src = rng.string_of(XML_TOKENS, 24);
+        assert_no_panic(&xml_src, || {
+            let _ = xml_skimmer::skim_xml(&xml_src, HashMap::<_, fn(&ParsedNode)>::new());
+        });
+        assert_no_panic(&xml_src, || {
+            let _ = xml_skimmer::events(&xml_src).count();
+        });
+        assert_no_panic(&xml_src, || {
+            let options = SkimOptions { lenient: true, strict_entities: true, html_lowercase: true, ..Default::default() };
+            let _ = xml_skimmer::events_with_options(&xml_src, options).count();
+        });
+    }
+
+    for _ in 0..20_000 {
+        if let Some(xml_src) = rng.bytes(32) {
+            assert_no_panic(&xml_src, || {
+                let _ = xml_skimmer::skim_xml(&xml_src, HashMap::<_, fn(&ParsedNode)>::new());
+            });
+        }
+    }
+}
+
+#[test]
+fn selectors_never_panic() {
+    let mut rng = Rng(0x5EED_1234_ABCD_0002);
+
+    for _ in 0..20_000 {
+        let selector = rng.string_of(SELECTOR_TOKENS, 12);
+        assert_no_panic(&selector, || {
+            let _ = selector.parse::<CommaSeparated<Selector>>();
+            let _ = selector.parse::<Selector>();
+        });
+        // Selectors that don't parse return an error instead of panicking
+        let selector: &'static str = Box::leak(selector.into_boxed_str());
+        assert_no_panic(selector, || {
+            let _ = xml_skimmer::skim_xml("<a><b x='1'/></a>", HashMap::from([(selector, |_: &ParsedNode| {})]));
+        });
+    }
+}
+
+#[test]
+fn regressions() {
+    use xml_skimmer::{SkimError, selector::SelectorParseError};
+
+    assert_eq!(xml_skimmer::skim_xml("<a x=b/>", HashMap::<_, fn(&ParsedNode)>::new()), Err(SkimError::UnquotedAttrValue("x".to_string())));
+    assert_eq!(xml_skimmer::skim_xml("<a/>", HashMap::from([("a >", |_: &ParsedNode| {})])), Err(SkimError::BadSelector(SelectorParseError::NoOtherSideCombinator)));
+    assert_eq!("é, 😀".parse::<CommaSeparated<Selector>>(), Ok(CommaSeparated(vec![
+        Selector { tag: "é".to_string().into(), ..Default::default() },
+        Selector { tag: "😀".to_string().into(), ..Default::default() },
+    ])));
+}
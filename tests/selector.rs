@@ -1,5 +1,5 @@
-use std::collections::{HashMap, HashSet};
-use xml_skimmer::{ParsedNode, selector::{CommaSeparated, Selector, Combinator}};
+use std::{collections::{HashMap, HashSet}, str::FromStr};
+use xml_skimmer::{node_path, ParsedNode, SkimError, selector::{CommaSeparated, Comparison, Selector, Combinator, MatchFailure, Namespace, PseudoClass, NthExpr, SelectorParseError}};
 
 #[test]
 fn matching() {
@@ -18,7 +18,8 @@ fn matching() {
                 (String::from("class"), String::from("class cls c")),
                 (String::from("id"),    String::from("id")),
                 (String::from("attr"),  String::from("val"))
-            ])
+            ]),
+            ..Default::default()
         }
     ];
 
@@ -35,6 +36,65 @@ fn matching() {
     assert!("tag#id.class.cls.c[attr=val]" .parse::<CommaSeparated<Selector>>().unwrap().match_node(&stack));
 }
 
+#[test]
+fn matching_depth() {
+    let stack = [
+        ParsedNode::new("tag3"),
+        ParsedNode::new("tag2"),
+        ParsedNode::new("tag")
+            .with_attr("class", "class cls c")
+            .with_attr("id", "id")
+            .with_attr("attr", "val"),
+    ];
+    let depth = |selector: &str| selector.parse::<Selector>().unwrap().match_node_depth(&stack);
+
+    assert_eq!(depth("tag"), Some(2));
+    assert_eq!(depth("tag#id.cls[attr=val]"), Some(2));
+    assert_eq!(depth("tag2 tag"), Some(1));
+    assert_eq!(depth("tag3 tag"), Some(0));
+    assert_eq!(depth("tag3 > tag2 > tag"), Some(0));
+    assert_eq!(depth("tag3 tag2 .c"), Some(0));
+
+    assert_eq!(depth("gat"), None);
+    assert_eq!(depth("tag3 > tag"), None);
+    assert_eq!(depth("tag2 ~ tag"), None);
+    assert_eq!("tag".parse::<Selector>().unwrap().match_node_depth::<ParsedNode>(&[]), None);
+
+    // The closest ancestor that can anchor the selector
+    assert_eq!("a b".parse::<Selector>().unwrap().match_node_depth(&node_path!["a", "a", "c", "b"]), Some(1));
+    assert_eq!("a > c b".parse::<Selector>().unwrap().match_node_depth(&node_path!["a", "c", "a", "b", "b"]), Some(0));
+}
+
+#[test]
+fn matching_path_parts() {
+    let no_attributes = HashMap::new();
+    let attributes = HashMap::from([
+        ("class".to_string(), "class cls c".to_string()),
+        ("id".to_string(), "id".to_string()),
+        ("attr".to_string(), "val".to_string()),
+    ]);
+    let parts = [
+        ("tag3", &no_attributes),
+        ("tag2", &no_attributes),
+        ("tag", &attributes),
+    ];
+
+    assert!("tag"               .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!("tag3 tag"          .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!("tag3 > tag2 > tag" .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!(".cls"              .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!("#id"               .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!("[attr]"            .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!("[attr=val]"        .parse::<Selector>().unwrap().match_path_parts(&parts));
+    // all combined
+    assert!("tag#id.class.cls.c[attr=val]" .parse::<Selector>().unwrap().match_path_parts(&parts));
+
+    assert!(!"tag2"             .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!(!"tag3 > tag"       .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!(!"[attr=other]"     .parse::<Selector>().unwrap().match_path_parts(&parts));
+    assert!(!"tag"              .parse::<Selector>().unwrap().match_path_parts(&[]));
+}
+
 #[test]
 fn all_selector_tokens() {
     assert_eq!("t_a-g".parse(),
@@ -113,11 +173,39 @@ fn all_selector_tokens() {
             parent: Some((Box::new(Selector {
                 tag: "parent".to_string().into(),
                 ..Default::default()
-            }), Combinator::Child))
+            }), Combinator::Child)),
+            ..Default::default()
         })
     );
 }
 
+#[test]
+fn multiple_attribute_conditions() {
+    let sel = "item[a][b=1][class~=x][n>2][n<=5]".parse::<Selector>().unwrap();
+    assert_eq!(sel.attributes, HashMap::from([("a".to_string(), None), ("b".to_string(), Some("1".to_string()))]));
+    assert_eq!(sel.to_string(), "item.x[a][b=\"1\"][n>2][n<=5]");
+
+    // Every condition has to match
+    assert!(sel.matches_path(&node_path!["item" .x[a = ""][b = "1"][n = "3"]]));
+    assert!(sel.matches_path(&node_path!["item" .y .x[a = "any"][b = "1"][n = "5"][other = "z"]]));
+    assert!(!sel.matches_path(&node_path!["item" .x[b = "1"][n = "3"]]));
+    assert!(!sel.matches_path(&node_path!["item" .x[a = ""][b = "2"][n = "3"]]));
+    assert!(!sel.matches_path(&node_path!["item" .y[a = ""][b = "1"][n = "3"]]));
+    assert!(!sel.matches_path(&node_path!["item" .x[a = ""][b = "1"][n = "6"]]));
+    assert!(!sel.matches_path(&node_path!["item" .x[a = ""][b = "1"]]));
+
+    // The same attribute can only have one condition
+    use xml_skimmer::selector::SelectorParseError as Error;
+    assert_eq!("[a][a]".parse::<Selector>(), Err(Error::DuplicateAttr));
+    assert_eq!("[a=1][a=2]".parse::<Selector>(), Err(Error::DuplicateAttr));
+    assert_eq!("[a=1][a=1]".parse::<Selector>(), Err(Error::DuplicateAttr));
+    assert_eq!("[a][a=1]".parse::<Selector>(), Err(Error::DuplicateAttr));
+    // but the groups of different selectors are separate
+    assert!("li[a] > a[a=1]".parse::<Selector>().is_ok());
+    assert!("[a]:not([a=1])".parse::<Selector>().is_ok());
+    assert!("[class][class~=x][class~=y]".parse::<Selector>().is_ok());
+}
+
 #[test]
 fn selector_erorrs() {
     use xml_skimmer::selector::SelectorParseError as Error;
@@ -127,7 +215,9 @@ fn selector_erorrs() {
     assert_eq!("tag[attr]tag".parse::<Selector>(),    Err(Error::MultipleTags));
     assert_eq!("#id1#id2".parse::<Selector>(),        Err(Error::MultipleIDs));
     // assert_eq!(".class.class".parse::<Selector>(),    Err(Error::DuplicateClass));
-    // assert_eq!("[attr][attr]".parse::<Selector>(),    Err(Error::DuplicateAttr));
+    assert_eq!("[attr][attr]".parse::<Selector>(),    Err(Error::DuplicateAttr));
+    assert_eq!("[a=1][a=2]".parse::<Selector>(),      Err(Error::DuplicateAttr));
+    assert_eq!("[a][b][a='1']".parse::<Selector>(),   Err(Error::DuplicateAttr));
     assert_eq!("tag#".parse::<Selector>(),            Err(Error::EmptyToken));
     assert_eq!("tag.".parse::<Selector>(),            Err(Error::EmptyToken));
     assert_eq!("[ ]".parse::<Selector>(),             Err(Error::EmptyToken));
@@ -137,6 +227,8 @@ fn selector_erorrs() {
     assert_eq!("[ attr = 'val ]".parse::<Selector>(), Err(Error::UnclosedString));
     assert_eq!("[ attr = val ".parse::<Selector>(),   Err(Error::UnclosedBracket));
     assert_eq!("[ attr = ".parse::<Selector>(),       Err(Error::UnclosedBracket));
+    assert_eq!("[a[b]".parse::<Selector>(),           Err(Error::UnclosedBracket));
+    assert_eq!("[a.b".parse::<Selector>(),            Err(Error::UnclosedBracket));
     assert_eq!("tag > ".parse::<Selector>(),          Err(Error::NoOtherSideCombinator));
     assert_eq!("[ attr == ]".parse::<Selector>(),     Err(Error::BadChar));
     assert_eq!("[ attr = ]".parse::<Selector>(),      Err(Error::BadChar));
@@ -146,3 +238,675 @@ fn selector_erorrs() {
     assert_eq!("tag.class=.cls".parse::<Selector>(),  Err(Error::BadChar));
     assert_eq!("tag.class].cls".parse::<Selector>(),  Err(Error::BadChar));
 }
+
+#[test]
+fn node_constructors() {
+    let stack = [
+        ParsedNode::new("tag3"),
+        ParsedNode::new("tag2"),
+        ParsedNode::new("tag")
+            .with_class("class").with_class("cls").with_class("c")
+            .with_attr("id", "id")
+            .with_attr("attr", "val"),
+    ];
+
+    assert_eq!(stack[2], ParsedNode {
+        tag: String::from("tag"),
+        attributes: HashMap::from([
+            (String::from("class"), String::from("class cls c")),
+            (String::from("id"),    String::from("id")),
+            (String::from("attr"),  String::from("val"))
+        ]),
+        ..Default::default()
+    });
+    assert_eq!(stack[0], ParsedNode { tag: String::from("tag3"), ..Default::default() });
+    assert!("tag3 > tag2 > tag#id.class.cls.c[attr=val]".parse::<CommaSeparated<Selector>>().unwrap().match_node(&stack));
+}
+
+#[test]
+fn namespaced_attributes() {
+    assert_eq!("[xml:lang=en]".parse(),
+        Ok(Selector {
+            attributes: HashMap::from([("xml:lang".to_string(), "en".to_string().into())]),
+            ..Default::default()
+        })
+    );
+    assert_eq!("tag[data.value='1'][a#b]".parse(),
+        Ok(Selector {
+            tag: "tag".to_string().into(),
+            attributes: HashMap::from([
+                ("data.value".to_string(), "1".to_string().into()),
+                ("a#b".to_string(), None),
+            ]),
+            ..Default::default()
+        })
+    );
+
+    let stack = [ParsedNode::new("tag").with_attr("xml:lang", "en").with_attr("data.value", "1")];
+    assert!("tag[xml:lang=en]".parse::<CommaSeparated<Selector>>().unwrap().match_node(&stack));
+    assert!("[data.value=1]".parse::<CommaSeparated<Selector>>().unwrap().match_node(&stack));
+    assert!(!"[xml:lang=fr]".parse::<CommaSeparated<Selector>>().unwrap().match_node(&stack));
+}
+
+#[test]
+fn punctuation_in_brackets() {
+    // `.` and `#` inside brackets don't start a class or id
+    assert_eq!("[a.b]".parse(),
+        Ok(Selector {
+            attributes: HashMap::from([("a.b".to_string(), None)]),
+            ..Default::default()
+        })
+    );
+    assert_eq!("[a#b]".parse(),
+        Ok(Selector {
+            attributes: HashMap::from([("a#b".to_string(), None)]),
+            ..Default::default()
+        })
+    );
+    // but they do after the bracket is closed
+    assert_eq!("[a].b#c".parse(),
+        Ok(Selector {
+            id: "c".to_string().into(),
+            classes: HashSet::from(["b".to_string()]),
+            attributes: HashMap::from([("a".to_string(), None)]),
+            ..Default::default()
+        })
+    );
+}
+
+#[test]
+fn not_pseudo_class() {
+    use xml_skimmer::selector::SelectorParseError as Error;
+
+    assert_eq!("li:not(.done)".parse(),
+        Ok(Selector {
+            tag: "li".to_string().into(),
+            not: vec![Selector { classes: HashSet::from(["done".to_string()]), ..Default::default() }],
+            ..Default::default()
+        })
+    );
+    assert_eq!(":not(#a):not(b)[c]".parse(),
+        Ok(Selector {
+            attributes: HashMap::from([("c".to_string(), None)]),
+            not: vec![
+                Selector { id: "a".to_string().into(), ..Default::default() },
+                Selector { tag: "b".to_string().into(), ..Default::default() },
+            ],
+            ..Default::default()
+        })
+    );
+
+    assert_eq!("li:not()".parse::<Selector>(),       Err(Error::EmptyToken));
+    assert_eq!("li:not".parse::<Selector>(),         Err(Error::BadPseudoClassArgument));
+    assert_eq!("li:not(.a.b)".parse::<Selector>(),   Err(Error::BadPseudoClassArgument));
+    assert_eq!("li:not(a > b)".parse::<Selector>(),  Err(Error::BadPseudoClassArgument));
+    assert_eq!("li:not(.a".parse::<Selector>(),      Err(Error::UnclosedParenthesis));
+    assert_eq!("li:nope".parse::<Selector>(),        Err(Error::UnknownPseudoClass));
+    assert_eq!("li:".parse::<Selector>(),            Err(Error::EmptyToken));
+
+    let mut found = vec![];
+    xml_skimmer::skim_xml(r#"
+        <ul>
+            <li id="1" class="done"/>
+            <li id="2"/>
+            <li id="3" class="todo done"/>
+            <li id="4" class="todo"/>
+        </ul>
+    "#, HashMap::from([
+        ("ul > li:not(.done)", |node: &ParsedNode| found.push(node.attributes["id"].clone()))
+    ])).unwrap();
+    found.sort();
+    assert_eq!(found, ["2", "4"]);
+}
+
+#[test]
+fn child_pseudo_classes() {
+    assert_eq!(
+        Selector::from_str("li:first-child:only-of-type").unwrap(),
+        Selector {
+            tag: Some("li".to_string()),
+            pseudo_classes: vec![PseudoClass::FirstChild, PseudoClass::OnlyOfType],
+            ..Default::default()
+        }
+    );
+    assert_eq!(Selector::from_str(":only-child(a)"), Err(SelectorParseError::BadPseudoClassArgument));
+    assert_eq!(Selector::from_str("a:not(:last-child)"), Err(SelectorParseError::BadPseudoClassArgument));
+
+    // The stack does not know the siblings of a node
+    assert!(!Selector::from_str("li:only-child").unwrap().matches_path(&node_path!["ul", "li"]));
+    // Descendants are matched even when the first candidate ancestor doesn't lead to a match
+    assert!(Selector::from_str("a > b c").unwrap().matches_path(&node_path!["a", "b", "a", "c"]));
+}
+
+#[test]
+fn text_pseudo_classes() {
+    assert_eq!(
+        Selector::from_str(r#"button:text(" OK ")"#).unwrap().pseudo_classes,
+        [PseudoClass::Text("OK".to_string())]
+    );
+    assert_eq!(
+        Selector::from_str(r#"b:exact-text(' say \'hi\' ')"#).unwrap().pseudo_classes,
+        [PseudoClass::ExactText(" say 'hi' ".to_string())]
+    );
+    assert_eq!(Selector::from_str("b:text(OK)"), Err(SelectorParseError::BadPseudoClassArgument));
+    assert_eq!(Selector::from_str("b:text('OK' x)"), Err(SelectorParseError::BadPseudoClassArgument));
+    assert_eq!(Selector::from_str("b:text"), Err(SelectorParseError::BadPseudoClassArgument));
+    assert_eq!(Selector::from_str("b:text('OK)"), Err(SelectorParseError::UnclosedParenthesis));
+}
+
+#[test]
+fn parenthesized_arguments() {
+    // Parenthesis inside quotes don't close the argument
+    assert_eq!(
+        Selector::from_str(r#"b:text("a)b"):first-child"#).unwrap().pseudo_classes,
+        [PseudoClass::Text("a)b".to_string()), PseudoClass::FirstChild]
+    );
+    assert_eq!(
+        Selector::from_str(r#"b:exact-text('(x')"#).unwrap().pseudo_classes,
+        [PseudoClass::ExactText("(x".to_string())]
+    );
+    // Nested parenthesis, and commas and parenthesis inside quotes of a nested argument
+    let sel = Selector::from_str(r#"li:is(:nth-child(odd):text("x, (y"), b:not(.c))"#).unwrap();
+    assert_eq!(sel.is[0].0.len(), 2);
+    assert_eq!(
+        sel.is[0].0[0].pseudo_classes,
+        [PseudoClass::NthChild(NthExpr { a: 2, b: 1 }), PseudoClass::Text("x, (y".to_string())]
+    );
+    assert_eq!(sel.is[0].0[1].not.len(), 1);
+
+    assert_eq!(Selector::from_str("li:is(:nth-child(odd)"), Err(SelectorParseError::UnclosedParenthesis));
+    assert_eq!(Selector::from_str(r#"b:text(")")"#).unwrap().pseudo_classes, [PseudoClass::Text(")".to_string())]);
+}
+
+#[test]
+fn compile_and_match_in() {
+    let xml = "<list><div class='item' id='a'/><div class='item'><p/></div></list>";
+    assert!(Selector::compile("div.item").unwrap().matches_in(xml).unwrap());
+    assert!(Selector::compile("list > div.item#a").unwrap().matches_in(xml).unwrap());
+    assert!(Selector::compile("div.item p").unwrap().matches_in(xml).unwrap());
+    assert!(!Selector::compile("div.other").unwrap().matches_in(xml).unwrap());
+    assert!(!Selector::compile("p > div").unwrap().matches_in(xml).unwrap());
+    assert_eq!(Selector::compile("div.item"), "div.item".parse::<Selector>());
+
+    // Parsing stops at the first match
+    assert_eq!(Selector::compile("div").unwrap().matches_in("<div><unclosed>"), Ok(true));
+    assert!(matches!(Selector::compile("p").unwrap().matches_in("<div><unclosed>"), Err(SkimError::UnclosedNode)));
+    assert_eq!(Selector::compile("> div").unwrap().matches_in(xml), Err(SkimError::BadSelector(SelectorParseError::NoScope)));
+    assert_eq!(Selector::compile(""), Err(SelectorParseError::EmptyString));
+}
+
+#[test]
+fn match_failures() {
+    let explain = |sel: &str, path: &[ParsedNode]| Selector::from_str(sel).unwrap().match_explain(path);
+    let string = |s: &str| s.to_string();
+
+    assert_eq!(explain("li", &node_path!["li"]), Ok(()));
+    assert_eq!(explain("li", &[]), Err(MatchFailure::EmptyStack));
+    assert_eq!(explain("li", &node_path!["ul"]), Err(MatchFailure::Tag { expected: string("li"), found: string("ul") }));
+    assert_eq!(explain("#a", &node_path!["li" #b]), Err(MatchFailure::Id { expected: string("a"), found: Some(string("b")) }));
+    assert_eq!(explain("#a", &node_path!["li"]), Err(MatchFailure::Id { expected: string("a"), found: None }));
+    assert_eq!(explain("li.x.y", &node_path!["li" .x]), Err(MatchFailure::MissingClass(string("y"))));
+    assert_eq!(explain("li[lang]", &node_path!["li"]), Err(MatchFailure::MissingAttribute(string("lang"))));
+    assert_eq!(explain("li[lang=en]", &node_path!["li"]), Err(MatchFailure::MissingAttribute(string("lang"))));
+    assert_eq!(
+        explain("li[lang=en]", &node_path!["li" [lang = "fr"]]),
+        Err(MatchFailure::AttributeValue { name: string("lang"), expected: string("en"), found: string("fr") })
+    );
+    assert_eq!(explain("li:not(.x):not(.y)", &node_path!["li" .y]), Err(MatchFailure::Not(1)));
+    assert_eq!(explain("li:is(.x, .y)", &node_path!["li" .z]), Err(MatchFailure::Is(0)));
+    assert_eq!(explain("li:where(.x)", &node_path!["li" .z]), Err(MatchFailure::Where(0)));
+    assert_eq!(explain("li:first-child", &node_path!["li"]), Err(MatchFailure::PseudoClass(PseudoClass::FirstChild)));
+    // The first failing condition is given, in the order the selector is checked
+    assert_eq!(explain("li.x[a]", &node_path!["ul"]), Err(MatchFailure::Tag { expected: string("li"), found: string("ul") }));
+
+    // Combinators
+    let path = node_path!["list", "ul" .menu, "li"];
+    assert_eq!(explain("list ul > li", &path), Ok(()));
+    assert_eq!(explain("ol > li", &path), Err(MatchFailure::Combinator {
+        combinator: Combinator::Child,
+        depth: 2,
+        reason: Box::new(MatchFailure::Tag { expected: string("ol"), found: string("ul") })
+    }));
+    // The closest ancestor is explained
+    assert_eq!(explain("ul.other li", &path), Err(MatchFailure::Combinator {
+        combinator: Combinator::Descendant,
+        depth: 2,
+        reason: Box::new(MatchFailure::MissingClass(string("other")))
+    }));
+    assert_eq!(explain("table > ul > li", &path), Err(MatchFailure::Combinator {
+        combinator: Combinator::Child,
+        depth: 2,
+        reason: Box::new(MatchFailure::Combinator {
+            combinator: Combinator::Child,
+            depth: 1,
+            reason: Box::new(MatchFailure::Tag { expected: string("table"), found: string("list") })
+        })
+    }));
+    assert_eq!(explain("list > li", &node_path!["li"]), Err(MatchFailure::Combinator {
+        combinator: Combinator::Child,
+        depth: 0,
+        reason: Box::new(MatchFailure::EmptyStack)
+    }));
+    assert_eq!(explain("ul + li", &path), Err(MatchFailure::Combinator {
+        combinator: Combinator::NextSibling,
+        depth: 2,
+        reason: Box::new(MatchFailure::UnknownSiblings)
+    }));
+
+    // Agrees with match_node
+    for sel in ["li", "ul li", "ul > li", "list li", "list > li", "ul.menu li", "list ul.x li", "list > ul > li", "li:not(li)"] {
+        assert_eq!(explain(sel, &path).is_ok(), Selector::from_str(sel).unwrap().match_node(&path), "{sel}");
+    }
+    assert_eq!(
+        MatchFailure::AttributeValue { name: string("lang"), expected: string("en"), found: string("fr") }.to_string(),
+        r#"Expected attribute lang="en", found "fr""#
+    );
+}
+
+#[test]
+fn display() {
+    let group = "a, b.c > d".parse::<CommaSeparated<Selector>>().unwrap();
+    assert_eq!(group.to_string(), "a, b.c > d");
+    assert_eq!(group.to_string().parse::<CommaSeparated<Selector>>().unwrap(), group);
+
+    // Every part of a selector can be parsed again into an equal selector
+    for selector in [
+        "li",
+        "ul#menu > li.b.a[lang=en][hidden]:not(.x):not(#y)",
+        "a b + c ~ d",
+        "> li:first-child",
+        "atom|entry > atom|title",
+        r#"td[title='say "hi" \\ bye']"#,
+        r#"b:text(" a)b "):exact-text('\'x\'')"#,
+        "li:nth-child(2n+1):nth-last-child(-n+3):nth-of-type(odd):nth-last-of-type(4):only-child:only-of-type:last-child",
+        "div:is(.a, p#b):where(h1, h2.x, :scope)",
+        "section :is(:nth-child(even), [x=\"]\"])",
+    ] {
+        let group = selector.parse::<CommaSeparated<Selector>>().unwrap();
+        let written = group.to_string();
+        assert_eq!(written.parse::<CommaSeparated<Selector>>().as_ref(), Ok(&group), "{selector} was written as {written}");
+    }
+
+    assert_eq!(Selector::from_str("> li").unwrap().to_string(), ":scope > li");
+    assert_eq!(Selector::from_str("a   >b").unwrap().to_string(), "a > b");
+    assert_eq!(Selector::from_str(r#"[v=a]:text(' "\\ ')"#).unwrap().to_string(), r#"[v="a"]:text("\"\\")"#);
+    assert_eq!(CommaSeparated::<Selector>(vec![]).to_string(), "");
+}
+
+#[test]
+fn nth_expressions() {
+    assert_eq!(NthExpr::from_str("odd"), Ok(NthExpr { a: 2, b: 1 }));
+    assert_eq!(NthExpr::from_str(" EVEN "), Ok(NthExpr { a: 2, b: 0 }));
+    assert_eq!(NthExpr::from_str("3"), Ok(NthExpr { a: 0, b: 3 }));
+    assert_eq!(NthExpr::from_str("-n + 3"), Ok(NthExpr { a: -1, b: 3 }));
+    assert_eq!(NthExpr::from_str("n"), Ok(NthExpr { a: 1, b: 0 }));
+    assert_eq!(NthExpr::from_str("3n-1"), Ok(NthExpr { a: 3, b: -1 }));
+    assert_eq!(NthExpr::from_str("2n1"), Err(SelectorParseError::BadPseudoClassArgument));
+    assert_eq!(NthExpr::from_str("x"), Err(SelectorParseError::BadPseudoClassArgument));
+
+    let first_three = NthExpr { a: -1, b: 3 };
+    assert_eq!((1..=5).filter(|&p| first_three.matches(p)).collect::<Vec<_>>(), [1, 2, 3]);
+    let every_third = NthExpr { a: 3, b: -1 };
+    assert_eq!((1..=9).filter(|&p| every_third.matches(p)).collect::<Vec<_>>(), [2, 5, 8]);
+
+    assert_eq!(
+        Selector::from_str("li:nth-last-of-type(2)").unwrap().pseudo_classes,
+        [PseudoClass::NthLastOfType(NthExpr { a: 0, b: 2 })]
+    );
+    assert_eq!(Selector::from_str("li:nth-child"), Err(SelectorParseError::BadPseudoClassArgument));
+    assert_eq!(Selector::from_str("li:nth-child()"), Err(SelectorParseError::EmptyToken));
+}
+
+#[test]
+fn unquoted_attribute_values() {
+    assert_eq!(Selector::from_str("[d=a b]"), Err(SelectorParseError::UnquotedSpecialChar));
+    assert_eq!(Selector::from_str("[d=a/b]"), Err(SelectorParseError::UnquotedSpecialChar));
+    assert_eq!(Selector::from_str("[d=@]"), Err(SelectorParseError::UnquotedSpecialChar));
+    assert_eq!(
+        Selector::from_str("[d=\"a b\"]"),
+        Ok(Selector { attributes: HashMap::from([("d".to_string(), Some("a b".to_string()))]), ..Default::default() })
+    );
+    assert_eq!(
+        Selector::from_str("[d='a]b']"),
+        Ok(Selector { attributes: HashMap::from([("d".to_string(), Some("a]b".to_string()))]), ..Default::default() })
+    );
+    assert_eq!(
+        Selector::from_str("[d = a-b_c ]"),
+        Ok(Selector { attributes: HashMap::from([("d".to_string(), Some("a-b_c".to_string()))]), ..Default::default() })
+    );
+}
+
+#[test]
+fn unicode_names() {
+    assert_eq!(
+        Selector::from_str("café > 名前#идент.クラス[属性=値]"),
+        Ok(Selector {
+            tag: Some("名前".to_string()),
+            id: Some("идент".to_string()),
+            classes: HashSet::from(["クラス".to_string()]),
+            attributes: HashMap::from([("属性".to_string(), Some("値".to_string()))]),
+            parent: Some((Box::new(Selector { tag: Some("café".to_string()), ..Default::default() }), Combinator::Child)),
+            ..Default::default()
+        })
+    );
+
+    let mut found = vec![];
+    xml_skimmer::skim_xml_one(
+        r#"<café><名前 id="идент" class="クラス x" 属性="値">テキスト</名前><名前/></café>"#,
+        "café > 名前#идент.クラス[属性=値], café > 名前:not([属性])",
+        |node| found.push(node.clone())
+    ).unwrap();
+    assert_eq!(found, [
+        ParsedNode::new("名前").with_attr("id", "идент").with_class("クラス").with_class("x").with_attr("属性", "値"),
+        ParsedNode::new("名前"),
+    ]);
+}
+
+#[test]
+fn any_of_classes() {
+    let any = "div.a, div.b".parse::<CommaSeparated<Selector>>().unwrap();
+    let all = "div.a.b".parse::<CommaSeparated<Selector>>().unwrap();
+
+    let b = node_path!["div" .b];
+    let a_b = node_path!["div" .b .a];
+    let c = node_path!["div" .c];
+    let span = node_path!["span" .a];
+
+    assert!(any.match_node(&b));
+    assert!(any.match_node(&a_b));
+    assert!(!any.match_node(&c));
+    assert!(!any.match_node(&span));
+
+    assert!(!all.match_node(&b));
+    assert!(all.match_node(&a_b));
+}
+
+#[test]
+fn class_includes_attribute() {
+    let class = Selector::from_str("div.a").unwrap();
+    let includes = Selector::from_str("div[class~=a]").unwrap();
+    assert_eq!(class, includes);
+    assert_eq!(Selector::from_str("[ class ~= 'a' ]"), Selector::from_str(".a"));
+
+    let nodes = |xml_src: &str| {
+        let mut nodes = vec![];
+        xml_skimmer::skim_xml_one(xml_src, "div", |node| nodes.push(node.clone())).unwrap();
+        nodes
+    };
+    // Classes are separated by any whitespace, and empty classes are skipped
+    let nodes = nodes("<r><div class='a b'/><div class='b a'/><div class='\ta\n  b'/><div class='ab'/><div class=''/><div class=' '/><div/></r>");
+    let matching = |sel: &Selector| nodes.iter().map(|node| sel.matches_path(std::slice::from_ref(node))).collect::<Vec<_>>();
+    assert_eq!(matching(&class), [true, true, true, false, false, false, false]);
+    assert_eq!(matching(&includes), matching(&class));
+    assert_eq!(nodes[2].class_list(), HashSet::from(["a", "b"]));
+    assert!(nodes[4].class_list().is_empty());
+    assert!(nodes[5].class_list().is_empty());
+
+    // Only the class attribute, and only a single class
+    assert_eq!(Selector::from_str("[title~=a]"), Err(SelectorParseError::UnknownPrefix));
+    assert_eq!(Selector::from_str("[class~='']"), Err(SelectorParseError::EmptyToken));
+    assert_eq!(Selector::from_str("[class~='a b']"), Err(SelectorParseError::BadChar));
+}
+
+#[test]
+fn numeric_comparisons() {
+    let sel = Selector::from_str("item[qty>=3]").unwrap();
+    assert_eq!(sel.comparisons, [("qty".to_string(), Comparison::GreaterOrEqual, 3.0)]);
+    assert!(sel.matches_path(&node_path!["item"[qty = "5"]]));
+    assert!(sel.matches_path(&node_path!["item"[qty = "3"]]));
+    assert!(!sel.matches_path(&node_path!["item"[qty = "2"]]));
+    // Not a number, or no attribute
+    assert!(!sel.matches_path(&node_path!["item"[qty = "abc"]]));
+    assert!(!sel.matches_path(&node_path!["item"[qty = ""]]));
+    assert!(!sel.matches_path(&node_path!["item"]));
+
+    let matches = |sel: &str, value: &'static str| Selector::from_str(sel).unwrap().matches_path(&[ParsedNode::new("a").with_attr("n", value)]);
+    assert!(matches("[n>5]", "5.5"));
+    assert!(!matches("[n>5]", "5"));
+    assert!(matches("[n<5]", "-1"));
+    assert!(matches("[ n <= 2.5 ]", " 2.5 "));
+    assert!(!matches("[n<=2.5]", "2.6"));
+    // Together with a child combinator, and other attributes
+    let sel = Selector::from_str("list > item[price<10][sale][price>=1]").unwrap();
+    assert!(sel.matches_path(&node_path!["list", "item"[price = "9.99"][sale = ""]]));
+    assert!(!sel.matches_path(&node_path!["list", "item"[price = "0.5"][sale = ""]]));
+    assert_eq!(sel.to_string(), "list > item[sale][price<10][price>=1]");
+    assert_eq!(sel.specificity(), (0, 3, 2));
+
+    assert_eq!(
+        Selector::from_str("item[qty>=3]").unwrap().match_explain(&node_path!["item"[qty = "2"]]),
+        Err(MatchFailure::Comparison { name: "qty".to_string(), comparison: Comparison::GreaterOrEqual, number: 3.0, found: "2".to_string() })
+    );
+    assert_eq!(Selector::from_str("[qty>x]"), Err(SelectorParseError::BadNumber));
+    assert_eq!(Selector::from_str("[qty>]"), Err(SelectorParseError::BadNumber));
+    assert_eq!(Selector::from_str("[>3]"), Err(SelectorParseError::EmptyToken));
+    assert_eq!(Selector::from_str("[qty>3"), Err(SelectorParseError::UnclosedBracket));
+}
+
+#[test]
+fn empty_attribute_values() {
+    let exists = Selector::from_str("[attr]").unwrap();
+    let empty = Selector::from_str("[attr=\"\"]").unwrap();
+    assert_eq!(exists.attributes["attr"], None);
+    assert_eq!(empty.attributes["attr"].as_deref(), Some(""));
+    assert_eq!(Selector::from_str("[attr='']"), Selector::from_str("[attr=\"\"]"));
+
+    // Which of attr="", attr (boolean) and no attr are matched when skimming
+    let matching = |sel: &str| {
+        let mut ids = vec![];
+        xml_skimmer::skim_xml_one(r#"<r><a id="1" attr=""/><a id="2" attr/><a id="3"/></r>"#, sel, |node| ids.push(node.attributes["id"].clone())).unwrap();
+        ids
+    };
+    assert_eq!(matching("[attr]"), ["1", "2"]);
+    assert_eq!(matching("[attr=\"\"]"), ["1"]);
+    // The last one of a repeated attribute is the one that is matched
+    let mut matched = 0;
+    xml_skimmer::skim_xml_one(r#"<r><a attr="x" attr/><a attr attr=""/></r>"#, "[attr=\"\"]", |_| matched += 1).unwrap();
+    assert_eq!(matched, 1);
+    // Copied nodes also know which attributes are boolean
+    assert!(empty.matches_path(&node_path!["a"[attr = ""]]));
+    assert!(!empty.matches_path(&[ParsedNode::new("a").with_boolean_attr("attr")]));
+    assert!(exists.matches_path(&[ParsedNode::new("a").with_boolean_attr("attr")]));
+    let mut nodes = vec![];
+    xml_skimmer::skim_xml_one(r#"<r><a attr=""/><a attr/></r>"#, "a", |node| nodes.push(node.clone())).unwrap();
+    assert_eq!(nodes.iter().map(|node| empty.matches_path(std::slice::from_ref(node))).collect::<Vec<_>>(), [true, false]);
+}
+
+#[test]
+fn namespace_prefixes() {
+    assert_eq!(Selector::from_str("atom|entry.a"), Ok(Selector {
+        tag: Some("entry".to_string()),
+        namespace: Some(Namespace::Prefix("atom".to_string())),
+        classes: HashSet::from(["a".to_string()]),
+        ..Default::default()
+    }));
+    assert_eq!(Selector::from_str("atom|#x").unwrap().namespace, Some(Namespace::Prefix("atom".to_string())));
+    // Only before the tag
+    assert_eq!(Selector::from_str("|entry"), Err(SelectorParseError::UnknownPrefix));
+    assert_eq!(Selector::from_str("a|b|c"), Err(SelectorParseError::UnknownPrefix));
+    assert_eq!(Selector::from_str(".a|b"), Err(SelectorParseError::UnknownPrefix));
+
+    // Resolved in parents and arguments too
+    let mut group = "atom|feed > entry:not(dc|creator), x|y".parse::<CommaSeparated<Selector>>().unwrap();
+    group.resolve_namespaces(&HashMap::from([
+        ("atom".to_string(), "urn:atom".to_string()),
+        ("dc".to_string(), "urn:dc".to_string()),
+    ]));
+    let (parent, _) = group.0[0].parent.as_ref().unwrap();
+    assert_eq!(parent.namespace, Some(Namespace::Uri("urn:atom".to_string())));
+    assert_eq!(group.0[0].not[0].namespace, Some(Namespace::Uri("urn:dc".to_string())));
+    assert_eq!(group.0[1].namespace, Some(Namespace::Prefix("x".to_string())));
+
+    let path = node_path!["feed" [xmlns = "urn:atom"], "entry"];
+    assert!(group.match_node(&path));
+    assert!(!group.match_node(&node_path!["feed", "entry"]));
+}
+
+#[test]
+fn matched_indices() {
+    let group = "ul > li, li.done, #menu li".parse::<CommaSeparated<Selector>>().unwrap();
+
+    let done = node_path!["ol", "li" .done];
+    assert_eq!(group.match_all(&done), [1]);
+    assert_eq!(group.match_first(&done), Some(1));
+
+    let in_menu = node_path!["ul" #menu, "li"];
+    assert_eq!(group.match_all(&in_menu), [0, 2]);
+    assert_eq!(group.match_first(&in_menu), Some(0));
+    assert!(group.match_node(&in_menu));
+
+    let none = node_path!["ol", "li"];
+    assert_eq!(group.match_all(&none), Vec::<usize>::new());
+    assert_eq!(group.match_first(&none), None);
+    assert!(!group.match_node(&none));
+}
+
+#[test]
+fn is_and_where() {
+    let sel = Selector::from_str("div:is(.a, .b)").unwrap();
+    assert_eq!(sel.is, [CommaSeparated(vec![
+        Selector { classes: HashSet::from(["a".to_string()]), ..Default::default() },
+        Selector { classes: HashSet::from(["b".to_string()]), ..Default::default() },
+    ])]);
+    assert!(sel.match_node(&[ParsedNode::new("div").with_class("b")]));
+    assert!(sel.match_node(&[ParsedNode::new("div").with_class("a").with_class("c")]));
+    assert!(!sel.match_node(&[ParsedNode::new("div").with_class("c")]));
+    assert!(!sel.match_node(&[ParsedNode::new("span").with_class("a")]));
+
+    let sel = Selector::from_str("section :where(h1, h2#title):is([lang])").unwrap();
+    let stack = [ParsedNode::new("section"), ParsedNode::new("h2").with_attr("id", "title").with_attr("lang", "en")];
+    assert!(sel.match_node(&stack));
+    assert!(!sel.match_node(&[ParsedNode::new("section"), ParsedNode::new("h2").with_attr("lang", "en")]));
+
+    // Commas inside the parenthesis don't split the group
+    assert_eq!("a:is(.x, .y), b".parse::<CommaSeparated<Selector>>().unwrap().0.len(), 2);
+    // Parenthesis inside the argument
+    assert_eq!(
+        Selector::from_str("li:is(:nth-child(2n+1), .x)").unwrap().is[0].0[0].pseudo_classes,
+        [PseudoClass::NthChild(NthExpr { a: 2, b: 1 })]
+    );
+
+    assert_eq!(Selector::from_str("div:is(ul li)"), Err(SelectorParseError::BadPseudoClassArgument));
+    assert_eq!(Selector::from_str("div:is()"), Err(SelectorParseError::EmptyToken));
+    assert_eq!(Selector::from_str("div:where"), Err(SelectorParseError::BadPseudoClassArgument));
+    assert_eq!(Selector::from_str("div:is(.a, (.b)"), Err(SelectorParseError::UnclosedParenthesis));
+}
+
+#[test]
+fn specificity() {
+    assert_eq!(Selector::from_str("div").unwrap().specificity(), (0, 0, 1));
+    assert_eq!(Selector::from_str("div.a[b]#c").unwrap().specificity(), (1, 2, 1));
+    assert_eq!(Selector::from_str("ul > li:first-child").unwrap().specificity(), (0, 1, 2));
+    assert_eq!(Selector::from_str("div:not(#x)").unwrap().specificity(), (1, 0, 1));
+    // :is() counts its most specific selector, :where() counts nothing
+    assert_eq!(Selector::from_str("div:is(.a, #b)").unwrap().specificity(), (1, 0, 1));
+    assert_eq!(Selector::from_str("div:where(.a, #b)").unwrap().specificity(), (0, 0, 1));
+    assert_eq!(Selector::from_str(":where(div.a)").unwrap().specificity(), (0, 0, 0));
+}
+
+#[test]
+fn partial_parse() {
+    assert_eq!(Selector::parse_partial("tag.a[b='c'] > d").1, "");
+    assert_eq!(Selector::parse_partial("  tag").0, Ok(Selector { tag: Some("tag".to_string()), ..Default::default() }));
+
+    let (result, remaining) = Selector::parse_partial("section > p[d=a b] .x");
+    assert_eq!(result, Err(SelectorParseError::UnquotedSpecialChar));
+    assert_eq!(remaining, "] .x");
+
+    let (result, remaining) = Selector::parse_partial("a > > b");
+    assert_eq!(result, Err(SelectorParseError::UnknownPrefix));
+    assert_eq!(remaining, " b");
+
+    assert_eq!(Selector::parse_partial(""), (Err(SelectorParseError::EmptyString), ""));
+}
+
+#[test]
+fn escaped_quotes() {
+    let value = |s: &str| Selector::from_str(s).map(|sel| sel.attributes["title"].clone());
+    assert_eq!(value(r#"[title="say \"hi\""]"#), Ok(Some(r#"say "hi""#.to_string())));
+    assert_eq!(value(r#"[title='it\'s']"#), Ok(Some("it's".to_string())));
+    assert_eq!(value(r#"[title="back\\slash"]"#), Ok(Some(r"back\slash".to_string())));
+    assert_eq!(value(r#"[title="ends with \\"]"#), Ok(Some(r"ends with \".to_string())));
+    assert_eq!(value(r#"[title="\""#), Err(SelectorParseError::UnclosedString));
+
+    // Escaped quotes don't end the string when splitting groups
+    let group = r#"a[title="x\", y"], b:is([title='\')'])"#.parse::<CommaSeparated<Selector>>().unwrap();
+    assert_eq!(group.0.len(), 2);
+    assert_eq!(group.0[0].attributes["title"], Some(r#"x", y"#.to_string()));
+    assert_eq!(group.0[1].is[0].0[0].attributes["title"], Some("')".to_string()));
+
+    // Matches the decoded value of the XML attribute
+    let stack = [ParsedNode::new("q").with_attr("title", r#"say "hi""#)];
+    assert!(Selector::from_str(r#"q[title="say \"hi\""]"#).unwrap().match_node(&stack));
+}
+
+#[test]
+fn explain() {
+    use xml_skimmer::selector::explain_selector;
+
+    assert_eq!(explain_selector("div").unwrap(), "match <div>");
+    assert_eq!(
+        explain_selector("body > main article p.note.big[lang=en]").unwrap(),
+        "match <p> with class 'big' with class 'note' with attribute lang = 'en' \
+         that is a descendant of <article> \
+         that is a descendant of <main> \
+         that is a direct child of <body>"
+    );
+    assert_eq!(
+        explain_selector("ul > li:nth-child(2n+1):not(.x)").unwrap(),
+        "match <li> not any element with class 'x' that is the child at position 2n+1 that is a direct child of <ul>"
+    );
+    assert_eq!(
+        explain_selector("#a:is(p, .b)").unwrap(),
+        "match any element with id 'a' that is any of (<p> or any element with class 'b')"
+    );
+    assert_eq!(explain_selector("a > "), Err(SelectorParseError::NoOtherSideCombinator));
+
+    assert_eq!(NthExpr { a: -1, b: 3 }.to_string(), "-n+3");
+    assert_eq!(NthExpr { a: 3, b: -1 }.to_string(), "3n-1");
+    assert_eq!(NthExpr { a: 0, b: 2 }.to_string(), "2");
+    assert_eq!(NthExpr { a: 2, b: 0 }.to_string(), "2n");
+}
+
+#[test]
+fn synthetic_paths() {
+    let path = node_path!["html", "body" #main .dark, "ul" .menu [role = "list"], "li" .item .active,];
+    assert_eq!(path[1], ParsedNode::new("body").with_attr("id", "main").with_class("dark"));
+    assert_eq!(path[2].attributes["role"], "list");
+
+    let matches = |s: &str| Selector::from_str(s).unwrap().matches_path(&path);
+    assert!(matches("li"));
+    assert!(matches("#main ul[role=list] > li.item.active"));
+    assert!(matches("html > body.dark li"));
+    assert!(!matches("body > li"));
+    assert!(!matches("ul"));
+    assert!(!Selector::from_str("li").unwrap().matches_path(&node_path![]));
+}
+
+#[test]
+fn leading_combinators() {
+    let scope = || Selector { pseudo_classes: vec![PseudoClass::Scope], ..Default::default() };
+
+    assert_eq!(Selector::from_str("> li"), Ok(Selector {
+        tag: Some("li".to_string()),
+        parent: Some((Box::new(scope()), Combinator::Child)),
+        ..Default::default()
+    }));
+    assert_eq!(Selector::from_str("+ p.note"), Ok(Selector {
+        tag: Some("p".to_string()),
+        classes: HashSet::from(["note".to_string()]),
+        parent: Some((Box::new(scope()), Combinator::NextSibling)),
+        ..Default::default()
+    }));
+    assert_eq!(Selector::from_str(">li"), Selector::from_str(":scope > li"));
+    assert_eq!(Selector::from_str("~ p"), Selector::from_str(":scope ~ p"));
+    assert_eq!(Selector::from_str("h2 + p"), Ok(Selector {
+        tag: Some("p".to_string()),
+        parent: Some((Box::new(Selector::from_str("h2").unwrap()), Combinator::NextSibling)),
+        ..Default::default()
+    }));
+
+    assert_eq!(Selector::from_str(">"), Err(SelectorParseError::NoOtherSideCombinator));
+    assert_eq!(Selector::from_str("> > li"), Err(SelectorParseError::UnknownPrefix));
+    assert_eq!(Selector::from_str("a + ~ b"), Err(SelectorParseError::UnknownPrefix));
+}
@@ -0,0 +1,377 @@
+use xml_skimmer::{ParsedNode, SkimOptions, tree::{canonicalize, parse_tree, parse_tree_with_options, Document, Node, NodeChild}, selector::{CommaSeparated, Selector, SelectorParseError}};
+
+fn tags<'a>(nodes: &[&'a Node]) -> Vec<&'a str> {
+    nodes.iter().map(|node| node.element.attributes.get("id").map(String::as_str).unwrap_or(&node.element.tag)).collect()
+}
+
+fn selector(s: &str) -> CommaSeparated<Selector> {
+    s.parse().unwrap()
+}
+
+#[test]
+fn build_tree() {
+    let doc = parse_tree("<a>one<b/><c>two</c></a>").unwrap();
+    assert_eq!(doc.children.len(), 1);
+    let a = doc.select(&selector("a"))[0];
+    assert_eq!(a.children.len(), 3);
+    assert_eq!(a.elements().map(|node| node.element.tag.as_str()).collect::<Vec<_>>(), ["b", "c"]);
+    assert_eq!(a.text(), "onetwo");
+
+    assert!(parse_tree("<a><b></a>").is_err());
+}
+
+#[test]
+fn child_count_pseudo_classes() {
+    let doc = parse_tree(r#"
+        <root>
+            <list id="one"><item id="only"/></list>
+            <list id="many"><item id="first"/><item id="middle"/><other id="last"/></list>
+        </root>
+    "#).unwrap();
+
+    // :first-child also matches nodes that have siblings, :only-child does not
+    assert_eq!(tags(&doc.select(&selector("item:first-child"))), ["only", "first"]);
+    assert_eq!(tags(&doc.select(&selector("item:only-child"))), ["only"]);
+    assert_eq!(tags(&doc.select(&selector("list > :last-child"))), ["only", "last"]);
+    // text between the elements is not counted
+    assert_eq!(tags(&doc.select(&selector("list:only-child"))), Vec::<&str>::new());
+    assert_eq!(tags(&doc.select(&selector(":only-of-type"))), ["root", "only", "last"]);
+    assert_eq!(tags(&doc.select(&selector("#many item:only-of-type"))), Vec::<&str>::new());
+    assert_eq!(tags(&doc.select(&selector("list:first-child item:last-child"))), ["only"]);
+
+    // the node that select() is called on does not know its siblings
+    let root = doc.select(&selector("root"))[0];
+    assert_eq!(tags(&root.select(&selector(":only-child > item"))), Vec::<&str>::new());
+    assert_eq!(tags(&root.select(&selector("root > :first-child"))), ["one"]);
+}
+
+#[test]
+fn backtracking_descendants() {
+    let doc = parse_tree("<a><b><a><c id='x'/></a></b></a>").unwrap();
+    assert_eq!(tags(&doc.select(&selector("a > b c"))), ["x"]);
+    assert_eq!(tags(&doc.select(&selector("a > b > c"))), Vec::<&str>::new());
+}
+
+#[test]
+fn nth_pseudo_classes() {
+    let doc = parse_tree(r#"
+        <ul>
+            <li id="1"/>
+            <hr id="2"/>
+            <li id="3"/>
+            <hr id="4"/>
+            <li id="5"/>
+            <li id="6"/>
+        </ul>
+    "#).unwrap();
+
+    // counts every element, not only li
+    assert_eq!(tags(&doc.select(&selector("li:nth-child(2)"))), Vec::<&str>::new());
+    assert_eq!(tags(&doc.select(&selector("li:nth-child(3)"))), ["3"]);
+    // type-scoped: the second li is the third element
+    assert_eq!(tags(&doc.select(&selector("li:nth-of-type(2)"))), ["3"]);
+    assert_eq!(tags(&doc.select(&selector("li:nth-of-type(odd)"))), ["1", "5"]);
+    assert_eq!(tags(&doc.select(&selector("hr:nth-of-type(2)"))), ["4"]);
+    assert_eq!(tags(&doc.select(&selector("ul > :nth-last-child(2)"))), ["5"]);
+    assert_eq!(tags(&doc.select(&selector("li:nth-last-of-type(-n+2)"))), ["5", "6"]);
+    assert_eq!(tags(&doc.select(&selector("ul > :nth-child(2n)"))), ["2", "4", "6"]);
+}
+
+#[test]
+fn scope_pseudo_class() {
+    let doc = parse_tree(r#"
+        <list id="outer">
+            <li id="a"/>
+            <list id="inner"><li id="b"/></list>
+            <li id="c"/>
+        </list>
+    "#).unwrap();
+
+    let outer = doc.select(&selector("#outer"))[0];
+    assert_eq!(tags(&outer.select(&selector(":scope > li"))), ["a", "c"]);
+    assert_eq!(tags(&outer.select(&selector(":scope li"))), ["a", "b", "c"]);
+    assert_eq!(tags(&outer.select(&selector(":scope > list > li"))), ["b"]);
+
+    let inner = doc.select(&selector("#inner"))[0];
+    assert_eq!(tags(&inner.select(&selector(":scope > li"))), ["b"]);
+    // The scope's ancestors are not part of the query
+    assert_eq!(tags(&inner.select(&selector("list > :scope > li"))), Vec::<&str>::new());
+
+    // The document is not an element
+    assert_eq!(tags(&doc.select(&selector(":scope > li"))), Vec::<&str>::new());
+}
+
+#[test]
+fn leading_combinators() {
+    let doc = parse_tree(r#"
+        <list id="outer">
+            <li id="a"/>
+            <h2 id="title"/>
+            <p id="first"/>
+            <p id="second"/>
+            <list id="inner"><li id="b"/></list>
+        </list>
+    "#).unwrap();
+
+    let outer = doc.select(&selector("#outer"))[0];
+    assert_eq!(tags(&outer.select(&selector("> li"))), ["a"]);
+    assert_eq!(tags(&outer.select(&selector("> list > li"))), ["b"]);
+    assert_eq!(tags(&outer.select(&selector("> h2 + p"))), ["first"]);
+    assert_eq!(tags(&outer.select(&selector("> h2 ~ p"))), ["first", "second"]);
+    assert_eq!(tags(&outer.select(&selector("li ~ p"))), ["first", "second"]);
+    // The siblings of the scope are not part of the query
+    assert_eq!(tags(&outer.select(&selector("+ p"))), Vec::<&str>::new());
+    let first = doc.select(&selector("#first"))[0];
+    assert_eq!(tags(&first.select(&selector("+ p"))), Vec::<&str>::new());
+
+    // The document is not an element
+    assert_eq!(tags(&doc.select(&selector("> list"))), Vec::<&str>::new());
+}
+
+#[test]
+fn is_with_pseudo_classes() {
+    let doc = parse_tree("<ul><li id='1'/><li id='2'/><li id='3'/></ul>").unwrap();
+    assert_eq!(tags(&doc.select(&selector("li:is(:first-child, :last-child)"))), ["1", "3"]);
+    assert_eq!(tags(&doc.select(&selector("li:where(#2, :nth-child(3))"))), ["2", "3"]);
+}
+
+#[test]
+fn text_pseudo_classes() {
+    let doc = parse_tree(r#"
+        <form>
+            <b id="direct">OK</b>
+            <b id="nested"><i id="inner">OK</i></b>
+            <b id="spaced"> OK </b>
+            <b id="mixed">O<i>-</i>K</b>
+            <button id="cancel">Cancel</button>
+        </form>
+    "#).unwrap();
+
+    // Only the direct text is matched, not the text of descendants
+    assert_eq!(tags(&doc.select(&selector("b:text('OK')"))), ["direct", "spaced", "mixed"]);
+    assert_eq!(tags(&doc.select(&selector(":text('OK')"))), ["direct", "inner", "spaced", "mixed"]);
+    assert_eq!(tags(&doc.select(&selector("b:exact-text('OK')"))), ["direct", "mixed"]);
+    assert_eq!(tags(&doc.select(&selector("b:exact-text(' OK ')"))), ["spaced"]);
+    assert_eq!(tags(&doc.select(&selector("form > :not(b):text('Cancel')"))), ["cancel"]);
+
+    let nested = doc.select(&selector("#nested"))[0];
+    assert_eq!(nested.direct_text(), "");
+    assert_eq!(nested.text(), "OK");
+    // Also matched on the node the query starts from
+    assert_eq!(tags(&nested.select(&selector(":scope > :text('OK')"))), ["inner"]);
+}
+
+#[test]
+fn leaf_pseudo_class() {
+    let options = SkimOptions { emit_comments: true, ..Default::default() };
+    let doc = parse_tree_with_options(r#"
+        <table id="table">
+            <tr id="head"><th id="name">Name</th><th id="qty"><!-- count -->Qty</th></tr>
+            <tr id="row"><td id="a">Apple</td><td id="nested"><b id="bold">3</b></td><td id="empty"/></tr>
+        </table>
+    "#, options).unwrap();
+
+    // Text and comments don't count as children
+    assert_eq!(tags(&doc.select(&selector(":leaf"))), ["name", "qty", "a", "bold", "empty"]);
+    assert_eq!(tags(&doc.select(&selector("td:leaf"))), ["a", "empty"]);
+    assert_eq!(tags(&doc.select(&selector("tr > :not(td):leaf"))), ["name", "qty"]);
+    assert_eq!(tags(&doc.select(&selector("td:not(#a):leaf, tr:leaf"))), ["empty"]);
+    assert_eq!(selector("td:leaf").to_string(), "td:leaf");
+    assert_eq!(":leaf(x)".parse::<Selector>(), Err(SelectorParseError::BadPseudoClassArgument));
+
+    // The stack of the skimmer doesn't know the children of a node
+    assert_eq!(xml_skimmer::find_first("<td>1</td>", "td:leaf"), Ok(None));
+}
+
+#[test]
+fn retain_matching_paths() {
+    let doc = parse_tree(
+        "<root><a><b><c id='1'>one<d/></c></b><e/>text</a><f><g/></f><a><c id='2'/></a></root>"
+    ).unwrap();
+    let root = doc.select(&selector("root"))[0];
+
+    let expected = parse_tree(
+        "<root><a><b><c id='1'>one<d/></c></b></a><a><c id='2'/></a></root>"
+    ).unwrap();
+    assert_eq!(root.retain(&selector("c")), *expected.select(&selector("root"))[0]);
+
+    // Only the root is left when nothing matches
+    assert_eq!(root.retain(&selector("nothing")), Node { element: root.element.clone(), children: vec![] });
+    // Structural selectors work too
+    assert_eq!(
+        root.retain(&selector(":scope > a:last-child c")),
+        *parse_tree("<root><a><c id='2'/></a></root>").unwrap().select(&selector("root"))[0]
+    );
+}
+
+#[test]
+fn boolean_attributes() {
+    let doc = parse_tree(r#"<r><b id="empty" x=""/><b id="boolean" x/><b id="none"/></r>"#).unwrap();
+    assert_eq!(tags(&doc.select(&selector("[x]"))), ["empty", "boolean"]);
+    assert_eq!(tags(&doc.select(&selector("[x=\"\"]"))), ["empty"]);
+    assert_eq!(tags(&doc.select(&selector(":not([x=''])"))), ["r", "boolean", "none"]);
+
+    let boolean = &doc.select(&selector("#boolean"))[0].element;
+    assert_eq!(boolean.attributes["x"], "");
+    assert!(boolean.boolean_attributes.contains("x"));
+}
+
+#[test]
+fn canonical_xml() {
+    let a = r#"<?xml version="1.0"?>
+        <doc b="2" a='1'>
+            <item   id="x"></item>
+            <!-- comment -->
+            <text>  some   &lt;escaped&gt;
+                text &amp; more </text>
+            <code xml:space="preserve">  keep  </code>
+            <data><![CDATA[<raw> & "data"]]></data>
+            <q title='say "hi" &amp; &lt;bye&gt;'/>
+        </doc>
+    "#;
+    let b = r#"<doc a="1" b="2"><item id="x"/><text> some &lt;escaped> text &amp; more </text><code xml:space="preserve">  keep  </code><data>&lt;raw> &amp; "data"</data><q title="say &quot;hi&quot; &amp; &lt;bye>"></q></doc>"#;
+
+    let canonical = canonicalize(a).unwrap();
+    assert_eq!(canonical, concat!(
+        r#"<doc a="1" b="2"><item id="x"/><text> some &lt;escaped&gt; text &amp; more </text>"#,
+        r#"<code xml:space="preserve">  keep  </code><data>&lt;raw&gt; &amp; "data"</data>"#,
+        r#"<q title="say &quot;hi&quot; &amp; &lt;bye>"/></doc>"#,
+    ));
+    assert_eq!(canonicalize(b).unwrap(), canonical);
+    // idempotent
+    assert_eq!(canonicalize(&canonical).unwrap(), canonical);
+
+    assert!(canonicalize("<a>").is_err());
+
+    // The spaces around inline nodes are part of the text
+    let mixed = "<p>Hello <b>big</b> world</p>";
+    assert_eq!(canonicalize(mixed).unwrap(), mixed);
+    assert_eq!(canonicalize("<p>Hello\n    <b>big</b><!-- c -->\n  world</p>").unwrap(), mixed);
+    assert_ne!(canonicalize("<p>Hello<b>big</b>world</p>").unwrap(), mixed);
+
+    // Raw attribute values are not escaped twice
+    let lazy = SkimOptions { lazy_attributes: true, ..Default::default() };
+    let doc = parse_tree_with_options(r#"<q title='&amp; &lt;'/>"#, lazy).unwrap();
+    assert_eq!(doc.to_xml(), r#"<q title="&amp; &lt;"/>"#);
+}
+
+#[test]
+fn child_with_mixed_content() {
+    let options = SkimOptions { emit_comments: true, ..Default::default() };
+    let doc = parse_tree_with_options(r#"
+        <section>
+            text before <!-- a comment -->
+            <p id="direct">text <!-- c --> <b id="bold"/> more</p>
+            <![CDATA[ cdata ]]>
+            <div><p id="nested"/></div>
+        </section>
+    "#, options).unwrap();
+
+    // the text and comments were kept in the tree
+    let section = doc.select(&selector("section"))[0];
+    assert!(section.children.iter().any(|child| matches!(child, NodeChild::Comment(_))));
+
+    assert_eq!(tags(&doc.select(&selector("section > p"))), ["direct"]);
+    assert_eq!(tags(&doc.select(&selector("section > p > b"))), ["bold"]);
+    assert_eq!(tags(&doc.select(&selector("section > div > p"))), ["nested"]);
+    assert_eq!(tags(&doc.select(&selector("section > b"))), Vec::<&str>::new());
+    assert_eq!(tags(&doc.select(&selector("section > :first-child"))), ["direct"]);
+}
+
+#[test]
+fn merged_adjacent_text() {
+    let merge = SkimOptions { merge_adjacent_text: true, ..Default::default() };
+    let texts = |doc: &Document| doc.select(&selector("a"))[0].children.clone();
+
+    // Separate runs of text by default
+    let doc = parse_tree("<a>a<!--x-->b</a>").unwrap();
+    assert_eq!(texts(&doc), [NodeChild::Text("a".to_string()), NodeChild::Text("b".to_string())]);
+
+    let doc = parse_tree_with_options("<a>a<!--x-->b</a>", merge.clone()).unwrap();
+    assert_eq!(texts(&doc), [NodeChild::Text("ab".to_string())]);
+    let doc = parse_tree_with_options("<a>a<![CDATA[b]]>c</a>", merge.clone()).unwrap();
+    assert_eq!(texts(&doc), [NodeChild::Text("abc".to_string())]);
+    assert_eq!(doc.to_xml(), "<a>abc</a>");
+
+    // Elements and kept comments are between the runs
+    let doc = parse_tree_with_options("<a>a<b/>b<!--x-->c</a>", SkimOptions { emit_comments: true, ..merge }).unwrap();
+    assert_eq!(texts(&doc), [
+        NodeChild::Text("a".to_string()),
+        NodeChild::Element(Node { element: ParsedNode::new("b"), children: vec![] }),
+        NodeChild::Text("b".to_string()),
+        NodeChild::Comment("x".to_string()),
+        NodeChild::Text("c".to_string()),
+    ]);
+}
+
+#[test]
+fn parsed_text() {
+    let doc = parse_tree("<stats><count>42</count><ratio>\n  0.25\n</ratio><total><count>1</count>0</total><name>n/a</name></stats>").unwrap();
+    let node = |sel: &str| doc.select(&selector(sel))[0];
+
+    assert_eq!(node("count").inner_text_parsed::<i32>(), Ok(42));
+    assert_eq!(node("ratio").inner_text_parsed::<f64>(), Ok(0.25));
+    // The text of the descendants is included
+    assert_eq!(node("total").inner_text_parsed::<i32>(), Ok(10));
+    assert_eq!(node("name").inner_text_parsed::<String>(), Ok("n/a".to_string()));
+
+    let error = node("name").inner_text_parsed::<i32>().unwrap_err();
+    assert_eq!(error.to_string(), "invalid digit found in string");
+    assert!(node("stats").inner_text_parsed::<bool>().is_err());
+}
+
+#[test]
+fn count_children_matching() {
+    let options = SkimOptions { emit_comments: true, ..Default::default() };
+    let doc = parse_tree_with_options(r#"
+        <ul>
+            <li id="a"/> text <!-- comment -->
+            <li id="b" class="done"><li id="nested"/></li>
+            <p id="c"/>
+            <li id="d"/>
+        </ul>
+    "#, options).unwrap();
+    let ul = doc.select(&selector("ul"))[0];
+    let simple = |s: &str| s.parse::<Selector>().unwrap();
+
+    assert_eq!(ul.count_children_matching(&simple("li")), 3);
+    assert_eq!(ul.count_children_matching(&simple("li.done")), 1);
+    assert_eq!(ul.count_children_matching(&simple("span")), 0);
+    // nested elements are not counted
+    assert_eq!(tags(&ul.children_matching(&simple("li")).collect::<Vec<_>>()), ["a", "b", "d"]);
+    // text and comments are not counted as siblings
+    assert_eq!(tags(&ul.children_matching(&simple(":first-child")).collect::<Vec<_>>()), ["a"]);
+    assert_eq!(tags(&ul.children_matching(&simple("li:last-child")).collect::<Vec<_>>()), ["d"]);
+}
+
+#[test]
+fn pretty_xml() {
+    let src = r#"<?xml version="1.0"?>
+<library name="A &amp; B"><shelf><book id="1"><title>Dune</title><note>a <b>bold</b> word</note></book><empty></empty></shelf><pre xml:space="preserve"><line> x </line>
+<line/></pre></library>"#;
+    let doc = parse_tree(src).unwrap();
+    let pretty = doc.to_pretty_xml(2);
+    assert_eq!(pretty, r#"<library name="A &amp; B">
+  <shelf>
+    <book id="1">
+      <title>Dune</title>
+      <note>a <b>bold</b> word</note>
+    </book>
+    <empty/>
+  </shelf>
+  <pre xml:space="preserve"><line> x </line>
+<line/></pre>
+</library>"#);
+    // The indentation is ignored when parsing again
+    assert_eq!(parse_tree(&pretty).unwrap(), doc);
+    assert_eq!(parse_tree(&doc.to_xml()).unwrap(), doc);
+
+    let shelf = doc.select(&selector("shelf"))[0];
+    assert_eq!(shelf.to_pretty_xml(0), "<shelf>\n<book id=\"1\">\n<title>Dune</title>\n<note>a <b>bold</b> word</note>\n</book>\n<empty/>\n</shelf>");
+
+    let options = SkimOptions { emit_comments: true, ..Default::default() };
+    let doc = parse_tree_with_options("<!-- top --><a><!-- inside --><b/></a>", options.clone()).unwrap();
+    let pretty = doc.to_pretty_xml(4);
+    assert_eq!(pretty, "<!-- top -->\n<a>\n    <!-- inside -->\n    <b/>\n</a>");
+    assert_eq!(parse_tree_with_options(&pretty, options).unwrap(), doc);
+}
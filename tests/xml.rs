@@ -1,14 +1,1490 @@
-use std::collections::HashMap;
-use xml_skimmer::{ParsedNode, SkimError};
+use std::{borrow::Cow, collections::{HashMap, HashSet}};
+use xml_skimmer::{ancestors, Attr, closest, Element, events, events_with_options, find_all_as, find_all_attr, find_all_spans, find_all_text, find_first, find_first_map, find_nth, FromNode, FromNodeError, has_ancestor, line_col, skim_xml_close, skim_xml_one, skim_xml_with_options, tag_histogram, tag_set, Event, NodeSpan, NodeType, ParsedNode, pretty_print, PrintConfig, SkimCursor, Skimmer, SkimError, SkimOptions, SkimStats, TextMode, entities::{decode_entities, decode_entities_strict, escape_attr, escape_text}, selector::{Selector, SelectorParseError}};
 
 #[test]
 fn skim_xml() -> Result<(), SkimError> {
     let mut node_count = 0;
     
-    xml_skimmer::skim_xml(include_str!("sample.xml"), HashMap::from([
+    // The sample has a `< tag />`, which is only read in lenient mode
+    assert_eq!(
+        xml_skimmer::skim_xml(include_str!("sample.xml"), HashMap::from([("tag", |_: &ParsedNode| {})])),
+        Err(SkimError::WhitespaceBeforeTagName)
+    );
+    skim_xml_with_options(include_str!("sample.xml"), HashMap::from([
         ("tag", |node: &ParsedNode| {
             println!("Call successful for {node}");
             node_count += 1;
         })
-    ]))
-}
\ No newline at end of file
+    ]), SkimOptions { lenient: true, ..Default::default() })?;
+    assert_eq!(node_count, 14);
+    Ok(())
+}
+
+#[test]
+fn text_of() -> Result<(), SkimError> {
+    let mut texts = vec![];
+    let mut skimmer = Skimmer::new();
+    skimmer.on_text_of("p, b, br", |text, node: &ParsedNode| {
+        texts.push((node.tag.clone(), text.to_string()));
+    }).unwrap();
+
+    skimmer.skim("<div>out<p>Hello <b>bold</b> world<br/>!</p>out</div>")?;
+    drop(skimmer);
+
+    assert_eq!(texts, [
+        ("b".to_string(), "bold".to_string()),
+        ("br".to_string(), "".to_string()),
+        ("p".to_string(), "Hello bold world!".to_string()),
+    ]);
+    Ok(())
+}
+
+#[test]
+fn text_modes() -> Result<(), SkimError> {
+    fn text_with(text_mode: TextMode, xml_src: &str) -> Result<String, SkimError> {
+        let mut rtrn = String::new();
+        let mut skimmer = Skimmer::with_options(SkimOptions { text_mode, ..Default::default() });
+        skimmer.on_text_of("p", |text, _| rtrn = text.to_string()).unwrap();
+        skimmer.skim(xml_src)?;
+        drop(skimmer);
+        Ok(rtrn)
+    }
+
+    assert_eq!(text_with(TextMode::Preserve, "<p>  a   b  </p>")?, "  a   b  ");
+    assert_eq!(text_with(TextMode::Trim,     "<p>  a   b  </p>")?, "a   b");
+    assert_eq!(text_with(TextMode::Collapse, "<p>  a   b  </p>")?, "a b");
+    // Mode applies to each run of text
+    assert_eq!(text_with(TextMode::Trim,     "<p> a <b> b </b> c </p>")?, "abc");
+    // xml:space="preserve" overrides the mode
+    assert_eq!(text_with(TextMode::Collapse, "<p xml:space=\"preserve\">  a   b  </p>")?, "  a   b  ");
+    assert_eq!(text_with(TextMode::Trim,     "<div xml:space=\"preserve\"><p>  a   b  </p></div>")?, "  a   b  ");
+    assert_eq!(text_with(TextMode::Trim,     "<div xml:space=\"preserve\"><p xml:space=\"default\">  a   b  </p></div>")?, "a   b");
+    Ok(())
+}
+
+#[test]
+fn closest_ancestor() -> Result<(), SkimError> {
+    let table = "table".parse::<Selector>().unwrap();
+    let mut found = vec![];
+
+    let mut skimmer = Skimmer::new();
+    skimmer.on_with_stack("td", |stack| {
+        found.push(closest(stack, &table).map(|node| node.attributes["id"].clone()));
+    }).unwrap();
+    skimmer.skim(r#"
+        <table id="outer">
+            <tr><td/></tr>
+            <tr><td><table id="inner"><tr><td/></tr></table></td></tr>
+        </table>
+        <td/>
+    "#)?;
+    drop(skimmer);
+
+    assert_eq!(found, [
+        Some("outer".to_string()),
+        Some("outer".to_string()),
+        Some("inner".to_string()),
+        None
+    ]);
+
+    // The node itself can be the closest
+    let stack = [ParsedNode::new("table"), ParsedNode::new("table").with_class("last")];
+    assert_eq!(closest(&stack, &table), stack.last());
+    assert_eq!(closest(&stack, &"table > table".parse().unwrap()), stack.last());
+    assert_eq!(closest(&stack[..1], &".last".parse().unwrap()), None);
+    Ok(())
+}
+
+#[test]
+fn unclosed_tag() {
+    fn skim(xml_src: &str) -> Result<(), SkimError> {
+        xml_skimmer::skim_xml(xml_src, HashMap::from([("tag", |_: &ParsedNode| {})]))
+    }
+
+    // Inside the tag name
+    assert_eq!(skim("<root><di"),                 Err(SkimError::UnclosedTag("di".to_string())));
+    // Inside an attribute name
+    assert_eq!(skim("<root><div cla"),            Err(SkimError::UnclosedTag("div".to_string())));
+    // Before the closing `>`
+    assert_eq!(skim("<root><div class=\"x\" "),   Err(SkimError::UnclosedTag("div".to_string())));
+    assert_eq!(skim("<root><div class=\"x\"/"),   Err(SkimError::UnclosedTag("div".to_string())));
+    assert_eq!(skim("<root></root"),              Err(SkimError::UnclosedTag("root".to_string())));
+    assert_eq!(skim("<"),                         Err(SkimError::UnclosedTag("".to_string())));
+    // Nodes that were not closed at all are still UnclosedNode
+    assert_eq!(skim("<root><div>"),               Err(SkimError::UnclosedNode));
+    assert_eq!(skim("<root/><!--comment-->"),     Ok(()));
+}
+
+#[test]
+fn comment_events() -> Result<(), SkimError> {
+    let options = SkimOptions { emit_comments: true, ..Default::default() };
+    assert_eq!(events_with_options("a<!--c-->b", options.clone()).collect::<Result<Vec<_>, _>>()?, [
+        Event::Text("a".to_string()),
+        Event::Comment("c".to_string()),
+        Event::Text("b".to_string()),
+    ]);
+    assert_eq!(events_with_options("<r><!----><a/>x<!-- <b> --></r>", options).collect::<Result<Vec<_>, _>>()?, [
+        Event::Open(ParsedNode::new("r")),
+        Event::Comment("".to_string()),
+        Event::SelfClose(ParsedNode::new("a")),
+        Event::Text("x".to_string()),
+        Event::Comment(" <b> ".to_string()),
+        Event::Close("r".to_string()),
+    ]);
+    // Comments are skipped by default, without swallowing the text around them
+    assert_eq!(events("a<!--c-->b").collect::<Result<Vec<_>, _>>()?, [
+        Event::Text("a".to_string()),
+        Event::Text("b".to_string()),
+    ]);
+    Ok(())
+}
+
+#[test]
+fn comments_not_allowed() {
+    let all = |src: &str, options: SkimOptions| events_with_options(src, options).collect::<Result<Vec<_>, _>>();
+    let no_comments = SkimOptions { allow_comments: false, ..Default::default() };
+    let src = "<r>\n  a<!-- c -->b</r>";
+
+    assert_eq!(all(src, no_comments.clone()), Err(SkimError::CommentsNotAllowed(7)));
+    assert_eq!(line_col(src, 7), (2, 4));
+    // Even before the root, and when comments would be emitted
+    let emitting = SkimOptions { emit_comments: true, ..no_comments.clone() };
+    assert_eq!(all("<!---->", emitting), Err(SkimError::CommentsNotAllowed(0)));
+    // CDATA, declarations, and processing instructions are not comments
+    assert!(all("<!DOCTYPE r><?pi?><r><![CDATA[<!-- -->]]></r>", no_comments).is_ok());
+
+    assert!(SkimOptions::default().allow_comments);
+    assert!(all(src, SkimOptions::default()).is_ok());
+}
+
+#[test]
+fn no_root_element() {
+    let all = |src: &str, options: SkimOptions| events_with_options(src, options).collect::<Result<Vec<_>, _>>();
+    let require_root = SkimOptions { require_root_element: true, ..Default::default() };
+
+    for src in ["", "   ", "just text", "<!-- c --><?pi?>"] {
+        assert_eq!(all(src, require_root.clone()), Err(SkimError::NoRootElement), "{src:?}");
+        assert!(all(src, SkimOptions::default()).is_ok(), "{src:?}");
+    }
+    assert!(all("<r/>", require_root.clone()).is_ok());
+    assert!(all(" <r>text</r> ", require_root.clone()).is_ok());
+    // Handlers of the Skimmer are not called, but the error is returned
+    assert_eq!(
+        Skimmer::with_options(require_root).on("r", |_| unreachable!()).unwrap().skim("just text"),
+        Err(SkimError::NoRootElement)
+    );
+}
+
+#[test]
+fn required_prolog() {
+    let all = |src: &str, options: SkimOptions| events_with_options(src, options).collect::<Result<Vec<_>, _>>();
+    let require_prolog = SkimOptions { require_prolog: true, ..Default::default() };
+
+    for src in ["<?xml version='1.0'?><r/>", "<?xml version=\"1.1\" encoding=\"UTF-8\"?>\n<r/>", "\u{FEFF}<?xml version='1.0'?><r/>"] {
+        assert_eq!(all(src, require_prolog.clone()), Ok(vec![Event::SelfClose(ParsedNode::new("r"))]), "{src:?}");
+    }
+    // Not at the very start, or not the XML declaration
+    for src in ["<r/>", "", " <?xml version='1.0'?><r/>", "<!-- c --><?xml version='1.0'?><r/>", "<?xml-stylesheet href='a.css'?><r/>", "<?xml?><r/>", "<?xml encoding='UTF-8'?><r/>"] {
+        assert_eq!(all(src, require_prolog.clone()), Err(SkimError::MissingProlog), "{src:?}");
+    }
+    assert!(all("<r/>", SkimOptions::default()).is_ok());
+    assert_eq!(all("<?xml version='2.0'?><r/>", require_prolog.clone()), Err(SkimError::UnsupportedVersion("2.0".to_string())));
+    assert!(all("<?xml version='2.0'?><r/>", SkimOptions::default()).is_ok());
+}
+
+#[test]
+fn whitespace_text() -> Result<(), SkimError> {
+    let src = "<list>\n  <item>a b</item>\n  <item> </item>\n  <pre xml:space='preserve'>\n    <line/>\n  </pre>\n  <![CDATA[ ]]>\n</list>\n";
+    let texts = |options: SkimOptions| events_with_options(src, options)
+        .filter_map(|event| match event {
+            Ok(Event::Text(text)) => Some(Ok(text)),
+            Ok(_) => None,
+            Err(error) => Some(Err(error))
+        })
+        .collect::<Result<Vec<_>, _>>();
+
+    // Only the text inside xml:space="preserve", and CDATA, is kept
+    assert!(SkimOptions::default().ignore_whitespace_text);
+    assert_eq!(texts(SkimOptions::default())?, ["a b", "\n    ", "\n  ", " "]);
+    let keep = SkimOptions { ignore_whitespace_text: false, ..Default::default() };
+    assert_eq!(texts(keep.clone())?, [
+        "\n  ", "a b", "\n  ", " ", "\n  ", "\n    ", "\n  ", "\n  ", " ", "\n", "\n"
+    ]);
+    // Kept whitespace is then handled by the text mode
+    assert_eq!(texts(SkimOptions { text_mode: TextMode::Trim, ..keep })?, ["a b", "\n    ", "\n  "]);
+
+    // Whitespace between inline nodes is always part of the text that is captured
+    let mut text = String::new();
+    Skimmer::new().on_text_of("p", |t, _| text = t.to_string()).unwrap().skim("<p><b>a</b> <i>b</i></p>")?;
+    assert_eq!(text, "a b");
+    Skimmer::with_options(SkimOptions { ignore_whitespace_text: false, ..Default::default() })
+        .on_text_of("p", |t, _| text = t.to_string()).unwrap()
+        .skim("<p><b>a</b> <i>b</i></p>")?;
+    assert_eq!(text, "a b");
+    assert_eq!(find_all_text("<r>\n  <p><b>a</b> <b>b</b></p>\n</r>", "p, b")?, ["a b", "a", "b"]);
+    Ok(())
+}
+
+#[test]
+fn expanded_self_closing_events() -> Result<(), SkimError> {
+    let src = "<r><a x='1'/><b></b><c/></r>";
+    assert_eq!(events(src).collect::<Result<Vec<_>, _>>()?, [
+        Event::Open(ParsedNode::new("r")),
+        Event::SelfClose(ParsedNode::new("a").with_attr("x", "1")),
+        Event::Open(ParsedNode::new("b")),
+        Event::Close("b".to_string()),
+        Event::SelfClose(ParsedNode::new("c")),
+        Event::Close("r".to_string()),
+    ]);
+
+    let expand = SkimOptions { expand_self_closing: true, ..Default::default() };
+    assert_eq!(events_with_options(src, expand.clone()).collect::<Result<Vec<_>, _>>()?, [
+        Event::Open(ParsedNode::new("r")),
+        Event::Open(ParsedNode::new("a").with_attr("x", "1")),
+        Event::Close("a".to_string()),
+        Event::Open(ParsedNode::new("b")),
+        Event::Close("b".to_string()),
+        Event::Open(ParsedNode::new("c")),
+        Event::Close("c".to_string()),
+        Event::Close("r".to_string()),
+    ]);
+    // Also the tags that are always self-closing
+    let void = SkimOptions { self_closing_tags: HashSet::from(["br".to_string()]), ..expand };
+    assert_eq!(events_with_options("<p><br>a</p>", void).collect::<Result<Vec<_>, _>>()?, [
+        Event::Open(ParsedNode::new("p")),
+        Event::Open(ParsedNode::new("br")),
+        Event::Close("br".to_string()),
+        Event::Text("a".to_string()),
+        Event::Close("p".to_string()),
+    ]);
+    Ok(())
+}
+
+#[test]
+fn processing_instruction_events() -> Result<(), SkimError> {
+    let options = SkimOptions { emit_processing_instructions: true, ..Default::default() };
+    let src = r#"<?xml version="1.0"?><?xml-stylesheet href="a.css"  ?><r>a<?php echo 1; ?>b<?empty?></r>"#;
+    assert_eq!(events_with_options(src, options.clone()).collect::<Result<Vec<_>, _>>()?, [
+        Event::ProcessingInstruction("xml-stylesheet".to_string(), r#"href="a.css"  "#.to_string()),
+        Event::Open(ParsedNode::new("r")),
+        Event::Text("a".to_string()),
+        Event::ProcessingInstruction("php".to_string(), "echo 1; ".to_string()),
+        Event::Text("b".to_string()),
+        Event::ProcessingInstruction("empty".to_string(), "".to_string()),
+        Event::Close("r".to_string()),
+    ]);
+    assert_eq!(Event::ProcessingInstruction("php".to_string(), "".to_string()).node_type(), None);
+    assert_eq!(
+        events_with_options("<r><?pi x</r>", options).collect::<Result<Vec<_>, _>>(),
+        Err(SkimError::UnclosedComment("pi x</r>".to_string()))
+    );
+
+    // Processing instructions are skipped by default, without swallowing the text around them
+    assert_eq!(events(src).collect::<Result<Vec<_>, _>>()?, [
+        Event::Open(ParsedNode::new("r")),
+        Event::Text("a".to_string()),
+        Event::Text("b".to_string()),
+        Event::Close("r".to_string()),
+    ]);
+    Ok(())
+}
+
+#[test]
+fn text_runs() -> Result<(), SkimError> {
+    fn texts(xml_src: &str) -> Result<Vec<(String, String)>, SkimError> {
+        let mut rtrn = vec![];
+        let mut skimmer = Skimmer::new();
+        skimmer.on_text_of("r, b, p", |text, node| rtrn.push((node.tag.clone(), text.to_string()))).unwrap();
+        skimmer.skim(xml_src)?;
+        drop(skimmer);
+        Ok(rtrn)
+    }
+    fn text(tag: &str, text: &str) -> (String, String) {
+        (tag.to_string(), text.to_string())
+    }
+
+    assert_eq!(texts("<r>a<b>c</b>d</r>")?,               [text("b", "c"), text("r", "acd")]);
+    assert_eq!(texts("<r>a<!--x-->b</r>")?,               [text("r", "ab")]);
+    assert_eq!(texts("<r>a<![CDATA[y]]>z</r>")?,          [text("r", "ayz")]);
+    assert_eq!(texts("<r>a<![CDATA[<b>&amp;</b>]]>z</r>")?, [text("r", "a<b>&amp;</b>z")]);
+    assert_eq!(texts("<r>a<?pi x?>b</r>")?,               [text("r", "ab")]);
+    assert_eq!(texts("<r>1 &lt; 2<b>&amp;</b>&#x33;&#62;</r>")?, [text("b", "&"), text("r", "1 < 2&3>")]);
+    assert_eq!(texts("<r>é<p>ü</p>😀</r>")?,                [text("p", "ü"), text("r", "éü😀")]);
+    assert_eq!(texts("<r><![CDATA[x"), Err(SkimError::UnclosedCData("x".to_string())));
+    Ok(())
+}
+
+#[test]
+fn sorted_attributes() {
+    let a = ParsedNode::new("tag").with_attr("b", "2").with_attr("c", "3").with_attr("a", "1");
+    let b = ParsedNode::new("tag").with_attr("c", "3").with_attr("a", "1").with_attr("b", "2");
+    assert_eq!(a.attributes_sorted(), [("a", "1"), ("b", "2"), ("c", "3")]);
+    assert_eq!(a.attributes_sorted(), b.attributes_sorted());
+    assert_eq!(a.to_string(), r#"<tag a="1" b="2" c="3">"#);
+    assert_eq!(a.to_string(), b.to_string());
+    assert_eq!(ParsedNode::new("empty").to_string(), "<empty>");
+}
+
+#[test]
+fn attr_constructor() {
+    let attr = Attr::new("id", String::from("main"));
+    assert_eq!(attr.name, "id");
+    assert_eq!(attr.value, "main");
+    assert_eq!(attr, Attr { name: "id".to_string(), value: "main".to_string() });
+}
+
+#[test]
+fn lenient_auto_close() {
+    let src = "<div><p>one<b>two</div><span/>";
+    let lenient = SkimOptions { lenient: true, ..Default::default() };
+
+    assert_eq!(
+        events(src).collect::<Result<Vec<_>, _>>(),
+        Err(SkimError::CantCloseNode("div".to_string(), Some(Box::new(ParsedNode::new("b"))), 17))
+    );
+    assert_eq!(events_with_options(src, lenient.clone()).collect::<Result<Vec<_>, _>>(), Ok(vec![
+        Event::Open(ParsedNode::new("div")),
+        Event::Open(ParsedNode::new("p")),
+        Event::Text("one".to_string()),
+        Event::Open(ParsedNode::new("b")),
+        Event::Text("two".to_string()),
+        Event::Close("b".to_string()),
+        Event::Close("p".to_string()),
+        Event::Close("div".to_string()),
+        Event::SelfClose(ParsedNode::new("span")),
+    ]));
+
+    // Text of the implicitly closed nodes is still given to the handlers
+    let mut texts = vec![];
+    Skimmer::with_options(lenient.clone())
+        .on_text_of("p, div", |text, node| texts.push(format!("{}: {text}", node.tag))).unwrap()
+        .skim(src).unwrap();
+    assert_eq!(texts, ["p: onetwo", "div: onetwo"]);
+
+    // A closing tag that matches no open node is skipped
+    assert_eq!(
+        events_with_options("<a><b></c></b></a>", lenient).collect::<Result<Vec<_>, _>>(),
+        Ok(vec![
+            Event::Open(ParsedNode::new("a")),
+            Event::Open(ParsedNode::new("b")),
+            Event::Close("b".to_string()),
+            Event::Close("a".to_string()),
+        ])
+    );
+}
+
+#[test]
+fn lenient_stray_closing_tags() {
+    let src = "</div><div><p>text</p></div></div><span>more</span></div>";
+    let lenient = SkimOptions { lenient: true, ..Default::default() };
+
+    assert_eq!(
+        events(src).collect::<Result<Vec<_>, _>>(),
+        Err(SkimError::CantCloseNode("div".to_string(), None, 0))
+    );
+    assert_eq!(
+        events("<div></div></div>").collect::<Result<Vec<_>, _>>(),
+        Err(SkimError::CantCloseNode("div".to_string(), None, 11))
+    );
+    assert_eq!(events_with_options(src, lenient.clone()).collect::<Result<Vec<_>, _>>(), Ok(vec![
+        Event::Open(ParsedNode::new("div")),
+        Event::Open(ParsedNode::new("p")),
+        Event::Text("text".to_string()),
+        Event::Close("p".to_string()),
+        Event::Close("div".to_string()),
+        Event::Open(ParsedNode::new("span")),
+        Event::Text("more".to_string()),
+        Event::Close("span".to_string()),
+    ]));
+
+    // The skipped tags are counted
+    let mut stats = None;
+    let mut texts = vec![];
+    Skimmer::with_options(lenient)
+        .on_text_of("span", |text, _| texts.push(text.to_string())).unwrap()
+        .on_complete(|completed| stats = Some(completed))
+        .skim(src).unwrap();
+    assert_eq!(texts, ["more"]);
+    assert_eq!(stats.map(|stats| stats.stray_closing_tags), Some(3));
+}
+
+#[test]
+fn tag_counts() {
+    let counts = tag_histogram(r#"
+        <?xml version="1.0"?>
+        <doc>
+            <!-- <ignored/> -->
+            <section><p>a</p><p/><br/></section>
+            <section><p>b<br/></p></section>
+        </doc>
+    "#).unwrap();
+    assert_eq!(counts, HashMap::from([
+        ("doc".to_string(), 1),
+        ("section".to_string(), 2),
+        ("p".to_string(), 3),
+        ("br".to_string(), 2),
+    ]));
+
+    assert_eq!(tag_histogram("<a>"), Err(SkimError::UnclosedNode));
+}
+
+#[test]
+fn distinct_tags() {
+    let src = r#"
+        <?xml version="1.0"?>
+        <doc>
+            <!-- <ignored/> -->
+            <section><p>a</p><p/><br/></section>
+            <section><p>b<br/><x:br xmlns:x="urn:x"/></p></section>
+        </doc>
+    "#;
+    let tags = tag_set(src).unwrap();
+    assert_eq!(tags, HashSet::from(["doc", "section", "p", "br", "x:br"].map(String::from)));
+    // The same tags as the histogram
+    assert_eq!(tags, tag_histogram(src).unwrap().into_keys().collect());
+
+    assert_eq!(tag_set(""), Ok(HashSet::new()));
+    assert_eq!(tag_set("<a><b></a>"), Err(SkimError::CantCloseNode("a".to_string(), Some(Box::new(ParsedNode::new("b"))), 6)));
+}
+
+#[test]
+fn closing_handlers() {
+    let mut closed = vec![];
+    skim_xml_close(r#"<doc><section id="1"><section id="2"/><p/></section><section id="3"></section></doc>"#, HashMap::from([
+        ("section", |node: &ParsedNode| closed.push(node.attributes["id"].clone()))
+    ])).unwrap();
+    // inner sections close first, and self-closing ones have no closing tag
+    assert_eq!(closed, ["1", "3"]);
+
+    assert_eq!(
+        skim_xml_close("<a></b>", HashMap::from([("a", |_: &ParsedNode| {})])),
+        Err(SkimError::CantCloseNode("b".to_string(), Some(Box::new(ParsedNode::new("a"))), 3))
+    );
+}
+
+#[test]
+fn inner_markup() {
+    let xml_src = r#"<list><item id="1">one &amp; <b>two</b><!-- c --></item><item id="2"/><item id="3"><![CDATA[<x>]]></item></list>"#;
+    let mut inner = vec![];
+    let mut opened = vec![];
+    let mut skimmer = Skimmer::new();
+    skimmer
+        .on("item", |node| opened.push(node.inner_span()))
+        .unwrap()
+        .on_close("item", |node| inner.push((node.attributes["id"].clone(), node.inner_xml(xml_src).unwrap().to_string())))
+        .unwrap();
+    skimmer.skim(xml_src).unwrap();
+    drop(skimmer);
+    // Entities, comments and CDATA are kept as they are in the source
+    assert_eq!(inner, [
+        ("1".to_string(), "one &amp; <b>two</b><!-- c -->".to_string()),
+        ("2".to_string(), String::new()),
+        ("3".to_string(), "<![CDATA[<x>]]>".to_string()),
+    ]);
+    // The closing tag has not been read yet, except for the self-closing node
+    assert_eq!(opened, [None, Some(70..70), None]);
+
+    // The nodes found with find_first are closed
+    let node = find_first(xml_src, "item").unwrap().unwrap();
+    assert_eq!(node.inner_xml(xml_src), Some("one &amp; <b>two</b><!-- c -->"));
+    assert_eq!(node.span, Some(NodeSpan { start: 6, inner_start: 19, inner_end: Some(49) }));
+    assert_eq!(find_nth(xml_src, "item", 1).unwrap().unwrap().span, Some(NodeSpan { start: 56, inner_start: 70, inner_end: Some(70) }));
+
+    // Nodes closed implicitly end where the tag that closed them starts
+    let xml_src = "<a><b><c>x</a>";
+    let mut closed = vec![];
+    let mut skimmer = Skimmer::new();
+    skimmer.on_close("a, b, c", |node| closed.push(node.inner_xml(xml_src).unwrap())).unwrap();
+    assert!(skimmer.skim(xml_src).is_err());
+    drop(skimmer);
+    assert!(closed.is_empty());
+    let mut skimmer = Skimmer::with_options(SkimOptions { lenient: true, ..Default::default() });
+    skimmer.on_close("a, b, c", |node| closed.push(node.inner_xml(xml_src).unwrap())).unwrap();
+    skimmer.skim(xml_src).unwrap();
+    drop(skimmer);
+    assert_eq!(closed, ["x", "<c>x", "<b><c>x"]);
+
+    assert_eq!(ParsedNode::new("a").inner_span(), None);
+}
+
+#[test]
+fn event_node_types() {
+    let types = events("<a>text<b/><!-- c --></a>")
+        .map(|event| event.unwrap().node_type())
+        .collect::<Vec<_>>();
+    assert_eq!(types, [
+        Some(NodeType::Opening),
+        None,
+        Some(NodeType::SelfClosing),
+        Some(NodeType::Closing),
+    ]);
+}
+
+#[test]
+fn unexpected_equals() {
+    let first_error = |src: &str| events(src).find_map(Result::err);
+    assert_eq!(first_error("<=foo>"), Some(SkimError::UnexpectedEquals(String::new())));
+    assert_eq!(first_error("<a=b>"), Some(SkimError::UnexpectedEquals("a".to_string())));
+    assert_eq!(first_error("<a =b>"), Some(SkimError::UnexpectedEquals("a".to_string())));
+    assert_eq!(first_error("<a x='1' = y='2'>"), Some(SkimError::UnexpectedEquals("a".to_string())));
+    assert_eq!(first_error("<a x='1'=y>"), Some(SkimError::UnexpectedEquals("a".to_string())));
+    assert_eq!(first_error("<a></a=>"), Some(SkimError::BadEqSign));
+    assert_eq!(first_error("<a x = 'y'></a>"), None);
+}
+
+#[test]
+fn whitespace_inside_tags() {
+    let expected = ParsedNode::new("a").with_attr("x", "1").with_boolean_attr("y");
+    for xml_src in [
+        r#"<a x="1" y/>"#,
+        r#"<a   x = "1"   y />"#,
+        r#"<a x   =   "1" y   />"#,
+        "<a\r\n\tx\r\n=\r\n'1'\r\n\ty\r\n/>",
+    ] {
+        assert_eq!(events(xml_src).collect::<Vec<_>>(), [Ok(Event::SelfClose(expected.clone()))], "{xml_src:?}");
+    }
+
+    // Whitespace before the `>` does not become part of an attribute
+    let expected = [Ok(Event::Open(expected)), Ok(Event::Close("a".to_string()))];
+    assert_eq!(events(r#"<a x = "1" y ></a >"#).collect::<Vec<_>>(), expected);
+    assert_eq!(events("<a x='1'\n y\n>\n</a\n>").filter(|event| !matches!(event, Ok(Event::Text(_)))).collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn lazy_attributes() {
+    let xml_src = r#"<a title="Q&amp;A" id="&#49;" plain="x"/>"#;
+    let lazy = SkimOptions { lazy_attributes: true, ..Default::default() };
+
+    let node = match events_with_options(xml_src, lazy.clone()).next() {
+        Some(Ok(Event::SelfClose(node))) => node,
+        event => panic!("{event:?}")
+    };
+    assert_eq!(node.attributes["title"], "Q&amp;A");
+    assert_eq!(decode_entities(&node.attributes["title"]), "Q&A");
+    assert_eq!(decode_entities(&node.attributes["id"]), "1");
+    assert!(matches!(decode_entities(&node.attributes["plain"]), std::borrow::Cow::Borrowed("x")));
+
+    // The nodes of the stack read the values from the source
+    let mut events = events_with_options(r#"<a title="Q&amp;A"></a>"#, lazy.clone());
+    events.next();
+    assert_eq!(events.stack()[0].attribute_decoded("title").as_deref(), Some("Q&A"));
+    assert_eq!(events.stack()[0].attribute_decoded("missing"), None);
+
+    // Eagerly decoded values are not decoded again
+    let node = find_first(r#"<a title="&amp;lt;"/>"#, "a").unwrap().unwrap();
+    assert_eq!(node.attribute_decoded("title").as_deref(), Some("&lt;"));
+
+    // Selectors match the decoded values
+    let mut matched = 0;
+    let mut skimmer = Skimmer::with_options(lazy);
+    skimmer.on("#1[title='Q&A']", |_| matched += 1).unwrap();
+    skimmer.skim(xml_src).unwrap();
+    drop(skimmer);
+    assert_eq!(matched, 1);
+}
+
+#[test]
+fn namespaces() {
+    const ATOM: &str = "http://www.w3.org/2005/Atom";
+    const DC: &str = "http://purl.org/dc/elements/1.1/";
+    let xml_src = r#"
+        <feed xmlns="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/elements/1.1/">
+            <entry id="1"><dc:creator id="2"/></entry>
+            <a:entry id="3" xmlns:a="http://www.w3.org/2005/Atom"/>
+            <other xmlns="urn:other"><entry id="4"/></other>
+            <plain xmlns=""><entry id="5" xml:lang="en"/></plain>
+            <dc:entry id="6"/>
+        </feed>
+    "#;
+
+    let mut names = vec![];
+    let mut skimmer = Skimmer::new();
+    skimmer.on_with_stack("[id]", |stack| {
+        let node = stack.last().unwrap();
+        names.push(node.resolved_name(stack));
+    }).unwrap();
+    skimmer.skim(xml_src).unwrap();
+    drop(skimmer);
+    let name = |uri: Option<&str>, local_name: &str| (uri.map(str::to_string), local_name.to_string());
+    assert_eq!(names, [
+        name(Some(ATOM), "entry"),
+        name(Some(DC), "creator"),
+        name(Some(ATOM), "entry"),
+        name(Some("urn:other"), "entry"),
+        name(None, "entry"),
+        name(Some(DC), "entry"),
+    ]);
+
+    let plain = [ParsedNode::new("plain").with_attr("xmlns", ""), ParsedNode::new("entry").with_attr("xml:lang", "en").with_attr("id", "5")];
+    assert_eq!(plain[1].resolved_attribute_name("xml:lang", &plain), name(Some(xml_skimmer::namespace::XML_NAMESPACE), "lang"));
+    assert_eq!(plain[1].resolved_attribute_name("id", &plain), name(None, "id"));
+
+    // The selector's prefix is mapped to a URI, whatever prefix the document uses
+    let ids = |selector: &str| {
+        let mut ids = vec![];
+        let options = SkimOptions {
+            namespaces: HashMap::from([("atom".to_string(), ATOM.to_string()), ("dc".to_string(), DC.to_string())]),
+            ..Default::default()
+        };
+        let mut skimmer = Skimmer::with_options(options);
+        skimmer.on(selector, |node| ids.push(node.attributes["id"].clone())).unwrap();
+        skimmer.skim(xml_src).unwrap();
+        drop(skimmer);
+        ids
+    };
+    assert_eq!(ids("atom|entry"), ["1", "3"]);
+    assert_eq!(ids("dc|creator, dc|entry"), ["2", "6"]);
+    assert_eq!(ids("dc|[id]"), ["2", "6"]);
+    assert_eq!(ids("atom|feed > atom|entry"), ["1", "3"]);
+    assert_eq!(ids("entry"), ["1", "4", "5"]);
+    // Prefixes that are not mapped are looked up in the document
+    assert_eq!(ids("a|entry"), ["3"]);
+}
+
+#[test]
+fn typed_extraction() {
+    #[derive(Debug, PartialEq)]
+    struct Book {
+        isbn: String,
+        title: String,
+        pages: Option<u32>,
+    }
+    impl FromNode for Book {
+        fn from_node(node: &ParsedNode) -> Result<Self, FromNodeError> {
+            let attr = |name: &str| node.attributes.get(name).cloned().ok_or_else(|| FromNodeError::MissingAttribute(name.to_string()));
+            Ok(Book {
+                isbn: attr("isbn")?,
+                title: attr("title")?,
+                pages: match node.attributes.get("pages") {
+                    Some(pages) => Some(pages.parse().map_err(|_| FromNodeError::BadAttribute("pages".to_string(), pages.clone()))?),
+                    None => None
+                },
+            })
+        }
+    }
+
+    let books = find_all_as::<Book>(r#"
+        <library>
+            <book isbn="978-0" title="Dune" pages="412"/>
+            <shelf><book isbn="978-1" title="Emma &amp; Co"></book></shelf>
+        </library>
+    "#, "book").unwrap();
+    assert_eq!(books, [
+        Book { isbn: "978-0".to_string(), title: "Dune".to_string(), pages: Some(412) },
+        Book { isbn: "978-1".to_string(), title: "Emma & Co".to_string(), pages: None },
+    ]);
+
+    assert_eq!(
+        find_all_as::<Book>(r#"<book isbn="1" title="A"/><book title="B"/>"#, "book"),
+        Err(SkimError::FromNode(FromNodeError::MissingAttribute("isbn".to_string())))
+    );
+    assert_eq!(
+        find_all_as::<Book>(r#"<book isbn="1" title="A" pages="many"/>"#, "book"),
+        Err(SkimError::FromNode(FromNodeError::BadAttribute("pages".to_string(), "many".to_string())))
+    );
+    assert_eq!(find_all_as::<Book>("<shelf/>", "book"), Ok(vec![]));
+}
+
+#[test]
+fn declarations() {
+    let tags = |xml_src: &str| events(xml_src)
+        .map(|event| match event? {
+            Event::Open(node) | Event::SelfClose(node) => Ok(Some(node.tag)),
+            _ => Ok(None)
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>, SkimError>>();
+
+    assert_eq!(tags(r#"<!ENTITY x "y"><a/>"#), Ok(vec!["a".to_string()]));
+    assert_eq!(tags("<!unknown><a><!foo></a>"), Ok(vec!["a".to_string()]));
+    assert_eq!(tags("<!DOCTYPE html><html/>"), Ok(vec!["html".to_string()]));
+    // `>` inside quotes or the internal subset doesn't end the DOCTYPE
+    assert_eq!(
+        tags(r#"<?xml version="1.0"?><!DOCTYPE note [ <!ENTITY gt2 ">>"> <!ELEMENT note (#PCDATA)> ]><note/>"#),
+        Ok(vec!["note".to_string()])
+    );
+    assert_eq!(tags("<!DOCTYPE note [ <!ENTITY x 'y'> "), Err(SkimError::UnclosedComment("DOCTYPE note [ <!ENTITY x 'y'> ".to_string())));
+}
+
+#[test]
+fn mismatch_positions() {
+    let xml_src = "<doc>\n  <list>\n    <item>é</list>\n</doc>";
+    let (node, offset) = match events(xml_src).find_map(Result::err) {
+        Some(SkimError::CantCloseNode(tag, Some(node), offset)) if tag == "list" => (node, offset),
+        error => panic!("{error:?}")
+    };
+    assert_eq!(*node, ParsedNode::new("item"));
+    assert_eq!(&xml_src[offset..], "</list>\n</doc>");
+    assert_eq!(line_col(xml_src, offset), (3, 12));
+    assert_eq!(line_col(xml_src, node.start().unwrap()), (3, 5));
+    assert_eq!(&xml_src[node.start().unwrap()..offset], "<item>é");
+
+    assert_eq!(events("</a>").next(), Some(Err(SkimError::CantCloseNode("a".to_string(), None, 0))));
+    // The stack of the events also has the positions
+    let mut events = events(xml_src);
+    events.next();
+    events.next();
+    assert_eq!(events.stack().iter().map(|node| line_col(xml_src, node.start())).collect::<Vec<_>>(), [(1, 1), (2, 3)]);
+    assert_eq!(line_col("ab", 100), (1, 3));
+}
+
+#[test]
+fn custom_self_closing_tags() {
+    let options = SkimOptions { self_closing_tags: HashSet::from(["pb".to_string(), "lb".to_string()]), ..Default::default() };
+    let xml_src = r#"<text><pb n="1">line one<lb>line two<lb/><pb n="2"></text>"#;
+
+    assert_eq!(events_with_options(xml_src, options.clone()).collect::<Result<Vec<_>, _>>(), Ok(vec![
+        Event::Open(ParsedNode::new("text")),
+        Event::SelfClose(ParsedNode::new("pb").with_attr("n", "1")),
+        Event::Text("line one".to_string()),
+        Event::SelfClose(ParsedNode::new("lb")),
+        Event::Text("line two".to_string()),
+        Event::SelfClose(ParsedNode::new("lb")),
+        Event::SelfClose(ParsedNode::new("pb").with_attr("n", "2")),
+        Event::Close("text".to_string()),
+    ]));
+
+    // Handlers are called and the node is popped right away
+    let mut pages = vec![];
+    let mut skimmer = Skimmer::with_options(options.clone());
+    skimmer.on_with_stack("text > pb", |stack| pages.push((stack.len(), stack[1].attributes["n"].clone()))).unwrap();
+    skimmer.skim(xml_src).unwrap();
+    drop(skimmer);
+    assert_eq!(pages, [(2, "1".to_string()), (2, "2".to_string())]);
+
+    // Without the option they are normal nodes that are never closed
+    assert_eq!(events(xml_src).last(), Some(Err(SkimError::CantCloseNode("text".to_string(), Some(Box::new(ParsedNode::new("pb").with_attr("n", "2"))), 51))));
+    assert!(matches!(events_with_options("<pb></pb>", options).last(), Some(Err(SkimError::CantCloseNode(_, None, _)))));
+}
+
+#[test]
+fn pull_matches() {
+    let mut skimmer = Skimmer::new();
+    skimmer
+        .on("item.done", |_| {}).unwrap()
+        .on_text_of("list > item", |_, _| {}).unwrap()
+        .on("list", |_| {}).unwrap();
+
+    let xml_src = r#"<list id="a"><item id="1"/><item id="2" class="done"><list id="b"><item id="3" class="done"/></list></item></list>"#;
+    let ids = skimmer.matches(xml_src)
+        .map(|found| found.map(|(index, node)| (index, node.attributes["id"].clone())))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let pair = |index: usize, id: &str| (index, id.to_string());
+    assert_eq!(ids, [pair(2, "a"), pair(1, "1"), pair(0, "2"), pair(1, "2"), pair(2, "b"), pair(0, "3"), pair(1, "3")]);
+
+    // The handlers are not called, so the skimmer can still be used
+    assert_eq!(skimmer.matches("<list/>").count(), 1);
+
+    let mut matches = skimmer.matches("<list><item></list>");
+    assert!(matches!(matches.next(), Some(Ok((2, _)))));
+    assert!(matches!(matches.next(), Some(Ok((1, _)))));
+    assert!(matches!(matches.next(), Some(Err(SkimError::CantCloseNode(..)))));
+    assert!(matches.next().is_none());
+
+    let mut limited = Skimmer::with_options(SkimOptions { max_matches: Some(2), ..Default::default() });
+    limited.on("item", |_| {}).unwrap().on("[id]", |_| {}).unwrap();
+    // `list#a` matches the second handler first
+    assert_eq!(limited.matches(xml_src).map(|found| found.unwrap().0).collect::<Vec<_>>(), [1, 0]);
+}
+
+#[test]
+fn single_handler() {
+    let mut tags = vec![];
+    skim_xml_one("<a><b/><c><b></b></c></a>", "a b", |node| tags.push(node.tag.clone())).unwrap();
+    assert_eq!(tags, ["b", "b"]);
+
+    assert_eq!(
+        skim_xml_one("<a/>", "a >", |_| {}),
+        Err(SkimError::BadSelector(SelectorParseError::NoOtherSideCombinator))
+    );
+}
+
+#[test]
+fn empty_selectors() {
+    let empty = Err(SkimError::BadSelector(SelectorParseError::EmptyString));
+    assert_eq!(xml_skimmer::skim_xml("<a/>", HashMap::from([("", |_: &ParsedNode| {})])), empty);
+    assert_eq!(xml_skimmer::skim_xml("<a/>", HashMap::from([(" \n ", |_: &ParsedNode| {})])), empty);
+    assert_eq!(skim_xml_one("<a/>", "", |_| {}), empty);
+    assert_eq!(find_first("<a/>", "  ").err(), empty.err());
+    assert!(matches!(Skimmer::new().on("", |_| {}), Err(SelectorParseError::EmptyString)));
+    // An empty selector in a group is an error too
+    assert!(matches!(Skimmer::new().on("a, , b", |_| {}), Err(SelectorParseError::EmptyString)));
+    assert!(Skimmer::new().on("a, b,", |_| {}).is_ok());
+}
+
+#[test]
+fn scoped_selectors_outside_scope() {
+    // There is no node that the query starts from, so `:scope` would never match
+    for selector in ["> li", "+ p", ":scope li", "li:is(:scope, .a)"] {
+        assert_eq!(skim_xml_one("<ul><li/></ul>", selector, |_| {}), Err(SkimError::BadSelector(SelectorParseError::NoScope)));
+        assert_eq!(find_first("<ul><li/></ul>", selector), Err(SkimError::BadSelector(SelectorParseError::NoScope)));
+    }
+    assert!(Skimmer::new().on_text_of("ul, > li", |_, _| {}).is_err());
+}
+
+#[test]
+fn duplicate_attributes() {
+    let strict = SkimOptions { reject_duplicate_attributes: true, ..Default::default() };
+
+    // last one wins by default
+    assert_eq!(events(r#"<a x="1" x="2"/>"#).next(), Some(Ok(Event::SelfClose(ParsedNode::new("a").with_attr("x", "2")))));
+
+    assert_eq!(
+        events_with_options(r#"<a x="1" x="2"/>"#, strict.clone()).next(),
+        Some(Err(SkimError::DuplicateAttribute("x".to_string())))
+    );
+    assert_eq!(
+        events_with_options("<a x y x>", strict.clone()).next(),
+        Some(Err(SkimError::DuplicateAttribute("x".to_string())))
+    );
+    assert_eq!(
+        events_with_options(r#"<a x="1" y x='2'/>"#, strict.clone()).next(),
+        Some(Err(SkimError::DuplicateAttribute("x".to_string())))
+    );
+    assert!(events_with_options(r#"<a x="1" y="2" z/>"#, strict).all(|event| event.is_ok()));
+}
+
+#[test]
+fn lowercase_names() {
+    let options = SkimOptions { html_lowercase: true, ..Default::default() };
+    assert_eq!(events_with_options(r#"<DIV CLASS="X" Data-Id='A'>Text</Div>"#, options).collect::<Result<Vec<_>, _>>(), Ok(vec![
+        Event::Open(ParsedNode::new("div").with_attr("class", "X").with_attr("data-id", "A")),
+        Event::Text("Text".to_string()),
+        Event::Close("div".to_string()),
+    ]));
+
+    // case matters by default
+    assert!(matches!(events("<DIV></div>").last(), Some(Err(SkimError::CantCloseNode(_, _, _)))));
+}
+
+#[test]
+fn content_after_self_close() {
+    let first = |src: &str, options: SkimOptions| events_with_options(src, options).next();
+    let lenient = SkimOptions { lenient: true, ..Default::default() };
+
+    for options in [SkimOptions::default(), lenient.clone()] {
+        assert_eq!(first("<a/>", options.clone()), Some(Ok(Event::SelfClose(ParsedNode::new("a")))));
+        assert_eq!(first("<a/ \n>", options.clone()), Some(Ok(Event::SelfClose(ParsedNode::new("a")))));
+        assert_eq!(first("<a x='1'/>", options), Some(Ok(Event::SelfClose(ParsedNode::new("a").with_attr("x", "1")))));
+    }
+
+    assert_eq!(first("<a/ b>", SkimOptions::default()), Some(Err(SkimError::ContentAfterSelfClose("a".to_string()))));
+    assert_eq!(first("<a/ b>", lenient.clone()), Some(Ok(Event::SelfClose(ParsedNode::new("a")))));
+    assert_eq!(first("<a/ b", lenient.clone()), Some(Err(SkimError::UnclosedTag("a".to_string()))));
+    assert_eq!(first("<a/ b<c>", lenient), Some(Err(SkimError::UnclosedTag("a".to_string()))));
+}
+
+#[test]
+fn ancestors_in_handler() {
+    let mut parents = vec![];
+    Skimmer::new()
+        .on_with_stack("li", |stack| {
+            let parent = ancestors(stack).next().unwrap();
+            parents.push(parent.attributes["id"].clone());
+            // every ancestor of a li is a list here
+            assert!(ancestors(stack).all(|node| node.tag == "ul"));
+        }).unwrap()
+        .skim(r#"<ul id="outer"><li/><ul id="inner"><li/></ul></ul>"#).unwrap();
+    assert_eq!(parents, ["outer", "inner"]);
+
+    assert_eq!(ancestors(&[]).count(), 0);
+    assert_eq!(ancestors(&[ParsedNode::new("root")]).count(), 0);
+}
+
+#[test]
+fn inside_tag() {
+    let mut found = vec![];
+    let table = "table".parse::<Selector>().unwrap();
+    Skimmer::new()
+        .on_with_stack("td", |stack| {
+            let id = stack.last().unwrap().attributes["id"].clone();
+            found.push((id, has_ancestor(stack, "table"), has_ancestor(stack, "td"), closest(&stack[..stack.len() - 1], &table).is_some()));
+        }).unwrap()
+        .skim(r#"
+            <doc>
+                <td id="loose"/>
+                <table><tr><td id="cell"><td id="nested"/></td></tr></table>
+            </doc>
+        "#).unwrap();
+    let row = |id: &str, in_table: bool, in_td: bool| (id.to_string(), in_table, in_td, in_table);
+    assert_eq!(found, [row("loose", false, false), row("cell", true, false), row("nested", true, true)]);
+
+    // The node itself is not its own ancestor
+    assert!(!has_ancestor(&[ParsedNode::new("table")], "table"));
+    assert!(!has_ancestor(&[], "table"));
+}
+
+#[test]
+fn whitespace_before_tag_name() {
+    let all = |src: &str, options: SkimOptions| events_with_options(src, options).collect::<Result<Vec<_>, _>>();
+    let lenient = SkimOptions { lenient: true, ..Default::default() };
+
+    assert_eq!(all("< a></a>", SkimOptions::default()), Err(SkimError::WhitespaceBeforeTagName));
+    assert_eq!(all("<a></ a>", SkimOptions::default()), Err(SkimError::WhitespaceBeforeTagName));
+    assert_eq!(all("<\na/>", SkimOptions::default()), Err(SkimError::WhitespaceBeforeTagName));
+    // whitespace after the tag name is fine
+    assert_eq!(all("<a ></a >", SkimOptions::default()), Ok(vec![
+        Event::Open(ParsedNode::new("a")),
+        Event::Close("a".to_string()),
+    ]));
+
+    assert_eq!(all("< a x='1'></ a>", lenient), Ok(vec![
+        Event::Open(ParsedNode::new("a").with_attr("x", "1")),
+        Event::Close("a".to_string()),
+    ]));
+}
+
+#[test]
+fn attribute_names() {
+    let first = |src: &str, options: SkimOptions| events_with_options(src, options).next().unwrap();
+    let lenient = SkimOptions { lenient: true, ..Default::default() };
+    let invalid = |name: &str| Err(SkimError::InvalidAttributeName(name.to_string()));
+
+    // Checked at the `=`, at whitespace, and at the `>`
+    assert_eq!(first(r#"<a 1x="y">"#, SkimOptions::default()), invalid("1x"));
+    assert_eq!(first(r#"<a 1x=y>"#, SkimOptions::default()), invalid("1x"));
+    assert_eq!(first("<a 1x b>", SkimOptions::default()), invalid("1x"));
+    assert_eq!(first("<a b 1x>", SkimOptions::default()), invalid("1x"));
+    assert_eq!(first("<a 1x/>", SkimOptions::default()), invalid("1x"));
+    assert_eq!(first(r#"<a b$="y">"#, SkimOptions::default()), invalid("b$"));
+    assert_eq!(first(r#"<a @click="f">"#, SkimOptions::default()), invalid("@click"));
+    assert_eq!(first(r#"<a -b="y">"#, SkimOptions::default()), invalid("-b"));
+
+    // Legal, even if unusual
+    let legal = ParsedNode::new("a")
+        .with_attr("_b", "1")
+        .with_attr("x:y", "2")
+        .with_attr("c-d.e9", "3")
+        .with_attr("ünï", "4");
+    assert_eq!(first(r#"<a _b="1" x:y="2" c-d.e9="3" ünï="4">"#, SkimOptions::default()), Ok(Event::Open(legal)));
+
+    assert_eq!(
+        first(r#"<a 1x="y" b$="z" @click>"#, lenient),
+        Ok(Event::Open(ParsedNode::new("a").with_attr("1x", "y").with_attr("b$", "z").with_boolean_attr("@click")))
+    );
+}
+
+#[test]
+fn slashes_in_attributes() {
+    let all = |src: &str, options: SkimOptions| events_with_options(src, options).collect::<Result<Vec<_>, _>>();
+    let lenient = SkimOptions { lenient: true, ..Default::default() };
+
+    // A slash that starts a value that is not quoted doesn't make the node self-closing, even when lenient
+    for options in [SkimOptions::default(), lenient.clone()] {
+        assert_eq!(all("<a path=/foo/bar>x</a>", options.clone()), Err(SkimError::UnquotedAttrValue("path".to_string())));
+        assert_eq!(all("<a path = /foo/>", options.clone()), Err(SkimError::UnquotedAttrValue("path".to_string())));
+        // Quoted values can have any slashes
+        assert_eq!(all("<a path='/foo/bar/' end=\"/\"/>", options), Ok(vec![
+            Event::SelfClose(ParsedNode::new("a").with_attr("path", "/foo/bar/").with_attr("end", "/")),
+        ]));
+    }
+
+    // Inside an attribute name, the slash is part of the name
+    assert_eq!(all(r#"<a x/y="1"/>"#, SkimOptions::default()), Err(SkimError::InvalidAttributeName("x/y".to_string())));
+    assert_eq!(all(r#"<a x/y="1" z/w/>"#, lenient.clone()), Ok(vec![
+        Event::SelfClose(ParsedNode::new("a").with_attr("x/y", "1").with_boolean_attr("z/w")),
+    ]));
+    // After an attribute name, the slash closes the node
+    for src in ["<a x/>", "<a x />", "<a x/ >"] {
+        assert_eq!(all(src, SkimOptions::default()), Ok(vec![Event::SelfClose(ParsedNode::new("a").with_boolean_attr("x"))]), "{src:?}");
+    }
+    assert_eq!(all("<a x /y>", SkimOptions::default()), Err(SkimError::ContentAfterSelfClose("a".to_string())));
+}
+
+#[test]
+fn first_match() {
+    let src = r#"<doc><p class="x">a</p><p class="y" id="2">b</p></doc><unclosed>"#;
+
+    assert_eq!(find_first(src, "p.y"), Ok(Some(ParsedNode::new("p").with_class("y").with_attr("id", "2"))));
+    assert_eq!(find_first_map(src, "p.y", |node| node.attribute("id").unwrap().to_string()), Ok(Some("2".to_string())));
+    assert_eq!(find_first_map(src, "doc > p", |node| node.class_list().len()), Ok(Some(1)));
+    // Parsing stops at the match, so the unclosed node after it is not an error
+    assert!(find_first(src, "doc").is_ok());
+    // But it is if there was no match before it
+    assert_eq!(find_first(src, "span"), Err(SkimError::UnclosedNode));
+    assert_eq!(find_first("<a/>", "span"), Ok(None));
+    assert_eq!(find_first("<a/>", "a >"), Err(SkimError::BadSelector(SelectorParseError::NoOtherSideCombinator)));
+}
+
+#[test]
+fn nth_match() {
+    let src = r#"<ul><li id="0"/><li id="1"><li id="2"/></li><li id="3"/></ul><unclosed>"#;
+    let li = |id: &str| Ok(Some(ParsedNode::new("li").with_attr("id", id)));
+
+    // In the order the nodes appear, nested or not
+    assert_eq!(find_nth(src, "li", 0), li("0"));
+    assert_eq!(find_nth(src, "li", 2), li("2"));
+    assert_eq!(find_nth(src, "li", 3), li("3"));
+    assert_eq!(find_nth(src, "ul > li", 2), li("3"));
+    assert_eq!(find_nth(src, "li", 0), find_first(src, "li"));
+    // Out of range, so the whole source is read
+    assert_eq!(find_nth(src, "li", 4), Err(SkimError::UnclosedNode));
+    assert_eq!(find_nth("<ul><li/></ul>", "li", 1), Ok(None));
+    assert_eq!(find_nth("<ul><li/></ul>", "li", usize::MAX), Ok(None));
+}
+
+#[test]
+fn all_text() {
+    // Siblings
+    assert_eq!(
+        find_all_text("<ul><li>\n  one </li><li>t<b>w</b>o</li><li/><li><![CDATA[<3]]>&amp;</li></ul>", "li"),
+        Ok(vec!["one".to_string(), "two".to_string(), String::new(), "<3&".to_string()])
+    );
+    // Nested: the outer text includes the inner one, and the outer node comes first
+    assert_eq!(
+        find_all_text("<r><div>a<div>b<div>c</div></div>d</div><div>e</div></r>", "div"),
+        Ok(vec!["abcd".to_string(), "bc".to_string(), "c".to_string(), "e".to_string()])
+    );
+    assert_eq!(
+        find_all_text("<r><p>x<i>y</i></p><i>z</i></r>", "p, i"),
+        Ok(vec!["xy".to_string(), "y".to_string(), "z".to_string()])
+    );
+    assert_eq!(find_all_text("<r>text</r>", "p"), Ok(vec![]));
+    assert!(matches!(find_all_text("<r><p>a</r>", "p"), Err(SkimError::CantCloseNode(..))));
+    assert_eq!(find_all_text("<r/>", ""), Err(SkimError::BadSelector(SelectorParseError::EmptyString)));
+}
+
+#[test]
+fn all_attributes() {
+    let src = r#"<nav>
+        <a href="/">Home</a>
+        <a name="top">Top</a>
+        <a href="/search?q=a&amp;page=2">Search</a>
+        <section><a href="">Empty</a><link href="/style.css"/></section>
+        <a HREF="/upper"/>
+    </nav>"#;
+    assert_eq!(
+        find_all_attr(src, "a", "href"),
+        Ok(vec!["/".to_string(), "/search?q=a&page=2".to_string(), String::new()])
+    );
+    assert_eq!(find_all_attr(src, "section [href]", "href"), Ok(vec![String::new(), "/style.css".to_string()]));
+    assert_eq!(find_all_attr(src, "a", "title"), Ok(vec![]));
+    assert_eq!(find_all_attr(src, "img", "src"), Ok(vec![]));
+    assert!(matches!(find_all_attr("<a href='/'>", "a", "href"), Err(SkimError::UnclosedNode)));
+    assert_eq!(find_all_attr(src, "", "href"), Err(SkimError::BadSelector(SelectorParseError::EmptyString)));
+}
+
+#[test]
+fn all_spans() {
+    let src = r#"<doc>
+    <item id="1">one</item>
+    <group><item id="2"><item id="3" /></item></group>
+    <!-- <item id="c"/> -->
+    <item id="4" title="a > b">four</item >
+</doc>"#;
+    let spans = find_all_spans(src, "item").unwrap();
+    let substrings = spans.iter().map(|span| &src[span.clone()]).collect::<Vec<_>>();
+    assert_eq!(substrings, [
+        r#"<item id="1">one</item>"#,
+        r#"<item id="2"><item id="3" /></item>"#,
+        r#"<item id="3" />"#,
+        r#"<item id="4" title="a > b">four</item >"#,
+    ]);
+    assert_eq!(spans[0].start, src.find("<item").unwrap());
+
+    assert_eq!(find_all_spans(src, "doc").unwrap()[0], 0..src.len());
+    assert_eq!(find_all_spans(src, "group > item > item"), Ok(vec![spans[2].clone()]));
+    assert_eq!(find_all_spans(src, "other"), Ok(vec![]));
+    assert!(matches!(find_all_spans("<a><b></a>", "b"), Err(SkimError::CantCloseNode(..))));
+}
+
+#[test]
+fn handler_order() {
+    let src = "<ul><li/><li/></ul>";
+    let order = std::cell::RefCell::new(vec![]);
+    let handler = |name: &'static str| { let order = &order; move |_: &ParsedNode| order.borrow_mut().push(name) };
+
+    let mut skimmer = Skimmer::new();
+    skimmer
+        .on("ul > li", handler("child")).unwrap()
+        .on("ul li", handler("descendant")).unwrap()
+        .on("li", handler("li")).unwrap();
+    skimmer.skim(src).unwrap();
+    assert_eq!(order.take(), ["child", "descendant", "li", "child", "descendant", "li"]);
+
+    // Same with skim_xml, in the order of the array
+    xml_skimmer::skim_xml(src, [("li", handler("li")), ("ul > li", handler("child"))]).unwrap();
+    xml_skimmer::skim_xml(src, vec![("ul > li", handler("child")), ("li", handler("li"))]).unwrap();
+    assert_eq!(order.take(), ["li", "child", "li", "child", "child", "li", "child", "li"]);
+}
+
+#[test]
+fn max_matches() {
+    let src = "<ul><li>1</li><li>2</li><li>3</li><li>4</li></ul><unclosed>";
+    let options = SkimOptions { max_matches: Some(2), ..Default::default() };
+
+    let mut found = vec![];
+    xml_skimmer::skim_xml_with_options(src, HashMap::from([
+        ("li", |node: &ParsedNode| found.push(node.tag.clone()))
+    ]), options.clone()).unwrap();
+    assert_eq!(found, ["li", "li"]);
+
+    // Counted across all handlers
+    let mut found = vec![];
+    Skimmer::with_options(SkimOptions { max_matches: Some(3), ..Default::default() })
+        .on_text_of("li, ul", |text, node| found.push(format!("{}: {text}", node.tag))).unwrap()
+        .skim(src).unwrap();
+    // ul matched first, then 2 li; the ul still gets its text, so parsing goes on until it closes
+    assert_eq!(found, ["li: 1", "li: 2", "ul: 1234"].map(String::from));
+
+    // Without a limit the unclosed node is found
+    assert_eq!(
+        xml_skimmer::skim_xml(src, HashMap::from([("li", |_: &ParsedNode| {})])),
+        Err(SkimError::UnclosedNode)
+    );
+}
+
+#[test]
+fn complete_hook() {
+    let mut completed = vec![];
+    let mut titles = 0;
+    let mut skimmer = Skimmer::new();
+    skimmer
+        .on("title", |_| titles += 1).unwrap()
+        .on("book, title", |_| {}).unwrap()
+        .on_complete(|stats| completed.push(stats));
+    skimmer.skim("<lib><book><title/></book><book><title/><p><b/></p></book></lib>").unwrap();
+    // Not called when skimming fails
+    assert_eq!(skimmer.skim("<lib><book></lib>"), Err(SkimError::CantCloseNode("lib".to_string(), Some(Box::new(ParsedNode::new("book"))), 11)));
+    assert_eq!(skimmer.skim("<lib>"), Err(SkimError::UnclosedNode));
+    drop(skimmer);
+
+    assert_eq!(titles, 2);
+    assert_eq!(completed, [SkimStats { nodes: 7, matches: 6, max_depth: 4, stray_closing_tags: 0 }]);
+
+    // Skimming that stopped at max_matches completed too
+    let mut completed = vec![];
+    let src = "<ul><li/><li/><li/></ul><unclosed>";
+    Skimmer::with_options(SkimOptions { max_matches: Some(2), ..Default::default() })
+        .on("li", |_| {}).unwrap()
+        .on_complete(|stats| completed.push(stats))
+        .skim(src).unwrap();
+    assert_eq!(completed, [SkimStats { nodes: 3, matches: 2, max_depth: 2, stray_closing_tags: 0 }]);
+}
+
+#[test]
+fn strict_entities() {
+    let strict = SkimOptions { strict_entities: true, ..Default::default() };
+    let text = |src: &str, options: SkimOptions| events_with_options(src, options)
+        .find_map(|event| match event {
+            Ok(Event::Text(text)) => Some(Ok(text)),
+            Err(error) => Some(Err(error)),
+            _ => None
+        });
+
+    assert_eq!(text("<a>&amp; &#60;</a>", strict.clone()), Some(Ok("& <".to_string())));
+    assert_eq!(text("<a>x &foo; y</a>", strict.clone()), Some(Err(SkimError::UnknownEntity("&foo;".to_string()))));
+    assert_eq!(text("<a>Tom & Jerry</a>", strict.clone()), Some(Err(SkimError::UnknownEntity("&".to_string()))));
+    assert_eq!(text("<a>&#xZZ;</a>", strict.clone()), Some(Err(SkimError::UnknownEntity("&#xZZ;".to_string()))));
+    assert_eq!(
+        events_with_options("<a x='&foo;'/>", strict.clone()).next(),
+        Some(Err(SkimError::UnknownEntity("&foo;".to_string())))
+    );
+    // CDATA is not decoded
+    assert_eq!(text("<a><![CDATA[&foo;]]></a>", strict), Some(Ok("&foo;".to_string())));
+
+    // left as-is by default
+    assert_eq!(text("<a>&foo; & &amp;</a>", SkimOptions::default()), Some(Ok("&foo; & &".to_string())));
+}
+
+#[test]
+fn bare_ampersands() {
+    let cases = [
+        ("Tom & Jerry", "Tom & Jerry", Err(SkimError::UnknownEntity("&".to_string()))),
+        ("a &amp; b", "a & b", Ok("a & b".to_string())),
+        ("x & &amp;", "x & &", Err(SkimError::UnknownEntity("&".to_string()))),
+        ("&", "&", Err(SkimError::UnknownEntity("&".to_string()))),
+        ("a &amp &lt;", "a &amp <", Err(SkimError::UnknownEntity("&".to_string()))),
+    ];
+    let strict = SkimOptions { strict_entities: true, ..Default::default() };
+
+    for (text, lenient_text, strict_text) in cases {
+        assert_eq!(decode_entities(text), lenient_text);
+        assert_eq!(decode_entities_strict(text).map(Cow::into_owned), strict_text);
+
+        // Text captured from a node
+        let src = format!("<p>{text}</p>");
+        let mut captured = None;
+        Skimmer::new().on_text_of("p", |text, _| captured = Some(text.to_string())).unwrap().skim(&src).unwrap();
+        assert_eq!(captured.as_deref(), Some(lenient_text));
+
+        let mut captured = None;
+        let result = Skimmer::with_options(strict.clone())
+            .on_text_of("p", |text, _| captured = Some(text.to_string())).unwrap()
+            .skim(&src);
+        assert_eq!(result.map(|()| captured.unwrap()), strict_text, "{text}");
+        assert_eq!(find_all_text(&src, "p"), Ok(vec![lenient_text.to_string()]));
+    }
+}
+
+#[test]
+fn escape_round_trip() {
+    // Nothing to escape
+    assert!(matches!(escape_text("plain text"), Cow::Borrowed("plain text")));
+    assert!(matches!(escape_attr("plain value"), Cow::Borrowed("plain value")));
+
+    for s in ["a < b", "Tom & Jerry", "x > 1 && y < 2", r#"say "hi""#, "it's", "é 😀 &amp;", ""] {
+        assert_eq!(decode_entities(&escape_text(s)), s);
+        assert_eq!(decode_entities(&escape_attr(s)), s);
+    }
+    assert_eq!(escape_text(r#"<a href="x">'"#), r#"&lt;a href="x"&gt;'"#);
+    assert_eq!(escape_attr(r#"<a href="x">'"#), "&lt;a href=&quot;x&quot;&gt;&apos;");
+
+    // Escaping text that was decoded from well-formed XML is stable
+    for src in ["1 &lt; 2 &amp;&amp; 3 &gt; 2", "plain", "&amp;amp;"] {
+        let escaped = escape_text(&decode_entities(src)).into_owned();
+        assert_eq!(escaped, src);
+        assert_eq!(escape_text(&decode_entities(&escaped)), escaped);
+    }
+
+    // Escaped values can be parsed back
+    let value = r#"a "quoted" & 'single' <value>"#;
+    let src = format!("<a x='{}' y=\"{}\">{}</a>", escape_attr(value), escape_attr(value), escape_text(value));
+    let mut events = events(&src);
+    assert_eq!(events.next(), Some(Ok(Event::Open(ParsedNode::new("a").with_attr("x", value).with_attr("y", value)))));
+    assert_eq!(events.next(), Some(Ok(Event::Text(value.to_string()))));
+}
+
+#[test]
+fn markup_in_attribute_values() {
+    // The value is read until the quote that opened it, so the other quote, `<`, and `>` are part of the value
+    let src = r#"<a onclick="f('<b>')" title='say "</a>"'>text</a>"#;
+    assert_eq!(events(src).collect::<Result<Vec<_>, _>>(), Ok(vec![
+        Event::Open(ParsedNode::new("a").with_attr("onclick", "f('<b>')").with_attr("title", r#"say "</a>""#)),
+        Event::Text("text".to_string()),
+        Event::Close("a".to_string()),
+    ]));
+    assert_eq!(tag_histogram(src), Ok(HashMap::from([("a".to_string(), 1)])));
+    assert_eq!(find_first(src, "b"), Ok(None));
+    assert_eq!(
+        find_all_spans(r#"<r><a v="<b/>"/><b/></r>"#, "b").unwrap()[0].start,
+        16
+    );
+
+    // The value is not closed when the source ends, even after markup
+    assert_eq!(
+        events(r#"<a onclick="f('<b>')"#).last(),
+        Some(Err(SkimError::UnclosedString("onclick".to_string(), Box::new(ParsedNode::new("a")))))
+    );
+    assert_eq!(
+        events(r#"<r><a x='1' y="<b></b>"#).last(),
+        Some(Err(SkimError::UnclosedString("y".to_string(), Box::new(ParsedNode::new("a").with_attr("x", "1")))))
+    );
+    assert_eq!(events("<a x=\"").last(), Some(Err(SkimError::UnclosedString("x".to_string(), Box::new(ParsedNode::new("a"))))));
+
+    // The value absorbed markup until an unrelated quote, which is found before the error that it causes later
+    let suspicious = SkimOptions { reject_markup_in_attributes: true, ..Default::default() };
+    let src = r#"<r><a title="x><b id="y"/></r>"#;
+    assert_eq!(events(src).find_map(Result::err), Some(SkimError::BadQuote));
+    assert_eq!(
+        events_with_options(src, suspicious.clone()).find_map(Result::err),
+        Some(SkimError::SuspiciousAttrValue("title".to_string(), "x><b id=".to_string()))
+    );
+    assert_eq!(
+        events_with_options(r#"<a onclick="f('<b>')"/>"#, suspicious.clone()).find_map(Result::err),
+        Some(SkimError::SuspiciousAttrValue("onclick".to_string(), "f('<b>')".to_string()))
+    );
+    // Escaped markup and the other quote are fine
+    assert_eq!(
+        events_with_options(r#"<a v="&lt;b&gt;" w="it's"/>"#, suspicious).collect::<Result<Vec<_>, _>>(),
+        Ok(vec![Event::SelfClose(ParsedNode::new("a").with_attr("v", "<b>").with_attr("w", "it's"))])
+    );
+}
+
+#[test]
+fn cdata_end_in_text() {
+    let strict = SkimOptions { reject_cdata_end_in_text: true, ..Default::default() };
+    let src = "<a>x ]]> y</a>";
+
+    // Accepted as text by default
+    assert_eq!(find_all_text(src, "a"), Ok(vec!["x ]]> y".to_string()]));
+    assert_eq!(xml_skimmer::skim_xml(src, [("a", |_: &ParsedNode| {})]), Ok(()));
+
+    assert_eq!(
+        skim_xml_with_options(src, [("a", |_: &ParsedNode| {})], strict.clone()),
+        Err(SkimError::IllegalCharSequence("x ]]> y".to_string()))
+    );
+    assert_eq!(
+        events_with_options("<a>ok</a>]]>", strict.clone()).find_map(Result::err),
+        Some(SkimError::IllegalCharSequence("]]>".to_string()))
+    );
+    // Escaped, split by markup, or in CDATA is fine
+    assert_eq!(
+        events_with_options("<a>]]&gt; ]]<!---->> <![CDATA[]]]]><![CDATA[>]]></a>", strict).collect::<Result<Vec<_>, _>>(),
+        Ok(vec![
+            Event::Open(ParsedNode::new("a")),
+            Event::Text("]]> ]]".to_string()),
+            Event::Text("> ".to_string()),
+            Event::Text("]]".to_string()),
+            Event::Text(">".to_string()),
+            Event::Close("a".to_string()),
+        ])
+    );
+}
+
+#[test]
+fn text_outside_root() {
+    let strict = SkimOptions { reject_text_outside_root: true, ..Default::default() };
+    let skim = |src: &str| skim_xml_with_options(src, [("root", |_: &ParsedNode| {})], strict.clone());
+
+    assert_eq!(skim("text<root/>"), Err(SkimError::TextOutsideRoot("text".to_string())));
+    assert_eq!(skim("<root/>junk"), Err(SkimError::TextOutsideRoot("junk".to_string())));
+    assert_eq!(skim("<root/>\n  x\n"), Err(SkimError::TextOutsideRoot("\n  x\n".to_string())));
+    assert_eq!(skim("<root/><![CDATA[data]]>"), Err(SkimError::TextOutsideRoot("data".to_string())));
+    // Whitespace, comments, and processing instructions are fine
+    assert_eq!(skim("<root/>\n"), Ok(()));
+    assert_eq!(skim("<?xml version=\"1.0\"?>\n<!-- c -->\n<?pi data?>\n<root>text</root>\n<!-- c -->\n"), Ok(()));
+    assert_eq!(
+        events_with_options("<root/>\n", SkimOptions { ignore_whitespace_text: false, ..strict.clone() }).collect::<Result<Vec<_>, _>>(),
+        Ok(vec![Event::SelfClose(ParsedNode::new("root")), Event::Text("\n".to_string())])
+    );
+
+    // Accepted by default
+    assert_eq!(xml_skimmer::skim_xml("text<root/>junk", [("root", |_: &ParsedNode| {})]), Ok(()));
+}
+
+#[test]
+fn retained_attributes() -> Result<(), SkimError> {
+    let options = SkimOptions { retain_attributes: Some(HashSet::from(["id".to_string()])), ..Default::default() };
+    let src = r#"<list><item id="1" class="a" data-x="y"/><item id="2" class="b"/><item class="a"/></list>"#;
+
+    // Selectors match the attributes that are not retained
+    let mut found = vec![];
+    Skimmer::with_options(options.clone()).on("item.a", |node| found.push(node.clone())).unwrap().skim(src)?;
+    assert_eq!(found, [ParsedNode::new("item").with_attr("id", "1"), ParsedNode::new("item")]);
+
+    let mut stacks = vec![];
+    Skimmer::with_options(options.clone()).on_with_stack("[data-x]", |stack| stacks.push(stack.to_vec())).unwrap().skim(src)?;
+    assert_eq!(stacks, [[ParsedNode::new("list"), ParsedNode::new("item").with_attr("id", "1")]]);
+
+    assert_eq!(events_with_options(src, options.clone()).nth(1), Some(Ok(Event::SelfClose(ParsedNode::new("item").with_attr("id", "1")))));
+    assert_eq!(
+        events_with_options("<a id='1' b='2", options.clone()).last(),
+        Some(Err(SkimError::UnclosedString("b".to_string(), Box::new(ParsedNode::new("a").with_attr("id", "1")))))
+    );
+    // Retained names are compared after lowercasing
+    let lowercase = SkimOptions { html_lowercase: true, ..options };
+    assert_eq!(events_with_options("<A ID='1' X='2'/>", lowercase).next(), Some(Ok(Event::SelfClose(ParsedNode::new("a").with_attr("id", "1")))));
+    // Nothing is retained with an empty set
+    let nothing = SkimOptions { retain_attributes: Some(HashSet::new()), ..Default::default() };
+    assert_eq!(events_with_options(src, nothing).nth(1), Some(Ok(Event::SelfClose(ParsedNode::new("item")))));
+    Ok(())
+}
+
+#[test]
+fn multibyte_content() {
+    let options = SkimOptions { emit_comments: true, ..Default::default() };
+    let src = "<?xml 名前=\"🦀\"?><a title='😀 &amp; 日本' 名前=\"🦀\"><!-- コメント 🎉 --><b/>テキスト🦀<![CDATA[ü<>]]></a>";
+
+    assert_eq!(events_with_options(src, options).collect::<Result<Vec<_>, _>>(), Ok(vec![
+        Event::Open(ParsedNode::new("a").with_attr("title", "😀 & 日本").with_attr("名前", "🦀")),
+        Event::Comment(" コメント 🎉 ".to_string()),
+        Event::SelfClose(ParsedNode::new("b")),
+        Event::Text("テキスト🦀".to_string()),
+        Event::Text("ü<>".to_string()),
+        Event::Close("a".to_string()),
+    ]));
+
+    // Errors in the middle of multibyte content
+    assert_eq!(events("<a x='🦀").last(), Some(Err(SkimError::UnclosedString("x".to_string(), Box::new(ParsedNode::new("a"))))));
+    assert_eq!(events("<a><!-- 🦀 -").last(), Some(Err(SkimError::UnclosedComment(" 🦀 -".to_string()))));
+    assert_eq!(events("<🦀/ 🦀>").last(), Some(Err(SkimError::ContentAfterSelfClose("🦀".to_string()))));
+    let strict = SkimOptions { strict_entities: true, ..Default::default() };
+    assert_eq!(
+        events_with_options("<a>&🦀;</a>", strict).find_map(Result::err),
+        Some(SkimError::UnknownEntity("&🦀;".to_string()))
+    );
+}
+
+#[test]
+fn cursor() {
+    let src = r#"
+        <list>
+            <item id="1"/>
+            <group><item id="2">text<item id="3"/></item></group>
+            <other/>
+            <item id="4"/>
+        </list>
+    "#;
+    let mut cursor = SkimCursor::new(src, "item").unwrap();
+    let mut ids = vec![];
+    while let Some(node) = cursor.next_match().unwrap() {
+        // The stack is where the matched node is
+        assert_eq!(cursor.stack().last().unwrap().tag, "item");
+        ids.push(node.attributes["id"].clone());
+        if ids.len() == 3 {
+            assert_eq!(cursor.stack().len(), 4);
+        }
+    }
+    assert_eq!(ids, ["1", "2", "3", "4"]);
+    // The cursor stays at the end
+    assert_eq!(cursor.next_match(), Ok(None));
+
+    // Same nodes as find_nth
+    let mut cursor = SkimCursor::new(src, "group > item, other").unwrap();
+    assert_eq!(cursor.next_match().unwrap(), find_nth(src, "group > item, other", 0).unwrap());
+    assert_eq!(cursor.next_match().unwrap().unwrap().tag, "other");
+    assert_eq!(cursor.next_match(), Ok(None));
+
+    // Errors after a match are only found when advancing to them
+    let mut cursor = SkimCursor::new("<a><b/></c>", "b").unwrap();
+    assert_eq!(cursor.next_match(), Ok(Some(ParsedNode::new("b"))));
+    assert!(matches!(cursor.next_match(), Err(SkimError::CantCloseNode(..))));
+    assert_eq!(cursor.next_match(), Ok(None));
+
+    let options = SkimOptions { max_matches: Some(2), ..Default::default() };
+    let mut cursor = SkimCursor::with_options(src, "item", options).unwrap();
+    assert!(cursor.next_match().unwrap().is_some());
+    assert!(cursor.next_match().unwrap().is_some());
+    assert_eq!(cursor.next_match(), Ok(None));
+
+    assert_eq!(SkimCursor::new(src, ":scope > item").err(), Some(SelectorParseError::NoScope));
+}
+
+#[test]
+fn pretty_printed_tree() {
+    let src = r#"<?xml version="1.0"?>
+<library>
+    <!-- comment -->
+    <book id="1" lang="en"><title>  Dune
+        (novel) </title><author/></book>
+    <book id="2"/>
+</library>"#;
+    let expected = r#"<library>
+    <book id="1" lang="en">
+        <title>
+            Dune (novel)
+        </title>
+        <author/>
+    </book>
+    <book id="2"/>
+</library>
+"#;
+    let config = PrintConfig { indent: 4, ..Default::default() };
+    assert_eq!(pretty_print(src, &config), Ok(expected.to_string()));
+
+    // Only the indentation changes
+    let indents = |indent: usize| pretty_print(src, &PrintConfig { indent, ..Default::default() }).unwrap().lines()
+        .map(|line| line.len() - line.trim_start().len())
+        .collect::<Vec<_>>();
+    assert_eq!(indents(0), [0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(indents(1), [0, 1, 2, 3, 2, 2, 1, 1, 0]);
+    assert_eq!(indents(2), [0, 2, 4, 6, 4, 4, 2, 2, 0]);
+    assert_eq!(indents(4), [0, 4, 8, 12, 8, 8, 4, 4, 0]);
+    assert_eq!(PrintConfig::default().indent, 2);
+    assert_eq!(pretty_print(src, &PrintConfig::default()), pretty_print(src, &PrintConfig { indent: 2, colors: false }));
+    assert_eq!(pretty_print("<a><b/></a>", &PrintConfig { indent: 0, colors: false }), Ok("<a>\n<b/>\n</a>\n".to_string()));
+
+    // The colors are only escape codes around the names
+    let colored = pretty_print(src, &PrintConfig { colors: true, ..config.clone() }).unwrap();
+    assert!(colored.contains("<\x1b[92mbook\x1b[0m \x1b[36mid\x1b[0m=\"1\""));
+    assert!(colored.contains("</\x1b[91mlibrary\x1b[0m>"));
+    let mut uncolored = colored.clone();
+    for code in ["\x1b[92m", "\x1b[91m", "\x1b[36m", "\x1b[0m"] {
+        uncolored = uncolored.replace(code, "");
+    }
+    assert_eq!(uncolored, expected);
+
+    assert_eq!(pretty_print("<a><b></a>", &config), Err(SkimError::CantCloseNode("a".to_string(), Some(Box::new(ParsedNode::new("b"))), 6)));
+}